@@ -11,9 +11,77 @@ dfx deps pull
 dfx deps init --argument '(null)' internet-identity
 dfx deps deploy
 
+# Deploy stand-in ckUSDC and ckBTC ledgers: plain ICRC-1 ledgers that mint to the local identity.
+# IC_VERSION is a dfinity/ic commit whose ledger canister build to use.
+echo "Deploying stand-in settlement ledgers..."
+if [ -z "$IC_VERSION" ]; then
+  echo "Set IC_VERSION to a dfinity/ic release commit to download the ICRC-1 ledger" >&2
+  exit 1
+fi
+mkdir -p .dfx-deps/icrc1_ledger
+curl -sSL -o .dfx-deps/icrc1_ledger/ic-icrc1-ledger.wasm.gz \
+  "https://download.dfinity.systems/ic/$IC_VERSION/canisters/ic-icrc1-ledger.wasm.gz"
+curl -sSL -o .dfx-deps/icrc1_ledger/ledger.did \
+  "https://raw.githubusercontent.com/dfinity/ic/$IC_VERSION/rs/ledger_suite/icrc1/ledger/ledger.did"
+
+DEPLOYER=$(dfx identity get-principal)
+
+# deploy_ledger <canister> <symbol> <decimals> <fee> <initial balance>
+deploy_ledger() {
+  dfx deploy "$1" --argument "(variant { Init = record {
+    token_symbol = \"$2\";
+    token_name = \"$2 (local)\";
+    decimals = opt $3;
+    minting_account = record { owner = principal \"2vxsx-fae\" };
+    transfer_fee = $4;
+    metadata = vec {};
+    initial_balances = vec { record { record { owner = principal \"$DEPLOYER\" }; $5 } };
+    archive_options = record {
+      num_blocks_to_archive = 1000;
+      trigger_threshold = 2000;
+      controller_id = principal \"$DEPLOYER\";
+    };
+    feature_flags = opt record { icrc2 = true };
+  }})"
+}
+
+deploy_ledger ckusdc_ledger ckUSDC 6 10_000 100_000_000_000
+deploy_ledger ckbtc_ledger ckBTC 8 10 100_000_000
+CKUSDC_LEDGER_ID=$(dfx canister id ckusdc_ledger)
+CKBTC_LEDGER_ID=$(dfx canister id ckbtc_ledger)
+
 # Deploy Backend Canister
 echo "Deploying Backend Canister..."
-dfx deploy backend
+dfx deploy backend --argument "(opt record {
+  initial_super_admins = vec {};
+  settlement = opt record {
+    cash_asset = \"ckUSDC\";
+    assets = vec {
+      record {
+        symbol = \"ckUSDC\";
+        ledger_canister_id = principal \"$CKUSDC_LEDGER_ID\";
+        decimals = 6 : nat8;
+        pricing = variant { Pegged = record { cents_per_unit = 100 : nat64 } };
+        minter_canister_id = null;
+      };
+      record {
+        symbol = \"ckBTC\";
+        ledger_canister_id = principal \"$CKBTC_LEDGER_ID\";
+        decimals = 8 : nat8;
+        pricing = variant { Oracle };
+        minter_canister_id = null;
+      };
+    };
+  };
+  oracle = opt record {
+    sources = vec { variant { Fixed = record { name = \"local\"; price_cents = 6_000_000 : nat64 } } };
+    priced_assets = vec { \"ckBTC\" };
+    min_sources = 1 : nat32;
+    max_deviation_bps = 100 : nat64;
+    max_age_seconds = 900 : nat64;
+    refresh_interval_seconds = 300 : nat64;
+  };
+})"
  sleep 15
 
 # Run Cargo tests to generate Candid file
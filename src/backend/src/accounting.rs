@@ -0,0 +1,727 @@
+use std::collections::BTreeMap;
+
+use candid::Principal;
+
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::storage::*;
+use crate::types::*;
+
+use LedgerAccount::*;
+
+// Descriptions of the entries the fee revenue report reads
+const PURCHASE: &str = "Purchase";
+const SALE: &str = "Sale";
+const ORDER_FILL: &str = "Order fill";
+const FEE_WITHDRAWAL: &str = "Platform fee withdrawal";
+const FEE_WITHDRAWAL_REFUND: &str = "Platform fee withdrawal refund";
+
+impl LedgerAccount {
+    /// Stable key of the account's running totals
+    pub fn key(&self) -> String {
+        match self {
+            UserCash(owner) => format!("UserCash/{}", owner),
+            UserHoldings(owner) => format!("UserHoldings/{}", owner),
+            OrderEscrow => "OrderEscrow".to_string(),
+            FeeRevenue => "FeeRevenue".to_string(),
+            BillInventory => "BillInventory".to_string(),
+            BrokerCustody => "BrokerCustody".to_string(),
+            YieldAdvances => "YieldAdvances".to_string(),
+            UserAsset(owner, asset) => format!("UserAsset/{}/{}", owner, asset),
+            AssetCustody(asset) => format!("AssetCustody/{}", asset),
+            AssetFeeRevenue(asset) => format!("AssetFeeRevenue/{}", asset),
+            AssetPayments(asset) => format!("AssetPayments/{}", asset),
+        }
+    }
+
+    /// Asset whose ledger units the account is kept in; cash accounts are kept in cents
+    pub fn asset(&self) -> Option<&str> {
+        match self {
+            UserAsset(_, asset)
+            | AssetCustody(asset)
+            | AssetFeeRevenue(asset)
+            | AssetPayments(asset) => Some(asset),
+            _ => None,
+        }
+    }
+}
+
+/// Account holding the fees collected in `asset`
+pub fn fee_account(asset: &str) -> LedgerAccount {
+    if crate::settlement::is_cash(asset) {
+        FeeRevenue
+    } else {
+        AssetFeeRevenue(asset.to_string())
+    }
+}
+
+/// Account holding the owner's balance of `asset`
+fn wallet_account(owner: Principal, asset: &str) -> LedgerAccount {
+    if crate::settlement::is_cash(asset) {
+        UserCash(owner)
+    } else {
+        UserAsset(owner, asset.to_string())
+    }
+}
+
+/// Account holding `asset` in custody
+fn custody_account(asset: &str) -> LedgerAccount {
+    if crate::settlement::is_cash(asset) {
+        BrokerCustody
+    } else {
+        AssetCustody(asset.to_string())
+    }
+}
+
+fn debit(account: LedgerAccount, amount: u64) -> JournalLine {
+    JournalLine {
+        account,
+        side: EntrySide::Debit,
+        amount,
+    }
+}
+
+fn credit(account: LedgerAccount, amount: u64) -> JournalLine {
+    JournalLine {
+        account,
+        side: EntrySide::Credit,
+        amount,
+    }
+}
+
+/// Posts an entry after dropping zero lines; an entry whose debits and credits differ in
+/// any unit is refused and nothing is written
+pub fn post(
+    description: impl Into<String>,
+    reference: Option<&str>,
+    lines: Vec<JournalLine>,
+    now: u64,
+) -> Result<()> {
+    let lines: Vec<JournalLine> = lines.into_iter().filter(|line| line.amount > 0).collect();
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let side_total = |unit: Option<&str>, side: EntrySide| -> u128 {
+        lines
+            .iter()
+            .filter(|line| line.account.asset() == unit && line.side == side)
+            .map(|line| line.amount as u128)
+            .sum()
+    };
+    let description = description.into();
+    let unbalanced = lines.iter().any(|line| {
+        let unit = line.account.asset();
+        side_total(unit, EntrySide::Debit) != side_total(unit, EntrySide::Credit)
+    });
+    if unbalanced {
+        return Err(BitcoinUSTBillsError::InternalError(format!(
+            "Unbalanced journal entry: {}",
+            description
+        )));
+    }
+
+    JournalStorage::append(JournalEntry {
+        id: 0,
+        timestamp: now,
+        description,
+        reference: reference.map(str::to_string),
+        lines,
+    })?;
+    Ok(())
+}
+
+/// The account's totals, zero if nothing was ever posted to it
+pub fn balance(account: &LedgerAccount) -> AccountBalance {
+    JournalStorage::balance(account).unwrap_or(AccountBalance {
+        account: account.clone(),
+        debits: 0,
+        credits: 0,
+        balance: 0,
+    })
+}
+
+/// Every account's totals. Each unit balances on its own, so the balances always net to
+/// zero.
+pub fn trial_balance() -> TrialBalance {
+    let accounts = JournalStorage::get_balances();
+    TrialBalance {
+        total_debits: accounts.iter().map(|account| account.debits).sum(),
+        total_credits: accounts.iter().map(|account| account.credits).sum(),
+        net: accounts.iter().map(|account| account.balance).sum(),
+        accounts,
+    }
+}
+
+/// Brings balances that predate the journal onto the books, once, against custody and
+/// inventory
+pub fn post_opening_balances(now: u64) -> Result<()> {
+    if JournalStorage::count() > 0 {
+        return Ok(());
+    }
+
+    let mut lines = Vec::new();
+    for user in UserStorage::get_all() {
+        let cash = crate::settlement::cash_balance(&user);
+        lines.push(debit(BrokerCustody, cash));
+        lines.push(credit(UserCash(user.principal), cash));
+        for balance in &user.balances {
+            if !crate::settlement::is_cash(&balance.asset) {
+                lines.push(debit(AssetCustody(balance.asset.clone()), balance.amount));
+                lines.push(credit(
+                    UserAsset(user.principal, balance.asset.clone()),
+                    balance.amount,
+                ));
+            }
+        }
+        lines.push(debit(BillInventory, user.total_invested));
+        lines.push(credit(UserHoldings(user.principal), user.total_invested));
+    }
+    let escrow: u64 = OrderStorage::get_open()
+        .iter()
+        .map(|order| order.escrowed_amount)
+        .sum();
+    lines.push(debit(BrokerCustody, escrow));
+    lines.push(credit(OrderEscrow, escrow));
+
+    post("Opening balances", None, lines, now)
+}
+
+// Postings for each flow. `reference` names the transaction, order or withdrawal, and
+// amounts of a settlement asset are in cents for the cash asset and ledger units otherwise.
+
+/// A deposit of `asset` swept into its custody
+pub fn deposit(
+    owner: Principal,
+    asset: &str,
+    amount: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    post(
+        "Deposit",
+        Some(reference),
+        vec![
+            debit(custody_account(asset), amount),
+            credit(wallet_account(owner, asset), amount),
+        ],
+        now,
+    )
+}
+
+/// An amount of `asset` locked by a withdrawal request leaves the wallet and custody
+pub fn withdrawal(
+    owner: Principal,
+    asset: &str,
+    amount: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    post(
+        "Withdrawal",
+        Some(reference),
+        vec![
+            debit(wallet_account(owner, asset), amount),
+            credit(custody_account(asset), amount),
+        ],
+        now,
+    )
+}
+
+/// A withdrawal that paid nothing out returns its amount to the wallet
+pub fn withdrawal_refund(
+    owner: Principal,
+    asset: &str,
+    amount: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    post(
+        "Withdrawal refund",
+        Some(reference),
+        vec![
+            debit(custody_account(asset), amount),
+            credit(wallet_account(owner, asset), amount),
+        ],
+        now,
+    )
+}
+
+/// A primary purchase. Paid in cash, the buyer's cash pays for the bills and the fee, and
+/// custody buys the bills into inventory. Paid with `units` of another asset, the fee's
+/// share of the buyer's balance is fee revenue in that asset, and the rest pays for the
+/// bills, held in its custody until it is converted.
+pub fn purchase(
+    owner: Principal,
+    cost: u64,
+    fees: u64,
+    paid_with: Option<(&str, u64)>,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    let lines = match paid_with {
+        None => vec![
+            debit(UserCash(owner), cost + fees),
+            credit(UserHoldings(owner), cost),
+            credit(FeeRevenue, fees),
+            debit(BillInventory, cost),
+            credit(BrokerCustody, cost),
+        ],
+        Some((asset, units)) => {
+            let fee_units = crate::pro_rata(units, fees, cost + fees)?;
+            vec![
+                debit(UserAsset(owner, asset.to_string()), units),
+                credit(AssetFeeRevenue(asset.to_string()), fee_units),
+                credit(AssetPayments(asset.to_string()), units - fee_units),
+                debit(BillInventory, cost),
+                credit(UserHoldings(owner), cost),
+            ]
+        }
+    };
+    post(PURCHASE, Some(reference), lines, now)
+}
+
+/// A sale back to the platform: the bills leave inventory at cost for their proceeds,
+/// which reach the wallet less the fee
+pub fn sale(
+    owner: Principal,
+    cost_basis: u64,
+    proceeds: u64,
+    fees: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    post(
+        SALE,
+        Some(reference),
+        vec![
+            debit(UserHoldings(owner), cost_basis),
+            credit(BillInventory, cost_basis),
+            debit(BrokerCustody, proceeds),
+            credit(UserCash(owner), proceeds - fees),
+            credit(FeeRevenue, fees),
+        ],
+        now,
+    )
+}
+
+/// Cash locked in a bid
+pub fn escrow(owner: Principal, cents: u64, reference: &str, now: u64) -> Result<()> {
+    post(
+        "Bid escrow",
+        Some(reference),
+        vec![debit(UserCash(owner), cents), credit(OrderEscrow, cents)],
+        now,
+    )
+}
+
+/// Escrow returned to the bidder
+pub fn escrow_release(owner: Principal, cents: u64, reference: &str, now: u64) -> Result<()> {
+    post(
+        "Bid escrow release",
+        Some(reference),
+        vec![debit(OrderEscrow, cents), credit(UserCash(owner), cents)],
+        now,
+    )
+}
+
+/// An order book fill between two users. The bid's escrow pays the seller, less the fee,
+/// and refunds any price improvement; the bills move from the seller's cost to the
+/// buyer's, and inventory follows.
+#[allow(clippy::too_many_arguments)]
+pub fn fill(
+    buyer: Principal,
+    seller: Principal,
+    escrow_used: u64,
+    value: u64,
+    fees: u64,
+    cost_basis: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    let revaluation = if value >= cost_basis {
+        debit(BillInventory, value - cost_basis)
+    } else {
+        credit(BillInventory, cost_basis - value)
+    };
+    post(
+        ORDER_FILL,
+        Some(reference),
+        vec![
+            debit(OrderEscrow, escrow_used),
+            credit(UserCash(buyer), escrow_used - value),
+            credit(UserCash(seller), value - fees),
+            credit(FeeRevenue, fees),
+            debit(UserHoldings(seller), cost_basis),
+            credit(UserHoldings(buyer), value),
+            revaluation,
+        ],
+        now,
+    )
+}
+
+/// Flexible yield paid ahead of maturity
+pub fn yield_payout(owner: Principal, cents: u64, reference: &str, now: u64) -> Result<()> {
+    post(
+        "Yield payout",
+        Some(reference),
+        vec![debit(YieldAdvances, cents), credit(UserCash(owner), cents)],
+        now,
+    )
+}
+
+/// A holding redeemed at maturity: the bills leave inventory at cost, custody receives
+/// face value and pays it out less the yield already advanced
+pub fn redemption(
+    owner: Principal,
+    cost_basis: u64,
+    face_value: u64,
+    advanced_yield: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    post(
+        "Maturity redemption",
+        Some(reference),
+        vec![
+            debit(UserHoldings(owner), cost_basis),
+            credit(BillInventory, cost_basis),
+            debit(BrokerCustody, face_value),
+            credit(UserCash(owner), face_value - advanced_yield),
+            credit(YieldAdvances, advanced_yield),
+        ],
+        now,
+    )
+}
+
+/// Bill tokens transferred between users at the sender's cost
+pub fn holdings_transfer(
+    from: Principal,
+    to: Principal,
+    cost_basis: u64,
+    reference: &str,
+    now: u64,
+) -> Result<()> {
+    post(
+        "Token transfer",
+        Some(reference),
+        vec![
+            debit(UserHoldings(from), cost_basis),
+            credit(UserHoldings(to), cost_basis),
+        ],
+        now,
+    )
+}
+
+/// Fee revenue collected in `asset` paid out of that asset's custody to the platform fee
+/// account
+pub fn fee_withdrawal(asset: &str, amount: u64, reference: &str, now: u64) -> Result<()> {
+    post(
+        FEE_WITHDRAWAL,
+        Some(reference),
+        vec![
+            debit(fee_account(asset), amount),
+            credit(custody_account(asset), amount),
+        ],
+        now,
+    )
+}
+
+/// A fee withdrawal that paid nothing out returns to the fee account
+pub fn fee_withdrawal_refund(asset: &str, amount: u64, reference: &str, now: u64) -> Result<()> {
+    post(
+        FEE_WITHDRAWAL_REFUND,
+        Some(reference),
+        vec![
+            debit(custody_account(asset), amount),
+            credit(fee_account(asset), amount),
+        ],
+        now,
+    )
+}
+
+/// Fee revenue collected in `asset` and not yet withdrawn, in its fee account's unit
+pub fn available_fees(asset: &str) -> u64 {
+    u64::try_from(-balance(&fee_account(asset)).balance).unwrap_or(0)
+}
+
+/// Fees collected in `asset` between `from` and `to` inclusive, by bill and fee type.
+/// Each fee entry's reference is the purchase or sale transaction, which names the bill.
+pub fn fee_revenue_report(asset: &str, from: u64, to: u64) -> FeeRevenueReport {
+    let account = fee_account(asset);
+    let mut lines: BTreeMap<(String, FeeType), FeeRevenueLine> = BTreeMap::new();
+    let mut total_earned = 0;
+    let mut total_withdrawn = 0;
+
+    for entry in JournalStorage::get_between(from, to) {
+        let fee_revenue = |side: EntrySide| -> u64 {
+            entry
+                .lines
+                .iter()
+                .filter(|line| line.account == account && line.side == side)
+                .map(|line| line.amount)
+                .sum()
+        };
+
+        let fee_type = match entry.description.as_str() {
+            PURCHASE => FeeType::Purchase,
+            SALE => FeeType::Sale,
+            ORDER_FILL => FeeType::OrderBook,
+            FEE_WITHDRAWAL => {
+                total_withdrawn += fee_revenue(EntrySide::Debit);
+                continue;
+            }
+            FEE_WITHDRAWAL_REFUND => {
+                total_withdrawn = total_withdrawn.saturating_sub(fee_revenue(EntrySide::Credit));
+                continue;
+            }
+            _ => continue,
+        };
+        let amount = fee_revenue(EntrySide::Credit);
+        if amount == 0 {
+            continue;
+        }
+
+        let ustbill_id = entry
+            .reference
+            .as_deref()
+            .and_then(|reference| TransactionStorage::get(reference).ok())
+            .and_then(|transaction| transaction.ustbill_id)
+            .unwrap_or_default();
+        let line = lines
+            .entry((ustbill_id.clone(), fee_type.clone()))
+            .or_insert(FeeRevenueLine {
+                ustbill_id,
+                fee_type,
+                trades: 0,
+                amount: 0,
+            });
+        line.trades += 1;
+        line.amount += amount;
+        total_earned += amount;
+    }
+
+    FeeRevenueReport {
+        asset: asset.to_string(),
+        from,
+        to,
+        lines: lines.into_values().collect(),
+        total_earned,
+        total_withdrawn,
+        available: available_fees(asset),
+    }
+}
+
+/// Checks the journal against the balances kept on users and orders: every wallet in
+/// every settlement asset, holdings at cost and bid escrow. Fails naming each account whose
+/// journal balance has drifted from the stored one.
+pub fn reconcile() -> Result<()> {
+    let assets = SettlementConfigStorage::get().assets;
+    let mut drift = Vec::new();
+    let mut check = |account: LedgerAccount, stored: u64| {
+        let booked = -balance(&account).balance;
+        if booked != stored as i64 {
+            drift.push(format!(
+                "{} is {} but {} is stored",
+                account.key(),
+                booked,
+                stored
+            ));
+        }
+    };
+
+    for user in UserStorage::get_all() {
+        for asset in &assets {
+            let stored = if crate::settlement::is_cash(&asset.symbol) {
+                crate::settlement::cash_balance(&user)
+            } else {
+                user.balance(&asset.symbol)
+            };
+            check(wallet_account(user.principal, &asset.symbol), stored);
+        }
+        check(UserHoldings(user.principal), user.total_invested);
+    }
+    let escrow = OrderStorage::get_open()
+        .iter()
+        .map(|order| order.escrowed_amount)
+        .sum();
+    check(OrderEscrow, escrow);
+
+    if drift.is_empty() {
+        Ok(())
+    } else {
+        Err(BitcoinUSTBillsError::JournalDrift(drift.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn principal(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
+    }
+
+    /// Credit balance of a liability account
+    fn owed(account: LedgerAccount) -> i64 {
+        -balance(&account).balance
+    }
+
+    #[test]
+    fn test_trial_balance_nets_to_zero() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let bob = principal("rrkah-fqaaa-aaaaa-aaaaq-cai");
+
+        deposit(alice, "ckUSDC", 100_000, "d1", NOW).unwrap();
+        deposit(bob, "ckUSDC", 50_000, "d2", NOW).unwrap();
+        purchase(alice, 95_000, 475, None, "p1", NOW).unwrap();
+        escrow(bob, 48_000, "o1", NOW).unwrap();
+        // Bob's bid fills 50 tokens at $9.60 against Alice's lot bought at $9.50
+        fill(bob, alice, 48_000, 48_000, 240, 47_500, "f1", NOW).unwrap();
+        yield_payout(bob, 100, "y1", NOW).unwrap();
+        redemption(bob, 48_000, 50_000, 100, "r1", NOW).unwrap();
+        sale(alice, 47_500, 47_800, 239, "s1", NOW).unwrap();
+        withdrawal(alice, "ckUSDC", 10_000, "w1", NOW).unwrap();
+
+        let trial_balance = trial_balance();
+        assert_eq!(trial_balance.net, 0);
+        assert_eq!(trial_balance.total_debits, trial_balance.total_credits);
+
+        // Balances derived from the journal match the wallets
+        assert_eq!(
+            owed(UserCash(alice)),
+            100_000 - 95_475 + 47_760 + 47_561 - 10_000
+        );
+        assert_eq!(owed(UserCash(bob)), 50_000 - 48_000 + 100 + 49_900);
+        assert_eq!(owed(UserHoldings(alice)), 0);
+        assert_eq!(owed(UserHoldings(bob)), 0);
+        assert_eq!(owed(OrderEscrow), 0);
+        assert_eq!(owed(FeeRevenue), 475 + 240 + 239);
+        assert_eq!(balance(&BillInventory).balance, 0);
+        assert_eq!(balance(&YieldAdvances).balance, 0);
+    }
+
+    #[test]
+    fn test_opening_balances_are_posted_once() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let mut user = User {
+            principal: alice,
+            email: "alice@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![],
+            total_invested: 95_000,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        };
+        crate::settlement::credit_cash(&mut user, 5_000);
+        user.credit("ckBTC", 70_000);
+        UserStorage::insert(user).unwrap();
+
+        post_opening_balances(NOW).unwrap();
+        post_opening_balances(NOW).unwrap();
+        assert_eq!(JournalStorage::count(), 1);
+        assert_eq!(owed(UserCash(alice)), 5_000);
+        assert_eq!(owed(UserHoldings(alice)), 95_000);
+        assert_eq!(balance(&BrokerCustody).balance, 5_000);
+        assert_eq!(balance(&BillInventory).balance, 95_000);
+        assert_eq!(owed(UserAsset(alice, "ckBTC".to_string())), 70_000);
+        assert_eq!(balance(&AssetCustody("ckBTC".to_string())).balance, 70_000);
+        assert_eq!(trial_balance().net, 0);
+        reconcile().unwrap();
+    }
+
+    #[test]
+    fn test_fee_revenue_report_by_bill_and_type() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let bob = principal("rrkah-fqaaa-aaaaa-aaaaq-cai");
+        let record = |id: &str, ustbill_id: &str| {
+            TransactionStorage::insert(Transaction {
+                id: id.to_string(),
+                user_principal: alice,
+                transaction_type: TransactionType::Purchase,
+                amount: 0,
+                ustbill_id: Some(ustbill_id.to_string()),
+                holding_id: None,
+                timestamp: NOW,
+                status: TransactionStatus::Completed,
+                fees: 0,
+                description: String::new(),
+            })
+            .unwrap();
+        };
+
+        record("p1", "bill-a");
+        purchase(alice, 95_000, 475, None, "p1", NOW).unwrap();
+        record("p2", "bill-a");
+        purchase(alice, 19_000, 95, None, "p2", NOW + 10).unwrap();
+        // Bob pays $95.48 in ckBTC at $60,000, so the fee is collected in ckBTC
+        record("p3", "bill-b");
+        deposit(bob, "ckBTC", 200_000, "d1", NOW + 20).unwrap();
+        purchase(bob, 9_500, 48, Some(("ckBTC", 159_134)), "p3", NOW + 20).unwrap();
+        record("f1", "bill-a");
+        escrow(bob, 48_000, "o1", NOW + 30).unwrap();
+        fill(bob, alice, 48_000, 48_000, 240, 47_500, "f1", NOW + 30).unwrap();
+        record("s1", "bill-a");
+        sale(alice, 19_000, 19_100, 96, "s1", NOW + 40).unwrap();
+        fee_withdrawal("ckUSDC", 500, "fw1", NOW + 50).unwrap();
+
+        let report = fee_revenue_report("ckUSDC", NOW, NOW + 50);
+        let line = |ustbill_id: &str, fee_type: FeeType| {
+            report
+                .lines
+                .iter()
+                .find(|line| line.ustbill_id == ustbill_id && line.fee_type == fee_type)
+                .map(|line| (line.trades, line.amount))
+        };
+        assert_eq!(report.lines.len(), 3);
+        assert_eq!(line("bill-a", FeeType::Purchase), Some((2, 570)));
+        assert_eq!(line("bill-a", FeeType::OrderBook), Some((1, 240)));
+        assert_eq!(line("bill-a", FeeType::Sale), Some((1, 96)));
+        assert_eq!(report.total_earned, 475 + 95 + 240 + 96);
+        assert_eq!(report.total_withdrawn, 500);
+        assert_eq!(report.available, report.total_earned - 500);
+
+        // Bob's fee is its share of the ckBTC paid, in ledger units
+        let report = fee_revenue_report("ckBTC", NOW, NOW + 50);
+        assert_eq!(report.lines.len(), 1);
+        assert_eq!(report.lines[0].ustbill_id, "bill-b");
+        assert_eq!((report.lines[0].trades, report.lines[0].amount), (1, 800));
+        assert_eq!(report.available, 800);
+        assert_eq!(owed(AssetPayments("ckBTC".to_string())), 159_134 - 800);
+        assert_eq!(owed(UserAsset(bob, "ckBTC".to_string())), 200_000 - 159_134);
+        assert_eq!(balance(&AssetCustody("ckBTC".to_string())).balance, 200_000);
+        assert_eq!(trial_balance().net, 0);
+
+        // Only entries inside the window count
+        let report = fee_revenue_report("ckUSDC", NOW + 5, NOW + 25);
+        assert_eq!(report.total_earned, 95);
+        assert_eq!(report.total_withdrawn, 0);
+    }
+
+    #[test]
+    fn test_unbalanced_entry_is_refused() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let lines = vec![debit(BrokerCustody, 100), credit(UserCash(alice), 99)];
+        assert!(post("Broken", None, lines, NOW).is_err());
+
+        // Cents and ledger units don't offset each other
+        let ckbtc = || "ckBTC".to_string();
+        let lines = vec![
+            debit(AssetCustody(ckbtc()), 100),
+            credit(AssetFeeRevenue(ckbtc()), 40),
+            credit(UserCash(alice), 60),
+        ];
+        assert!(post("Mixed", None, lines, NOW).is_err());
+        assert_eq!(JournalStorage::count(), 0);
+        assert_eq!(balance(&BrokerCustody).debits, 0);
+
+        // Zero lines are dropped rather than posted
+        post("Empty", None, vec![debit(BrokerCustody, 0)], NOW).unwrap();
+        assert_eq!(JournalStorage::count(), 0);
+    }
+}
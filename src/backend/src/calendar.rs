@@ -0,0 +1,184 @@
+use time::{Date, Month, OffsetDateTime, Weekday};
+
+pub const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Business days between a trade and its settlement (T+1)
+pub const SETTLEMENT_DAYS: u64 = 1;
+
+/// Day-count conventions: actual days elapsed over a 360- or 365-day year
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DayCount {
+    Actual360,
+    Actual365,
+}
+
+impl DayCount {
+    pub fn basis_days(self) -> u64 {
+        match self {
+            DayCount::Actual360 => 360,
+            DayCount::Actual365 => 365,
+        }
+    }
+
+    /// Actual whole days from `start` to `end`, zero if `end` is not later
+    pub fn days(start: u64, end: u64) -> u64 {
+        end.saturating_sub(start) / SECONDS_PER_DAY
+    }
+}
+
+/// UTC calendar date of a Unix timestamp
+pub fn date_of(timestamp: u64) -> Date {
+    OffsetDateTime::from_unix_timestamp(timestamp as i64)
+        .map(|datetime| datetime.date())
+        .unwrap_or(Date::MAX)
+}
+
+/// Unix timestamp of midnight UTC on `date`
+pub fn start_of(date: Date) -> u64 {
+    date.midnight().assume_utc().unix_timestamp().max(0) as u64
+}
+
+/// Holidays observed by the Federal Reserve, when Treasury securities do not settle
+/// or pay. A holiday falling on a Sunday is observed the Monday after; one falling on
+/// a Saturday is not moved, as the Reserve Banks stay open the Friday before.
+pub fn federal_holidays(year: i32) -> Vec<Date> {
+    let fixed = |month: Month, day: u8| {
+        let date = Date::from_calendar_date(year, month, day).ok()?;
+        match date.weekday() {
+            Weekday::Saturday => None,
+            Weekday::Sunday => date.next_day(),
+            _ => Some(date),
+        }
+    };
+    let nth = |month: Month, weekday: Weekday, n: u8| nth_weekday(year, month, weekday, n);
+
+    [
+        fixed(Month::January, 1),
+        nth(Month::January, Weekday::Monday, 3), // Birthday of Martin Luther King, Jr.
+        nth(Month::February, Weekday::Monday, 3), // Washington's Birthday
+        last_weekday(year, Month::May, Weekday::Monday), // Memorial Day
+        (year >= 2022).then(|| fixed(Month::June, 19)).flatten(), // Juneteenth
+        fixed(Month::July, 4),
+        nth(Month::September, Weekday::Monday, 1), // Labor Day
+        nth(Month::October, Weekday::Monday, 2),   // Columbus Day
+        fixed(Month::November, 11),                // Veterans Day
+        nth(Month::November, Weekday::Thursday, 4), // Thanksgiving Day
+        fixed(Month::December, 25),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+pub fn is_holiday(date: Date) -> bool {
+    federal_holidays(date.year()).contains(&date)
+}
+
+pub fn is_business_day(date: Date) -> bool {
+    !matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday) && !is_holiday(date)
+}
+
+/// `date` itself if it is a business day, else the next one
+pub fn roll_forward(mut date: Date) -> Date {
+    while !is_business_day(date) {
+        match date.next_day() {
+            Some(next) => date = next,
+            None => break,
+        }
+    }
+    date
+}
+
+/// The business day `days` business days after `date`
+pub fn add_business_days(mut date: Date, days: u64) -> Date {
+    for _ in 0..days {
+        date = roll_forward(date.next_day().unwrap_or(date));
+    }
+    date
+}
+
+/// When a bill maturing at `maturity_date` pays: on the day, or the next business day
+/// when it falls on a weekend or holiday, at the same time of day
+pub fn payment_date(maturity_date: u64) -> u64 {
+    let date = date_of(maturity_date);
+    let rolled_days = (roll_forward(date) - date).whole_days().max(0) as u64;
+    maturity_date + rolled_days * SECONDS_PER_DAY
+}
+
+/// Start of the day a trade made at `trade_time` settles (T+1)
+pub fn settlement_date(trade_time: u64) -> u64 {
+    start_of(add_business_days(date_of(trade_time), SETTLEMENT_DAYS))
+}
+
+fn nth_weekday(year: i32, month: Month, weekday: Weekday, n: u8) -> Option<Date> {
+    let first = Date::from_calendar_date(year, month, 1).ok()?;
+    let offset =
+        (7 + weekday.number_days_from_monday() - first.weekday().number_days_from_monday()) % 7;
+    Date::from_calendar_date(year, month, 1 + offset + 7 * (n - 1)).ok()
+}
+
+fn last_weekday(year: i32, month: Month, weekday: Weekday) -> Option<Date> {
+    let last = Date::from_calendar_date(year, month, month.length(year)).ok()?;
+    let offset =
+        (7 + last.weekday().number_days_from_monday() - weekday.number_days_from_monday()) % 7;
+    Date::from_calendar_date(year, month, last.day() - offset).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: Month, day: u8) -> Date {
+        Date::from_calendar_date(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_federal_holidays() {
+        let holidays_2025: Vec<Date> = [
+            (Month::January, 1),
+            (Month::January, 20),
+            (Month::February, 17),
+            (Month::May, 26),
+            (Month::June, 19),
+            (Month::July, 4),
+            (Month::September, 1),
+            (Month::October, 13),
+            (Month::November, 11),
+            (Month::November, 27),
+            (Month::December, 25),
+        ]
+        .into_iter()
+        .map(|(month, day)| date(2025, month, day))
+        .collect();
+        assert_eq!(federal_holidays(2025), holidays_2025);
+
+        // Sunday holidays move to Monday; Saturday ones are not observed on Friday
+        assert!(is_holiday(date(2022, Month::December, 26)));
+        assert!(!is_holiday(date(2022, Month::December, 25)));
+        assert!(is_business_day(date(2021, Month::December, 24)));
+        assert!(!is_holiday(date(2021, Month::June, 18)));
+    }
+
+    #[test]
+    fn test_maturity_and_settlement_roll_over_holidays() {
+        // A bill maturing on Thanksgiving pays on the Friday, at the same time of day
+        let thanksgiving = start_of(date(2025, Month::November, 27)) + 3_600;
+        assert_eq!(
+            payment_date(thanksgiving),
+            start_of(date(2025, Month::November, 28)) + 3_600
+        );
+        let friday = start_of(date(2025, Month::November, 28));
+        assert_eq!(payment_date(friday), friday);
+
+        // Bought the day before Independence Day, a Friday: settles the Monday after
+        let trade = start_of(date(2025, Month::July, 3)) + 50_000;
+        assert_eq!(settlement_date(trade), start_of(date(2025, Month::July, 7)));
+        let trade = start_of(date(2025, Month::July, 7)) + 50_000;
+        assert_eq!(settlement_date(trade), start_of(date(2025, Month::July, 8)));
+
+        assert_eq!(
+            DayCount::days(settlement_date(trade), payment_date(thanksgiving)),
+            143
+        );
+    }
+}
@@ -24,7 +24,12 @@ pub enum BitcoinUSTBillsError {
     InvalidAmount,
     MinimumInvestmentNotMet,
     MaximumInvestmentExceeded,
+    UnknownAsset(String),
     TradingNotAllowed,
+    FeatureDisabled(String),
+    QuoteNotFound,
+    QuoteExpired,
+    PriceLimitExceeded,
     
     // Holdings-related errors
     HoldingNotFound,
@@ -32,11 +37,19 @@ pub enum BitcoinUSTBillsError {
     HoldingMatured,
     InvalidHoldingData,
     
+    // Order book errors
+    OrderNotFound,
+    OrderNotOpen,
+    InvalidOrderPrice,
+    
     // Transaction-related errors
     TransactionNotFound,
     TransactionFailed,
     TransactionCancelled,
     InvalidTransactionType,
+    WithdrawalNotFound,
+    WithdrawalNotPending,
+    SelfApproval,
     
     // Platform errors
     PlatformConfigurationError,
@@ -45,18 +58,24 @@ pub enum BitcoinUSTBillsError {
     // External API errors
     ExternalAPIError(String),
     TreasuryDataFetchError,
+    TreasuryRateNotFound,
     HTTPRequestError(String),
+    LedgerError(String),
+    AssetPriceUnavailable(String),
+    StalePrice(String),
     
     // Storage & Database errors
     DatabaseError(String),
     StorageError(String),
     SerializationError(String),
+    JournalDrift(String),
     
     // Authentication & Authorization errors
     Unauthorized,
     AnonymousCaller,
     InvalidPrincipal,
     AccessDenied,
+    LastSuperAdmin,
     
     // Validation errors
     ValidationError(String),
@@ -69,6 +88,7 @@ pub enum BitcoinUSTBillsError {
     SystemError(String),
     InternalError(String),
     NotImplemented,
+    ArithmeticOverflow,
     
     // Yield calculation errors
     YieldCalculationError,
@@ -104,7 +124,12 @@ impl std::fmt::Display for BitcoinUSTBillsError {
             BitcoinUSTBillsError::InvalidAmount => write!(f, "Invalid transaction amount"),
             BitcoinUSTBillsError::MinimumInvestmentNotMet => write!(f, "Minimum investment amount not met"),
             BitcoinUSTBillsError::MaximumInvestmentExceeded => write!(f, "Maximum investment amount exceeded"),
+            BitcoinUSTBillsError::UnknownAsset(asset) => write!(f, "Unknown settlement asset: {}", asset),
             BitcoinUSTBillsError::TradingNotAllowed => write!(f, "Trading not allowed for this user"),
+            BitcoinUSTBillsError::FeatureDisabled(feature) => write!(f, "Feature disabled: {}", feature),
+            BitcoinUSTBillsError::QuoteNotFound => write!(f, "Quote not found"),
+            BitcoinUSTBillsError::QuoteExpired => write!(f, "Quote has expired"),
+            BitcoinUSTBillsError::PriceLimitExceeded => write!(f, "Total cost exceeds the limit given"),
             
             // Holdings-related errors
             BitcoinUSTBillsError::HoldingNotFound => write!(f, "Token holding not found"),
@@ -112,11 +137,19 @@ impl std::fmt::Display for BitcoinUSTBillsError {
             BitcoinUSTBillsError::HoldingMatured => write!(f, "Token holding has matured"),
             BitcoinUSTBillsError::InvalidHoldingData => write!(f, "Invalid holding data"),
             
+            // Order book errors
+            BitcoinUSTBillsError::OrderNotFound => write!(f, "Order not found"),
+            BitcoinUSTBillsError::OrderNotOpen => write!(f, "Order is no longer open"),
+            BitcoinUSTBillsError::InvalidOrderPrice => write!(f, "Invalid order price"),
+            
             // Transaction-related errors
             BitcoinUSTBillsError::TransactionNotFound => write!(f, "Transaction not found"),
             BitcoinUSTBillsError::TransactionFailed => write!(f, "Transaction failed"),
             BitcoinUSTBillsError::TransactionCancelled => write!(f, "Transaction was cancelled"),
             BitcoinUSTBillsError::InvalidTransactionType => write!(f, "Invalid transaction type"),
+            BitcoinUSTBillsError::WithdrawalNotFound => write!(f, "Withdrawal not found"),
+            BitcoinUSTBillsError::WithdrawalNotPending => write!(f, "Withdrawal is no longer pending approval"),
+            BitcoinUSTBillsError::SelfApproval => write!(f, "Withdrawals must be approved by someone other than the requester"),
             
             // Platform errors
             BitcoinUSTBillsError::PlatformConfigurationError => write!(f, "Platform configuration error"),
@@ -125,18 +158,24 @@ impl std::fmt::Display for BitcoinUSTBillsError {
             // External API errors
             BitcoinUSTBillsError::ExternalAPIError(msg) => write!(f, "External API error: {}", msg),
             BitcoinUSTBillsError::TreasuryDataFetchError => write!(f, "Failed to fetch Treasury data"),
+            BitcoinUSTBillsError::TreasuryRateNotFound => write!(f, "Treasury rate not found"),
             BitcoinUSTBillsError::HTTPRequestError(msg) => write!(f, "HTTP request error: {}", msg),
+            BitcoinUSTBillsError::LedgerError(msg) => write!(f, "Ledger error: {}", msg),
+            BitcoinUSTBillsError::AssetPriceUnavailable(asset) => write!(f, "No USD price for {}", asset),
+            BitcoinUSTBillsError::StalePrice(asset) => write!(f, "USD price for {} is stale", asset),
             
             // Storage & Database errors
             BitcoinUSTBillsError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             BitcoinUSTBillsError::StorageError(msg) => write!(f, "Storage error: {}", msg),
             BitcoinUSTBillsError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
+            BitcoinUSTBillsError::JournalDrift(msg) => write!(f, "Journal disagrees with stored balances: {}", msg),
             
             // Authentication & Authorization errors
             BitcoinUSTBillsError::Unauthorized => write!(f, "Unauthorized access"),
             BitcoinUSTBillsError::AnonymousCaller => write!(f, "Anonymous caller not allowed"),
             BitcoinUSTBillsError::InvalidPrincipal => write!(f, "Invalid principal"),
             BitcoinUSTBillsError::AccessDenied => write!(f, "Access denied"),
+            BitcoinUSTBillsError::LastSuperAdmin => write!(f, "Cannot revoke the last SuperAdmin"),
             
             // Validation errors
             BitcoinUSTBillsError::ValidationError(msg) => write!(f, "Validation error: {}", msg),
@@ -149,6 +188,7 @@ impl std::fmt::Display for BitcoinUSTBillsError {
             BitcoinUSTBillsError::SystemError(msg) => write!(f, "System error: {}", msg),
             BitcoinUSTBillsError::InternalError(msg) => write!(f, "Internal error: {}", msg),
             BitcoinUSTBillsError::NotImplemented => write!(f, "Functionality not implemented"),
+            BitcoinUSTBillsError::ArithmeticOverflow => write!(f, "Amount out of range"),
             
             // Yield calculation errors
             BitcoinUSTBillsError::YieldCalculationError => write!(f, "Yield calculation error"),
@@ -206,4 +246,8 @@ impl BitcoinUSTBillsError {
     pub fn http_request_error(msg: impl Into<String>) -> Self {
         BitcoinUSTBillsError::HTTPRequestError(msg.into())
     }
+
+    pub fn ledger_error(msg: impl Into<String>) -> Self {
+        BitcoinUSTBillsError::LedgerError(msg.into())
+    }
 }
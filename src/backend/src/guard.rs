@@ -1,65 +1,113 @@
 use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::storage::RoleStorage;
+use crate::types::{Permission, Role};
 use candid::Principal;
-use std::{cell::RefCell, collections::BTreeSet};
 
-const INITIAL_AUTHORIZED_PRINCIPAL: &str =
-    "6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe";
+/// Permissions carried by each role. SuperAdmin carries every permission.
+pub fn role_permissions(role: Role) -> &'static [Permission] {
+    match role {
+        Role::SuperAdmin => &[
+            Permission::ManageRoles,
+            Permission::ManageBills,
+            Permission::ManageKyc,
+            Permission::ManageTreasury,
+            Permission::ManageMarketData,
+            Permission::ManageOrders,
+            Permission::ManagePlatform,
+            Permission::ViewAuditData,
+        ],
+        Role::BillIssuer => &[Permission::ManageBills],
+        Role::KYCOfficer => &[Permission::ManageKyc],
+        Role::Treasurer => &[Permission::ManageTreasury, Permission::ManageMarketData],
+        Role::Auditor => &[Permission::ViewAuditData],
+        Role::Support => &[Permission::ManageOrders],
+    }
+}
+
+/// Checks if any of the principal's roles carries the permission
+pub fn has_permission(principal: &Principal, permission: Permission) -> bool {
+    RoleStorage::get(principal)
+        .into_iter()
+        .any(|role| role_permissions(role).contains(&permission))
+}
 
-thread_local! {
-    pub static GUARD: RefCell<BTreeSet<Principal>> = RefCell::new({
-        let mut set = BTreeSet::new();
-        let initial_principal = Principal::from_text(INITIAL_AUTHORIZED_PRINCIPAL)
-        .expect("Invalid initial principal");
+/// Assert that the caller holds a role carrying the permission
+pub fn assert_permission(permission: Permission) -> Result<()> {
+    let caller = ic_cdk::api::msg_caller();
+    validate_principal(&caller)?;
+
+    if !has_permission(&caller, permission) {
+        return Err(BitcoinUSTBillsError::Unauthorized);
+    }
 
-        set.insert(initial_principal);
-        set
-    });
+    Ok(())
 }
 
-/// Adds a principal to the authorized list
-pub fn add_to_list(principal: Principal) -> () {
-    GUARD.with(|guard| {
-        let mut guard_ref = guard.borrow_mut();
+/// Grants SuperAdmin to the principals named at install, or to the installer if none are named
+pub fn init_super_admins(admins: Vec<Principal>, installer: Principal) -> Result<()> {
+    let admins = if admins.is_empty() {
+        vec![installer]
+    } else {
+        admins
+    };
+
+    for admin in admins {
+        grant_role(admin, Role::SuperAdmin)?;
+    }
 
-        if guard_ref.contains(&principal) {
-            return ();
-        } else {
-            guard_ref.insert(principal);
-            return ();
-        }
-    })
+    Ok(())
 }
 
-/// Removes a principal from the authorized list
-pub fn delete_from_list(p: Principal) -> String {
-    GUARD.with(|guard| {
-        let mut guard_ref = guard.borrow_mut();
+/// Makes `fallback` SuperAdmin when no SuperAdmin exists, so the canister can't be locked out
+pub fn ensure_super_admin(fallback: Principal) {
+    if RoleStorage::holders(Role::SuperAdmin).is_empty() && validate_principal(&fallback).is_ok() {
+        RoleStorage::grant(fallback, Role::SuperAdmin);
+    }
+}
 
-        if !guard_ref.contains(&p) {
-            return "Principal {} is not in the guard list".to_string();
-        } else {
-            guard_ref.remove(&p);
-            return "removed".to_string();
-        }
-    })
+/// Grants a role, returning false if the principal already held it
+pub fn grant_role(principal: Principal, role: Role) -> Result<bool> {
+    validate_principal(&principal)?;
+    Ok(RoleStorage::grant(principal, role))
 }
 
-/// Checks if the caller is an authorized developer/admin
+/// Revokes a role, returning false if the principal did not hold it.
+/// The last SuperAdmin can't be revoked.
+pub fn revoke_role(principal: &Principal, role: Role) -> Result<bool> {
+    if role == Role::SuperAdmin && RoleStorage::holders(Role::SuperAdmin) == [*principal] {
+        return Err(BitcoinUSTBillsError::LastSuperAdmin);
+    }
+
+    Ok(RoleStorage::revoke(principal, role))
+}
+
+/// Adds a principal as SuperAdmin (legacy allow-list API)
+pub fn add_to_list(principal: Principal) -> Result<()> {
+    grant_role(principal, Role::SuperAdmin).map(|_| ())
+}
+
+/// Removes a principal's SuperAdmin role (legacy allow-list API)
+pub fn delete_from_list(p: Principal) -> String {
+    match revoke_role(&p, Role::SuperAdmin) {
+        Ok(true) => "removed".to_string(),
+        Ok(false) => format!("Principal {} is not in the guard list", p),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Checks if the caller is a SuperAdmin
 pub fn is_dev() -> std::result::Result<(), String> {
-        let caller = ic_cdk::api::msg_caller();
+    let caller = ic_cdk::api::msg_caller();
     let anonymous = Principal::anonymous();
     if caller == anonymous {
         return Err("AnonymousCaller".to_string());
     }
 
-    GUARD.with(|guard| {
-        let guard_ref = guard.borrow();
-        if !guard_ref.contains(&caller) {
-            return Err(format!("Caller {} is not authorized", caller));
-        } else {
-            return Ok(());
-        }
-    })
+    if !is_authorized(&caller) {
+        Err(format!("Caller {} is not authorized", caller))
+    } else {
+        Ok(())
+    }
 }
 
 /// Checks if the caller is an admin (same as is_dev for now)
@@ -67,64 +115,37 @@ pub fn is_admin() -> std::result::Result<(), String> {
     is_dev()
 }
 
-/// Assert that the caller is an admin, returning BitcoinUSTBillsError
+/// Assert that the caller is a SuperAdmin, returning BitcoinUSTBillsError
 pub fn assert_admin() -> Result<()> {
-        let caller = ic_cdk::api::msg_caller();
-    let anonymous = Principal::anonymous();
+    let caller = ic_cdk::api::msg_caller();
+    validate_principal(&caller)?;
 
-    if caller == anonymous {
-        return Err(BitcoinUSTBillsError::AnonymousCaller);
+    if !is_authorized(&caller) {
+        Err(BitcoinUSTBillsError::Unauthorized)
+    } else {
+        Ok(())
     }
-
-    GUARD.with(|guard| {
-        let guard_ref = guard.borrow();
-        if !guard_ref.contains(&caller) {
-            return Err(BitcoinUSTBillsError::Unauthorized);
-        } else {
-            return Ok(());
-        }
-    })
 }
 
 /// Assert that the caller is a verified user (not anonymous)
 pub fn assert_user() -> Result<()> {
-        let caller = ic_cdk::api::msg_caller();
-    let anonymous = Principal::anonymous();
-
-    if caller == anonymous {
-        return Err(BitcoinUSTBillsError::AnonymousCaller);
-    }
-
-    Ok(())
+    let caller = ic_cdk::api::msg_caller();
+    validate_principal(&caller)
 }
 
-/// Checks if a principal is in the authorized list
+/// Checks if a principal is a SuperAdmin
 pub fn is_authorized(principal: &Principal) -> bool {
-    GUARD.with(|guard| {
-        let guard_ref = guard.borrow();
-        guard_ref.contains(principal)
-    })
+    RoleStorage::has_role(principal, Role::SuperAdmin)
 }
 
-/// Gets all authorized principals
+/// Gets all SuperAdmin principals
 pub fn get_authorized_principals() -> Vec<Principal> {
-    GUARD.with(|guard| {
-        let guard_ref = guard.borrow();
-        guard_ref.iter().cloned().collect()
-    })
-}
-
-/// Gets the count of authorized principals
-pub fn get_authorized_count() -> usize {
-    GUARD.with(|guard| {
-        let guard_ref = guard.borrow();
-        guard_ref.len()
-    })
+    RoleStorage::holders(Role::SuperAdmin)
 }
 
 /// Checks if the caller is the specific principal
 pub fn assert_caller_is(expected: &Principal) -> Result<()> {
-        let caller = ic_cdk::api::msg_caller();
+    let caller = ic_cdk::api::msg_caller();
 
     if caller != *expected {
         return Err(BitcoinUSTBillsError::Unauthorized);
@@ -135,7 +156,7 @@ pub fn assert_caller_is(expected: &Principal) -> Result<()> {
 
 /// Checks if the caller is either admin or the specific principal
 pub fn assert_admin_or_caller(expected: &Principal) -> Result<()> {
-        let caller = ic_cdk::api::msg_caller();
+    let caller = ic_cdk::api::msg_caller();
 
     // Check if caller is admin
     if is_authorized(&caller) {
@@ -150,26 +171,6 @@ pub fn assert_admin_or_caller(expected: &Principal) -> Result<()> {
     Err(BitcoinUSTBillsError::Unauthorized)
 }
 
-/// Initializes the guard with a specific principal
-pub fn init_guard(principal: Principal) -> Result<()> {
-    GUARD.with(|guard| {
-        let mut guard_ref = guard.borrow_mut();
-        guard_ref.insert(principal);
-        Ok(())
-    })
-}
-
-/// Clears all authorized principals (use with caution)
-pub fn clear_guard() -> Result<()> {
-    assert_admin()?;
-
-    GUARD.with(|guard| {
-        let mut guard_ref = guard.borrow_mut();
-        guard_ref.clear();
-        Ok(())
-    })
-}
-
 /// Validates that a principal is not anonymous
 pub fn validate_principal(principal: &Principal) -> Result<()> {
     if *principal == Principal::anonymous() {
@@ -178,51 +179,59 @@ pub fn validate_principal(principal: &Principal) -> Result<()> {
     Ok(())
 }
 
-/// Checks if the system has any authorized principals
-pub fn has_authorized_principals() -> bool {
-    GUARD.with(|guard| {
-        let guard_ref = guard.borrow();
-        !guard_ref.is_empty()
-    })
-}
-
-/// Gets the initial authorized principal
-pub fn get_initial_principal() -> Principal {
-    Principal::from_text(INITIAL_AUTHORIZED_PRINCIPAL).expect("Invalid initial principal")
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_initial_principal() {
-        let initial = get_initial_principal();
-        assert!(is_authorized(&initial));
+    fn principal(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
     }
 
     #[test]
-    fn test_add_and_remove_principal() {
-        let test_principal = Principal::from_text("rdmx6-jaaaa-aaaah-qcaiq-cai").unwrap();
-
-        // Initially should not be authorized
-        assert!(!is_authorized(&test_principal));
-
-        // Add to list
-        add_to_list(test_principal);
-        assert!(is_authorized(&test_principal));
+    fn test_permission_matrix() {
+        let officer = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        grant_role(officer, Role::KYCOfficer).unwrap();
+
+        assert!(has_permission(&officer, Permission::ManageKyc));
+        assert!(!has_permission(&officer, Permission::ManageBills));
+        assert!(!is_authorized(&officer));
+
+        // Roles accumulate; SuperAdmin carries every permission
+        grant_role(officer, Role::SuperAdmin).unwrap();
+        assert!(has_permission(&officer, Permission::ManageBills));
+        assert_eq!(RoleStorage::get(&officer), vec![Role::SuperAdmin, Role::KYCOfficer]);
+    }
 
-        // Remove from list
-        delete_from_list(test_principal);
-        assert!(!is_authorized(&test_principal));
+    #[test]
+    fn test_init_and_revoke_super_admin() {
+        let installer = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let admin = principal("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe");
+
+        init_super_admins(vec![], installer).unwrap();
+        assert_eq!(get_authorized_principals(), vec![installer]);
+
+        // The last SuperAdmin stays
+        assert!(matches!(
+            revoke_role(&installer, Role::SuperAdmin),
+            Err(BitcoinUSTBillsError::LastSuperAdmin)
+        ));
+
+        add_to_list(admin).unwrap();
+        assert_eq!(delete_from_list(installer), "removed");
+        assert!(!is_authorized(&installer));
+        assert!(!revoke_role(&installer, Role::SuperAdmin).unwrap());
+
+        ensure_super_admin(installer);
+        assert!(!is_authorized(&installer));
     }
 
     #[test]
     fn test_validate_principal() {
         let anonymous = Principal::anonymous();
         assert!(validate_principal(&anonymous).is_err());
+        assert!(grant_role(anonymous, Role::Support).is_err());
 
-        let valid = get_initial_principal();
+        let valid = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
         assert!(validate_principal(&valid).is_ok());
     }
 }
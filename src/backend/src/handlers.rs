@@ -1,42 +1,190 @@
-use crate::guard::GUARD;
-use crate::store::DATA;
-use candid::{CandidType, Principal};
-use ic_cdk::{post_upgrade, pre_upgrade, storage};
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
-use std::collections::HashMap;
-
-// StableStore struct is used for serializing and deserializing the data during upgrades
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
-pub struct StableStore {
-    pub data: HashMap<Principal, String>,
-    pub guard: Vec<Principal>,
-}
-
-#[pre_upgrade]
-pub fn pre_upgrade_handler() {
-    let stable_store = DATA.with(|data| {
-        GUARD.with(|guard| {
-            let data_ref = data.borrow().clone();
-            let guard_ref = guard.borrow().iter().cloned().collect();
-            StableStore {
-                data: data_ref,
-                guard: guard_ref,
-            }
-        })
-    });
-    storage::stable_save((stable_store,)).unwrap();
-}
-
-// Post-upgrade hook: Restores the state from stable storage after an upgrade
+use crate::errors::Result;
+use crate::storage::{
+    get_current_timestamp, take_legacy_stable_store, FeatureFlagsStorage, LegacyDataStorage,
+    LegacyStableStore, OracleConfigStorage, PlatformConfigStorage, RateSourceStorage, RoleStorage,
+    SettlementConfigStorage,
+};
+use crate::types::{
+    FeatureFlags, InitArgs, OracleConfig, PlatformConfig, RateSource, Role, SettlementConfig,
+    UpgradeArgs,
+};
+use crate::{accounting, guard, icrc3, oracle, settlement, timers, treasury};
+use candid::Principal;
+use ic_cdk::{init, post_upgrade};
+
+// All canister state lives in stable structures (see storage.rs), so upgrades
+// need no pre_upgrade serialization step. The baseline still had one; its payload is
+// taken over on the first upgrade.
+
+#[init]
+pub fn init_handler(args: Option<InitArgs>) {
+    if let Err(e) = apply_init_args(args.unwrap_or_default(), ic_cdk::api::msg_caller()) {
+        ic_cdk::trap(format!("Invalid init arguments: {}", e));
+    }
+    timers::start_timers();
+}
+
+// Post-upgrade hook: stable structures are re-attached lazily; certified data and timers
+// need restoring.
+// Trapping on invalid arguments rolls the upgrade back.
 #[post_upgrade]
-pub fn post_upgrade_handler() {
-    let (stable_store,): (StableStore,) = storage::stable_restore().unwrap();
-    DATA.with(|data| {
-        *data.borrow_mut() = stable_store.data;
-    });
-
-    GUARD.with(|guard| {
-        *guard.borrow_mut() = BTreeSet::from_iter(stable_store.guard);
-    })
+pub fn post_upgrade_handler(args: Option<UpgradeArgs>) {
+    // Must run before any store is touched
+    match take_legacy_stable_store() {
+        Ok(Some(store)) => restore_legacy_store(store),
+        Ok(None) => {}
+        Err(e) => ic_cdk::trap(format!("Failed to read the pre-upgrade state: {}", e)),
+    }
+
+    if let Some(args) = args {
+        if let Err(e) = apply_upgrade_args(args) {
+            ic_cdk::trap(format!("Invalid upgrade arguments: {}", e));
+        }
+    }
+
+    // Canisters upgraded from the single admin allow-list have no SuperAdmin yet
+    guard::ensure_super_admin(ic_cdk::api::msg_caller());
+    // Canisters upgraded from before the journal carry their balances onto the books
+    if let Err(e) = accounting::post_opening_balances(get_current_timestamp()) {
+        ic_cdk::trap(format!("Failed to post opening balances: {}", e));
+    }
+    icrc3::certify_tip();
+    timers::start_timers();
+}
+
+// The baseline's admin allow-list maps to SuperAdmin, as the legacy allow-list API does
+fn restore_legacy_store(store: LegacyStableStore) {
+    for (principal, data) in store.data {
+        LegacyDataStorage::insert(principal, data);
+    }
+    for admin in store.guard {
+        if guard::validate_principal(&admin).is_ok() {
+            RoleStorage::grant(admin, Role::SuperAdmin);
+        }
+    }
+}
+
+fn apply_init_args(args: InitArgs, installer: Principal) -> Result<()> {
+    validate_settings(
+        &args.initial_super_admins,
+        &args.platform_config,
+        &args.treasury_rates_url,
+        &args.settlement,
+        &args.oracle,
+    )?;
+
+    guard::init_super_admins(args.initial_super_admins, installer)?;
+    apply_settings(
+        args.platform_config,
+        args.treasury_rates_url,
+        args.features,
+        args.settlement,
+        args.oracle,
+    )
+}
+
+fn apply_upgrade_args(args: UpgradeArgs) -> Result<()> {
+    let admins = args.add_super_admins.unwrap_or_default();
+    validate_settings(
+        &admins,
+        &args.platform_config,
+        &args.treasury_rates_url,
+        &args.settlement,
+        &args.oracle,
+    )?;
+
+    for admin in admins {
+        guard::grant_role(admin, Role::SuperAdmin)?;
+    }
+    apply_settings(
+        args.platform_config,
+        args.treasury_rates_url,
+        args.features,
+        args.settlement,
+        args.oracle,
+    )
+}
+
+/// Checks every argument before anything is written
+fn validate_settings(
+    admins: &[Principal],
+    platform_config: &Option<PlatformConfig>,
+    treasury_rates_url: &Option<String>,
+    settlement_config: &Option<SettlementConfig>,
+    oracle_config: &Option<OracleConfig>,
+) -> Result<()> {
+    for admin in admins {
+        guard::validate_principal(admin)?;
+    }
+    if let Some(config) = platform_config {
+        crate::validate_platform_config(config)?;
+    }
+    if let Some(url) = treasury_rates_url {
+        treasury::validate_rates_url(url)?;
+    }
+    if let Some(config) = settlement_config {
+        settlement::validate_config(config)?;
+    }
+    if let Some(config) = oracle_config {
+        oracle::validate_config(config)?;
+    }
+    Ok(())
+}
+
+fn apply_settings(
+    platform_config: Option<PlatformConfig>,
+    treasury_rates_url: Option<String>,
+    features: Option<FeatureFlags>,
+    settlement_config: Option<SettlementConfig>,
+    oracle_config: Option<OracleConfig>,
+) -> Result<()> {
+    if let Some(config) = platform_config {
+        PlatformConfigStorage::update(config)?;
+    }
+    if let Some(url) = treasury_rates_url {
+        RateSourceStorage::update(RateSource::TreasuryApi { url })?;
+    }
+    if let Some(flags) = features {
+        FeatureFlagsStorage::update(flags)?;
+    }
+    if let Some(config) = settlement_config {
+        SettlementConfigStorage::update(config)?;
+    }
+    if let Some(config) = oracle_config {
+        OracleConfigStorage::update(config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_init_args_write_nothing() {
+        let admin = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let args = InitArgs {
+            initial_super_admins: vec![admin],
+            platform_config: Some(PlatformConfig {
+                minimum_investment: 10_000,
+                ..PlatformConfig::default()
+            }),
+            treasury_rates_url: Some("http://localhost:8000/rates".to_string()),
+            features: None,
+            settlement: None,
+            oracle: None,
+        };
+
+        assert!(apply_init_args(args.clone(), admin).is_err());
+        assert!(!guard::is_authorized(&admin));
+        assert_eq!(PlatformConfigStorage::get().minimum_investment, 100);
+
+        let args = InitArgs {
+            treasury_rates_url: Some("https://localhost:8000/rates".to_string()),
+            ..args
+        };
+        apply_init_args(args, Principal::anonymous()).unwrap();
+        assert!(guard::is_authorized(&admin));
+        assert_eq!(PlatformConfigStorage::get().minimum_investment, 10_000);
+    }
 }
@@ -0,0 +1,963 @@
+use candid::{CandidType, Nat, Principal};
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::accounting;
+use crate::errors::BitcoinUSTBillsError;
+use crate::icrc3::{self, BlockOp};
+use crate::money::Money;
+use crate::orderbook;
+use crate::storage::*;
+use crate::types::*;
+
+/// Ledger transfers are free; platform fees are only charged on trades
+pub const TRANSFER_FEE: u64 = 0;
+
+/// Tokens are whole units of a bill
+pub const DECIMALS: u8 = 0;
+
+const MAX_MEMO_BYTES: usize = 32;
+const TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000;
+
+// `GenericError` codes
+const ERROR_UNKNOWN_TOKEN: u64 = 1;
+const ERROR_NOT_TRANSFERABLE: u64 = 2;
+const ERROR_UNSUPPORTED_SUBACCOUNT: u64 = 3;
+const ERROR_INELIGIBLE_ACCOUNT: u64 = 4;
+const ERROR_INVALID_ARGUMENT: u64 = 5;
+const ERROR_INTERNAL: u64 = 6;
+
+/// Failures shared by transfer, approve and transfer_from, mapped onto each call's error type
+#[derive(Debug)]
+enum LedgerError {
+    BadFee,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    InsufficientFunds { balance: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: u64 },
+    Generic { code: u64, message: String },
+}
+
+impl LedgerError {
+    fn generic(code: u64, message: impl Into<String>) -> Self {
+        LedgerError::Generic {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<BitcoinUSTBillsError> for LedgerError {
+    fn from(error: BitcoinUSTBillsError) -> Self {
+        LedgerError::generic(ERROR_INTERNAL, error.to_string())
+    }
+}
+
+impl From<LedgerError> for TransferError {
+    fn from(error: LedgerError) -> Self {
+        match error {
+            LedgerError::BadFee => TransferError::BadFee {
+                expected_fee: Nat::from(TRANSFER_FEE),
+            },
+            LedgerError::TooOld => TransferError::TooOld,
+            LedgerError::CreatedInFuture { ledger_time } => {
+                TransferError::CreatedInFuture { ledger_time }
+            }
+            LedgerError::InsufficientFunds { balance } => TransferError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            LedgerError::TemporarilyUnavailable => TransferError::TemporarilyUnavailable,
+            LedgerError::Duplicate { duplicate_of } => TransferError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            LedgerError::Generic { code, message } => TransferError::GenericError {
+                error_code: Nat::from(code),
+                message,
+            },
+        }
+    }
+}
+
+impl From<LedgerError> for TransferFromError {
+    fn from(error: LedgerError) -> Self {
+        match error {
+            LedgerError::BadFee => TransferFromError::BadFee {
+                expected_fee: Nat::from(TRANSFER_FEE),
+            },
+            LedgerError::TooOld => TransferFromError::TooOld,
+            LedgerError::CreatedInFuture { ledger_time } => {
+                TransferFromError::CreatedInFuture { ledger_time }
+            }
+            LedgerError::InsufficientFunds { balance } => TransferFromError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            LedgerError::TemporarilyUnavailable => TransferFromError::TemporarilyUnavailable,
+            LedgerError::Duplicate { duplicate_of } => TransferFromError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            LedgerError::Generic { code, message } => TransferFromError::GenericError {
+                error_code: Nat::from(code),
+                message,
+            },
+        }
+    }
+}
+
+impl From<LedgerError> for ApproveError {
+    fn from(error: LedgerError) -> Self {
+        match error {
+            LedgerError::BadFee => ApproveError::BadFee {
+                expected_fee: Nat::from(TRANSFER_FEE),
+            },
+            LedgerError::TooOld => ApproveError::TooOld,
+            LedgerError::CreatedInFuture { ledger_time } => {
+                ApproveError::CreatedInFuture { ledger_time }
+            }
+            LedgerError::InsufficientFunds { balance } => ApproveError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            LedgerError::TemporarilyUnavailable => ApproveError::TemporarilyUnavailable,
+            LedgerError::Duplicate { duplicate_of } => ApproveError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            LedgerError::Generic { code, message } => ApproveError::GenericError {
+                error_code: Nat::from(code),
+                message,
+            },
+        }
+    }
+}
+
+pub fn token_name(ustbill: &USTBill) -> String {
+    format!("{} US Treasury Bill {}", ustbill.bill_type, ustbill.cusip)
+}
+
+pub fn token_symbol(ustbill: &USTBill) -> String {
+    format!("UST-{}", ustbill.cusip)
+}
+
+pub fn metadata(ustbill: &USTBill) -> Vec<(String, MetadataValue)> {
+    vec![
+        (
+            "ustbills:name".to_string(),
+            MetadataValue::Text(token_name(ustbill)),
+        ),
+        (
+            "ustbills:symbol".to_string(),
+            MetadataValue::Text(token_symbol(ustbill)),
+        ),
+        (
+            "ustbills:decimals".to_string(),
+            MetadataValue::Nat(Nat::from(DECIMALS)),
+        ),
+        (
+            "ustbills:fee".to_string(),
+            MetadataValue::Nat(Nat::from(TRANSFER_FEE)),
+        ),
+        (
+            "ustbills:cusip".to_string(),
+            MetadataValue::Text(ustbill.cusip.clone()),
+        ),
+        (
+            "ustbills:face_value".to_string(),
+            MetadataValue::Nat(Nat::from(ustbill.face_value)),
+        ),
+        (
+            "ustbills:maturity_date".to_string(),
+            MetadataValue::Nat(Nat::from(ustbill.maturity_date)),
+        ),
+    ]
+}
+
+/// Bill tokens take a bill id on every call, so only the block log follows a standard
+pub fn supported_standards() -> Vec<StandardRecord> {
+    vec![
+        StandardRecord {
+            name: "ICRC-3".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+        StandardRecord {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-10/ICRC-10.md".to_string(),
+        },
+    ]
+}
+
+/// Transferable balance; tokens committed to open ask orders are excluded
+pub fn balance_of(ustbill_id: &str, account: &Account, now_nanos: u64) -> u64 {
+    if !is_default_subaccount(&account.subaccount) {
+        return 0;
+    }
+
+    let now = now_nanos / 1_000_000_000;
+    owned_holdings(ustbill_id, &account.owner)
+        .iter()
+        .map(|holding| orderbook::available_tokens(holding, now))
+        .sum()
+}
+
+pub fn transfer(
+    ustbill_id: &str,
+    caller: Principal,
+    arg: TransferArg,
+    now_nanos: u64,
+) -> Result<Nat, TransferError> {
+    check_call(&arg.fee, arg.created_at_time, &arg.memo, now_nanos)?;
+    let call = call_key("transfer", ustbill_id, caller, &arg, arg.created_at_time)?;
+    check_duplicate(&call)?;
+
+    let from = Account {
+        owner: caller,
+        subaccount: arg.from_subaccount,
+    };
+    let mut batch = WriteBatch::new();
+    let amount = move_tokens(&mut batch, ustbill_id, &from, &arg.to, &arg.amount, now_nanos)?;
+
+    let (ustbill_id, to) = (ustbill_id.to_string(), arg.to.owner);
+    Ok(commit_with_block(batch, call, now_nanos, move || {
+        let transfer = BlockOp::Transfer {
+            ustbill_id: &ustbill_id,
+            from: caller,
+            to,
+            spender: None,
+            amount,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+        };
+        icrc3::append(transfer, now_nanos)
+    })?)
+}
+
+/// Current allowance, reading an expired one as zero
+pub fn allowance(ustbill_id: &str, args: &AllowanceArgs, now_nanos: u64) -> Allowance {
+    AllowanceStorage::get(ustbill_id, &args.account.owner, &args.spender.owner)
+        .filter(|allowance| {
+            allowance
+                .expires_at
+                .is_none_or(|expires_at| expires_at > now_nanos)
+        })
+        .unwrap_or(Allowance {
+            allowance: Nat::from(0u64),
+            expires_at: None,
+        })
+}
+
+pub fn approve(
+    ustbill_id: &str,
+    caller: Principal,
+    args: ApproveArgs,
+    now_nanos: u64,
+) -> Result<Nat, ApproveError> {
+    check_call(&args.fee, args.created_at_time, &args.memo, now_nanos)?;
+    let call = call_key("approve", ustbill_id, caller, &args, args.created_at_time)?;
+    check_duplicate(&call)?;
+
+    let owner = Account {
+        owner: caller,
+        subaccount: args.from_subaccount,
+    };
+    check_default_subaccount(&owner)?;
+    check_default_subaccount(&args.spender)?;
+    if args.spender.owner == caller {
+        return Err(LedgerError::generic(
+            ERROR_INVALID_ARGUMENT,
+            "Cannot approve yourself as spender",
+        )
+        .into());
+    }
+    transferable_bill(ustbill_id, now_nanos)?;
+
+    if let Some(expires_at) = args.expires_at {
+        if expires_at <= now_nanos {
+            return Err(ApproveError::Expired {
+                ledger_time: now_nanos,
+            });
+        }
+    }
+
+    let current = allowance(
+        ustbill_id,
+        &AllowanceArgs {
+            account: owner,
+            spender: args.spender.clone(),
+        },
+        now_nanos,
+    );
+    if let Some(expected) = args.expected_allowance {
+        if expected != current.allowance {
+            return Err(ApproveError::AllowanceChanged {
+                current_allowance: current.allowance,
+            });
+        }
+    }
+
+    let transaction = Transaction {
+        id: generate_id(),
+        user_principal: caller,
+        transaction_type: TransactionType::Approval,
+        amount: u64::try_from(&args.amount.0).unwrap_or(u64::MAX),
+        ustbill_id: Some(ustbill_id.to_string()),
+        holding_id: None,
+        timestamp: now_nanos / 1_000_000_000,
+        status: TransactionStatus::Completed,
+        fees: 0,
+        description: format!(
+            "Approved {} to spend {} tokens of UST Bill {}",
+            args.spender.owner, args.amount, ustbill_id
+        ),
+    };
+    let mut batch = WriteBatch::new();
+    batch.insert_transaction(transaction);
+
+    let (ustbill_id, spender) = (ustbill_id.to_string(), args.spender.owner);
+    Ok(commit_with_block(batch, call, now_nanos, move || {
+        AllowanceStorage::set(
+            &ustbill_id,
+            &caller,
+            &spender,
+            Allowance {
+                allowance: args.amount.clone(),
+                expires_at: args.expires_at,
+            },
+        );
+        let approval = BlockOp::Approve {
+            ustbill_id: &ustbill_id,
+            from: caller,
+            spender,
+            amount: args.amount,
+            expires_at: args.expires_at,
+        };
+        icrc3::append(approval, now_nanos)
+    })?)
+}
+
+pub fn transfer_from(
+    ustbill_id: &str,
+    caller: Principal,
+    args: TransferFromArgs,
+    now_nanos: u64,
+) -> Result<Nat, TransferFromError> {
+    check_call(&args.fee, args.created_at_time, &args.memo, now_nanos)?;
+    let call = call_key("transfer_from", ustbill_id, caller, &args, args.created_at_time)?;
+    check_duplicate(&call)?;
+
+    let spender = Account {
+        owner: caller,
+        subaccount: args.spender_subaccount,
+    };
+    check_default_subaccount(&spender)?;
+
+    let (bill_id, from, to) = (ustbill_id.to_string(), args.from.owner, args.to.owner);
+    let (memo, created_at_time) = (args.memo.clone(), args.created_at_time);
+    let transfer = move |amount, spender| {
+        move || {
+            let transfer = BlockOp::Transfer {
+                ustbill_id: &bill_id,
+                from,
+                to,
+                spender,
+                amount,
+                memo,
+                created_at_time,
+            };
+            icrc3::append(transfer, now_nanos)
+        }
+    };
+
+    // Owners moving their own tokens need no allowance
+    let mut batch = WriteBatch::new();
+    if caller == args.from.owner {
+        let amount = move_tokens(
+            &mut batch,
+            ustbill_id,
+            &args.from,
+            &args.to,
+            &args.amount,
+            now_nanos,
+        )?;
+        return Ok(commit_with_block(batch, call, now_nanos, transfer(amount, None))?);
+    }
+
+    let current = allowance(
+        ustbill_id,
+        &AllowanceArgs {
+            account: args.from.clone(),
+            spender,
+        },
+        now_nanos,
+    );
+    if current.allowance < args.amount {
+        return Err(TransferFromError::InsufficientAllowance {
+            allowance: current.allowance,
+        });
+    }
+
+    let amount = move_tokens(
+        &mut batch,
+        ustbill_id,
+        &args.from,
+        &args.to,
+        &args.amount,
+        now_nanos,
+    )?;
+    let remaining = Allowance {
+        allowance: current.allowance - args.amount,
+        expires_at: current.expires_at,
+    };
+    let owner_bill_id = ustbill_id.to_string();
+    batch.then(move || {
+        AllowanceStorage::set(&owner_bill_id, &from, &caller, remaining);
+        Ok(())
+    });
+
+    Ok(commit_with_block(batch, call, now_nanos, transfer(amount, Some(caller)))?)
+}
+
+/// Stages moving tokens between accounts by reassigning or splitting the sender's
+/// holdings, returning the number of tokens moved
+fn move_tokens(
+    batch: &mut WriteBatch,
+    ustbill_id: &str,
+    from: &Account,
+    to: &Account,
+    amount: &Nat,
+    now_nanos: u64,
+) -> Result<u64, LedgerError> {
+    if !FeatureFlagsStorage::get().trading_enabled {
+        return Err(LedgerError::TemporarilyUnavailable);
+    }
+    check_default_subaccount(from)?;
+    check_default_subaccount(to)?;
+    let ustbill = transferable_bill(ustbill_id, now_nanos)?;
+
+    let mut recipient = UserStorage::get(&to.owner)
+        .ok()
+        .filter(|user| user.is_eligible_for_trading())
+        .ok_or_else(|| {
+            LedgerError::generic(
+                ERROR_INELIGIBLE_ACCOUNT,
+                "Recipient must be a KYC-verified user",
+            )
+        })?;
+
+    let now = now_nanos / 1_000_000_000;
+    let holdings = owned_holdings(ustbill_id, &from.owner);
+    let balance: u64 = holdings
+        .iter()
+        .map(|holding| orderbook::available_tokens(holding, now))
+        .sum();
+    let amount = match u64::try_from(&amount.0) {
+        Ok(amount) if amount <= balance => amount,
+        _ => return Err(LedgerError::InsufficientFunds { balance }),
+    };
+
+    let mut sender = batch.user(&from.owner)?;
+    let cost_basis = if from.owner == to.owner {
+        0
+    } else {
+        reassign_tokens(batch, holdings, &to.owner, amount, now)?
+    };
+
+    let description = format!(
+        "Transfer of {} tokens of UST Bill {} from {} to {}",
+        amount, ustbill.id, from.owner, to.owner
+    );
+    let sent = Transaction {
+        id: generate_id(),
+        user_principal: from.owner,
+        transaction_type: TransactionType::Transfer,
+        amount: cost_basis,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: None,
+        timestamp: now,
+        status: TransactionStatus::Completed,
+        fees: 0,
+        description: description.clone(),
+    };
+
+    if from.owner != to.owner {
+        sender.total_invested = sender.total_invested.saturating_sub(cost_basis);
+        sender.updated_at = now;
+        recipient.total_invested += cost_basis;
+        recipient.updated_at = now;
+
+        let received = Transaction {
+            id: generate_id(),
+            user_principal: to.owner,
+            description,
+            ..sent.clone()
+        };
+
+        let (sender_principal, recipient_principal) = (from.owner, to.owner);
+        let reference = sent.id.clone();
+        batch.update_user(sender);
+        batch.update_user(recipient);
+        batch.insert_transaction(received);
+        batch.then(move || {
+            accounting::holdings_transfer(
+                sender_principal,
+                recipient_principal,
+                cost_basis,
+                &reference,
+                now,
+            )
+        });
+    }
+    batch.insert_transaction(sent);
+
+    Ok(amount)
+}
+
+/// Stages handing `amount` tokens to `to`, oldest lots first, returning their cost basis.
+/// Whole lots change owner and keep their yield history; partial lots are split.
+fn reassign_tokens(
+    batch: &mut WriteBatch,
+    holdings: Vec<TokenHolding>,
+    to: &Principal,
+    amount: u64,
+    now: u64,
+) -> crate::errors::Result<u64> {
+    let mut remaining = amount;
+    let mut cost_basis = 0;
+
+    for mut holding in holdings {
+        if remaining == 0 {
+            break;
+        }
+        let moved = orderbook::available_tokens(&holding, now).min(remaining);
+        if moved == 0 {
+            continue;
+        }
+        remaining -= moved;
+        let moved_cost = crate::pro_rata(holding.cost_basis, moved, holding.tokens_owned)?;
+        cost_basis = Money::from_cents(cost_basis)
+            .checked_add(Money::from_cents(moved_cost))?
+            .cents();
+
+        if moved == holding.tokens_owned {
+            holding.user_principal = *to;
+            batch.update_holding(holding);
+            continue;
+        }
+
+        let moved_value = crate::pro_rata(holding.current_value, moved, holding.tokens_owned)?;
+        let moved_yield = crate::pro_rata(holding.projected_yield, moved, holding.tokens_owned)?;
+        let moved_yield_paid = crate::pro_rata(holding.yield_paid, moved, holding.tokens_owned)?;
+        holding.tokens_owned -= moved;
+        holding.cost_basis -= moved_cost;
+        holding.current_value -= moved_value;
+        holding.projected_yield -= moved_yield;
+        holding.yield_paid -= moved_yield_paid;
+
+        let split = TokenHolding {
+            id: generate_id(),
+            user_principal: *to,
+            tokens_owned: moved,
+            cost_basis: moved_cost,
+            current_value: moved_value,
+            projected_yield: moved_yield,
+            yield_paid: moved_yield_paid,
+            ..holding.clone()
+        };
+
+        // The split-off lot accrues yield from the same point as its parent
+        if let Some(checkpoint) = YieldDistributionStorage::accrued_until(&holding.id) {
+            let split_id = split.id.clone();
+            batch.then(move || YieldDistributionStorage::set_accrued_until(&split_id, checkpoint));
+        }
+
+        batch.update_holding(holding);
+        batch.insert_holding(split);
+    }
+
+    Ok(cost_basis)
+}
+
+/// Active holdings of a bill owned by `owner`, oldest first
+fn owned_holdings(ustbill_id: &str, owner: &Principal) -> Vec<TokenHolding> {
+    let mut holdings: Vec<TokenHolding> = HoldingStorage::get_by_user(owner)
+        .into_iter()
+        .filter(|holding| holding.ustbill_id == ustbill_id && holding.is_active())
+        .collect();
+    holdings.sort_by_key(|holding| (holding.purchase_date, id_sequence(&holding.id)));
+    holdings
+}
+
+fn transferable_bill(ustbill_id: &str, now_nanos: u64) -> Result<USTBill, LedgerError> {
+    let ustbill = USTBillStorage::get(ustbill_id).map_err(|_| {
+        LedgerError::generic(ERROR_UNKNOWN_TOKEN, format!("Unknown token {}", ustbill_id))
+    })?;
+
+    let tradable = matches!(
+        ustbill.status,
+        USTBillStatus::Active | USTBillStatus::SoldOut
+    ) && ustbill.maturity_date > now_nanos / 1_000_000_000;
+    if !tradable {
+        return Err(LedgerError::generic(
+            ERROR_NOT_TRANSFERABLE,
+            format!("UST Bill {} is no longer transferable", ustbill_id),
+        ));
+    }
+
+    Ok(ustbill)
+}
+
+/// Checks shared by every ledger update: fee, deduplication window and memo
+fn check_call(
+    fee: &Option<Nat>,
+    created_at_time: Option<u64>,
+    memo: &Option<Vec<u8>>,
+    now_nanos: u64,
+) -> Result<(), LedgerError> {
+    if fee.as_ref().is_some_and(|fee| *fee != TRANSFER_FEE) {
+        return Err(LedgerError::BadFee);
+    }
+
+    if let Some(created_at) = created_at_time {
+        if created_at.saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now_nanos {
+            return Err(LedgerError::TooOld);
+        }
+        if created_at > now_nanos.saturating_add(PERMITTED_DRIFT_NANOS) {
+            return Err(LedgerError::CreatedInFuture {
+                ledger_time: now_nanos,
+            });
+        }
+    }
+
+    if memo
+        .as_ref()
+        .is_some_and(|memo| memo.len() > MAX_MEMO_BYTES)
+    {
+        return Err(LedgerError::generic(
+            ERROR_INVALID_ARGUMENT,
+            format!("Memo exceeds {} bytes", MAX_MEMO_BYTES),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Holdings carry no subaccounts, so only the default one holds tokens
+fn check_default_subaccount(account: &Account) -> Result<(), LedgerError> {
+    if !is_default_subaccount(&account.subaccount) {
+        return Err(LedgerError::generic(
+            ERROR_UNSUPPORTED_SUBACCOUNT,
+            "Only the default subaccount is supported",
+        ));
+    }
+    Ok(())
+}
+
+fn is_default_subaccount(subaccount: &Option<Vec<u8>>) -> bool {
+    subaccount
+        .as_ref()
+        .is_none_or(|bytes| bytes.iter().all(|byte| *byte == 0))
+}
+
+/// Identifies a call for deduplication: only calls that set `created_at_time` are
+/// deduplicated, and only against the same caller making the same call
+fn call_key<A: CandidType>(
+    method: &str,
+    ustbill_id: &str,
+    caller: Principal,
+    args: &A,
+    created_at_time: Option<u64>,
+) -> Result<Option<String>, LedgerError> {
+    let Some(created_at) = created_at_time else {
+        return Ok(None);
+    };
+    let call = candid::encode_args((method, ustbill_id, caller, args))
+        .map_err(|e| LedgerError::generic(ERROR_INTERNAL, e.to_string()))?;
+    Ok(Some(format!(
+        "{:020}:{}",
+        created_at,
+        hex::encode(Sha256::digest(&call))
+    )))
+}
+
+/// Answers a repeated call with the block of the original, within the window
+fn check_duplicate(call: &Option<String>) -> Result<(), LedgerError> {
+    match call.as_deref().and_then(LedgerCallStorage::get) {
+        Some(duplicate_of) => Err(LedgerError::Duplicate { duplicate_of }),
+        None => Ok(()),
+    }
+}
+
+/// Commits the staged writes together with the block recording them, returning the
+/// block's index. The call is remembered for deduplication until it is too old to replay.
+fn commit_with_block(
+    mut batch: WriteBatch,
+    call: Option<String>,
+    now_nanos: u64,
+    append: impl FnOnce() -> crate::errors::Result<u64> + 'static,
+) -> Result<Nat, LedgerError> {
+    let index = Rc::new(Cell::new(0));
+    let appended = Rc::clone(&index);
+    batch.then(move || {
+        let block_index = append()?;
+        appended.set(block_index);
+        if let Some(call) = call {
+            LedgerCallStorage::insert(call, block_index);
+        }
+        LedgerCallStorage::prune(now_nanos.saturating_sub(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS));
+        Ok(())
+    });
+    batch.commit()?;
+    Ok(Nat::from(index.get()))
+}
+
+/// Ids come from a counter, so they order holdings created at the same time
+fn id_sequence(id: &str) -> u64 {
+    id.parse().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Rate;
+
+    const NOW_NANOS: u64 = 1_700_000_000 * 1_000_000_000;
+
+    fn principal(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
+    }
+
+    fn account(owner: Principal) -> Account {
+        Account {
+            owner,
+            subaccount: None,
+        }
+    }
+
+    fn register(owner: Principal, kyc_status: KYCStatus) {
+        UserStorage::insert(User {
+            principal: owner,
+            email: "investor@example.com".to_string(),
+            kyc_status,
+            balances: vec![],
+            total_invested: 9_500,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        })
+        .unwrap();
+    }
+
+    /// A bill with one 100-token holding owned by `owner`
+    fn setup(owner: Principal) -> (USTBill, TokenHolding) {
+        let ustbill = USTBill {
+            id: generate_id(),
+            cusip: "912796ZV4".to_string(),
+            face_value: 100_000,
+            purchase_price: 95_000,
+            maturity_date: 1_800_000_000,
+            annual_yield: Rate::from_nanos(52_600_000),
+            total_tokens: 1_000,
+            tokens_sold: 100,
+            status: USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        let holding = TokenHolding {
+            id: generate_id(),
+            user_principal: owner,
+            ustbill_id: ustbill.id.clone(),
+            tokens_owned: 100,
+            purchase_price_per_token: 95,
+            cost_basis: 9_500,
+            purchase_date: 0,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 9_500,
+            projected_yield: 500,
+            yield_paid: 0,
+        };
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        HoldingStorage::insert(holding.clone()).unwrap();
+        (ustbill, holding)
+    }
+
+    fn transfer_arg(to: Principal, amount: u64) -> TransferArg {
+        TransferArg {
+            from_subaccount: None,
+            to: account(to),
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    #[test]
+    fn test_transfer_splits_then_reassigns_holdings() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let bob = principal("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe");
+        register(alice, KYCStatus::Verified);
+        register(bob, KYCStatus::Verified);
+        let (ustbill, holding) = setup(alice);
+
+        transfer(&ustbill.id, alice, transfer_arg(bob, 40), NOW_NANOS).unwrap();
+        assert_eq!(balance_of(&ustbill.id, &account(alice), NOW_NANOS), 60);
+        assert_eq!(balance_of(&ustbill.id, &account(bob), NOW_NANOS), 40);
+        assert_eq!(
+            HoldingStorage::get(&holding.id).unwrap().current_value,
+            5_700
+        );
+        assert_eq!(
+            UserStorage::get(&bob).unwrap().total_invested,
+            9_500 + 3_800
+        );
+
+        // The remaining lot moves whole
+        transfer(&ustbill.id, alice, transfer_arg(bob, 60), NOW_NANOS).unwrap();
+        assert_eq!(
+            HoldingStorage::get(&holding.id).unwrap().user_principal,
+            bob
+        );
+        assert_eq!(balance_of(&ustbill.id, &account(bob), NOW_NANOS), 100);
+
+        assert_eq!(
+            transfer(&ustbill.id, alice, transfer_arg(bob, 1), NOW_NANOS),
+            Err(TransferError::InsufficientFunds {
+                balance: Nat::from(0u64)
+            })
+        );
+    }
+
+    #[test]
+    fn test_transfer_rejects_bad_calls() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let bob = principal("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe");
+        register(alice, KYCStatus::Verified);
+        register(bob, KYCStatus::Pending);
+        let (ustbill, _) = setup(alice);
+
+        let unverified = transfer(&ustbill.id, alice, transfer_arg(bob, 1), NOW_NANOS);
+        assert!(matches!(
+            unverified,
+            Err(TransferError::GenericError { .. })
+        ));
+
+        let bad_fee = TransferArg {
+            fee: Some(Nat::from(10u64)),
+            ..transfer_arg(bob, 1)
+        };
+        assert!(matches!(
+            transfer(&ustbill.id, alice, bad_fee, NOW_NANOS),
+            Err(TransferError::BadFee { .. })
+        ));
+
+        let too_old = TransferArg {
+            created_at_time: Some(NOW_NANOS - 2 * TX_WINDOW_NANOS),
+            ..transfer_arg(bob, 1)
+        };
+        assert_eq!(
+            transfer(&ustbill.id, alice, too_old, NOW_NANOS),
+            Err(TransferError::TooOld)
+        );
+        assert_eq!(balance_of(&ustbill.id, &account(alice), NOW_NANOS), 100);
+    }
+
+    #[test]
+    fn test_repeated_call_is_a_duplicate() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let bob = principal("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe");
+        register(alice, KYCStatus::Verified);
+        register(bob, KYCStatus::Verified);
+        let (ustbill, _) = setup(alice);
+
+        let arg = TransferArg {
+            created_at_time: Some(NOW_NANOS),
+            memo: Some(b"invoice 7".to_vec()),
+            ..transfer_arg(bob, 10)
+        };
+        let index = transfer(&ustbill.id, alice, arg.clone(), NOW_NANOS).unwrap();
+        assert_eq!(
+            transfer(&ustbill.id, alice, arg.clone(), NOW_NANOS + 60_000_000_000),
+            Err(TransferError::Duplicate {
+                duplicate_of: index
+            })
+        );
+        assert_eq!(balance_of(&ustbill.id, &account(bob), NOW_NANOS), 10);
+
+        // A different memo, or no created_at_time, is a new transfer
+        let other = TransferArg {
+            memo: Some(b"invoice 8".to_vec()),
+            ..arg.clone()
+        };
+        transfer(&ustbill.id, alice, other, NOW_NANOS).unwrap();
+        transfer(&ustbill.id, alice, transfer_arg(bob, 10), NOW_NANOS).unwrap();
+        transfer(&ustbill.id, alice, transfer_arg(bob, 10), NOW_NANOS).unwrap();
+        assert_eq!(balance_of(&ustbill.id, &account(bob), NOW_NANOS), 40);
+
+        // Calls older than the window are forgotten once they can no longer be replayed
+        let later = NOW_NANOS + TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS + 1;
+        transfer(&ustbill.id, alice, transfer_arg(bob, 10), later).unwrap();
+        assert_eq!(LedgerCallStorage::count(), 0);
+        assert_eq!(
+            transfer(&ustbill.id, alice, arg, later),
+            Err(TransferError::TooOld)
+        );
+    }
+
+    #[test]
+    fn test_approve_and_transfer_from() {
+        let alice = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        let bob = principal("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe");
+        register(alice, KYCStatus::Verified);
+        register(bob, KYCStatus::Verified);
+        let (ustbill, _) = setup(alice);
+
+        let approve_args = ApproveArgs {
+            from_subaccount: None,
+            spender: account(bob),
+            amount: Nat::from(30u64),
+            expected_allowance: Some(Nat::from(0u64)),
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        approve(&ustbill.id, alice, approve_args, NOW_NANOS).unwrap();
+
+        let pull = |amount: u64| TransferFromArgs {
+            spender_subaccount: None,
+            from: account(alice),
+            to: account(bob),
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        transfer_from(&ustbill.id, bob, pull(20), NOW_NANOS).unwrap();
+        assert_eq!(balance_of(&ustbill.id, &account(bob), NOW_NANOS), 20);
+
+        assert_eq!(
+            transfer_from(&ustbill.id, bob, pull(20), NOW_NANOS),
+            Err(TransferFromError::InsufficientAllowance {
+                allowance: Nat::from(10u64)
+            })
+        );
+
+        let remaining = allowance(
+            &ustbill.id,
+            &AllowanceArgs {
+                account: account(alice),
+                spender: account(bob),
+            },
+            NOW_NANOS,
+        );
+        assert_eq!(remaining.allowance, Nat::from(10u64));
+    }
+}
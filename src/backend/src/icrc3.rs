@@ -0,0 +1,485 @@
+use candid::{Nat, Principal};
+use sha2::{Digest, Sha256};
+
+use crate::errors::Result;
+use crate::storage::BlockStorage;
+use crate::types::*;
+
+/// Maximum number of blocks returned by one `icrc3_get_blocks` call
+const MAX_BLOCKS_PER_RESPONSE: u64 = 100;
+
+/// Fee blocks are specific to this ledger, so they live in their own namespace
+const FEE_BLOCK_TYPE: &str = "ustbills_fee";
+
+/// A ledger event recorded as a block. Token amounts are whole bill tokens; fees are in cents.
+pub enum BlockOp<'a> {
+    /// Tokens bought from the platform's unsold supply
+    Mint {
+        ustbill_id: &'a str,
+        to: Principal,
+        amount: u64,
+    },
+    /// Tokens returned to the platform, by a sale to the pool or redemption at maturity
+    Burn {
+        ustbill_id: &'a str,
+        from: Principal,
+        amount: u64,
+    },
+    Transfer {
+        ustbill_id: &'a str,
+        from: Principal,
+        to: Principal,
+        spender: Option<Principal>,
+        amount: u64,
+        memo: Option<Vec<u8>>,
+        created_at_time: Option<u64>,
+    },
+    Approve {
+        ustbill_id: &'a str,
+        from: Principal,
+        spender: Principal,
+        amount: Nat,
+        expires_at: Option<u64>,
+    },
+    Fee {
+        ustbill_id: &'a str,
+        from: Principal,
+        amount: u64,
+    },
+}
+
+impl<'a> BlockOp<'a> {
+    fn ustbill_id(&self) -> &'a str {
+        match self {
+            BlockOp::Mint { ustbill_id, .. }
+            | BlockOp::Burn { ustbill_id, .. }
+            | BlockOp::Transfer { ustbill_id, .. }
+            | BlockOp::Approve { ustbill_id, .. }
+            | BlockOp::Fee { ustbill_id, .. } => ustbill_id,
+        }
+    }
+
+    /// Block type and `tx` fields, following the ICRC-1/ICRC-2 block schemas
+    fn encode(self) -> (&'static str, Vec<(String, ICRC3Value)>) {
+        let mut tx = Vec::new();
+        let btype = match self {
+            BlockOp::Mint {
+                ustbill_id,
+                to,
+                amount,
+            } => {
+                tx.push(field("token", text(ustbill_id)));
+                tx.push(field("to", account(to)));
+                tx.push(field("amt", nat(amount)));
+                "1mint"
+            }
+            BlockOp::Burn {
+                ustbill_id,
+                from,
+                amount,
+            } => {
+                tx.push(field("token", text(ustbill_id)));
+                tx.push(field("from", account(from)));
+                tx.push(field("amt", nat(amount)));
+                "1burn"
+            }
+            BlockOp::Transfer {
+                ustbill_id,
+                from,
+                to,
+                spender,
+                amount,
+                memo,
+                created_at_time,
+            } => {
+                tx.push(field("token", text(ustbill_id)));
+                tx.push(field("from", account(from)));
+                tx.push(field("to", account(to)));
+                tx.push(field("amt", nat(amount)));
+                if let Some(memo) = memo {
+                    tx.push(field("memo", ICRC3Value::Blob(memo)));
+                }
+                if let Some(created_at_time) = created_at_time {
+                    tx.push(field("ts", nat(created_at_time)));
+                }
+                match spender {
+                    Some(spender) => {
+                        tx.push(field("spender", account(spender)));
+                        "2xfer"
+                    }
+                    None => "1xfer",
+                }
+            }
+            BlockOp::Approve {
+                ustbill_id,
+                from,
+                spender,
+                amount,
+                expires_at,
+            } => {
+                tx.push(field("token", text(ustbill_id)));
+                tx.push(field("from", account(from)));
+                tx.push(field("spender", account(spender)));
+                tx.push(field("amt", ICRC3Value::Nat(amount)));
+                if let Some(expires_at) = expires_at {
+                    tx.push(field("expires_at", nat(expires_at)));
+                }
+                "2approve"
+            }
+            BlockOp::Fee {
+                ustbill_id,
+                from,
+                amount,
+            } => {
+                tx.push(field("token", text(ustbill_id)));
+                tx.push(field("from", account(from)));
+                tx.push(field("amt", nat(amount)));
+                FEE_BLOCK_TYPE
+            }
+        };
+        (btype, tx)
+    }
+}
+
+/// Appends a block linked to the current tip and re-certifies the tip, returning the block index
+pub fn append(op: BlockOp, ts_nanos: u64) -> Result<u64> {
+    let (btype, tx) = op.encode();
+
+    let mut block = vec![
+        field("btype", text(btype)),
+        field("ts", nat(ts_nanos)),
+        field("tx", ICRC3Value::Map(tx)),
+    ];
+    if let Some((_, parent_hash)) = tip() {
+        block.push(field("phash", ICRC3Value::Blob(parent_hash.to_vec())));
+    }
+
+    let index = BlockStorage::append(ICRC3Value::Map(block))?;
+    certify_tip();
+    Ok(index)
+}
+
+/// Appends a trade followed by the platform fee charged to `payer`, if any
+pub fn append_trade(trade: BlockOp, payer: Principal, fees: u64, ts_nanos: u64) -> Result<u64> {
+    let ustbill_id = trade.ustbill_id();
+    let index = append(trade, ts_nanos)?;
+
+    if fees > 0 {
+        let fee = BlockOp::Fee {
+            ustbill_id,
+            from: payer,
+            amount: fees,
+        };
+        append(fee, ts_nanos)?;
+    }
+    Ok(index)
+}
+
+/// Index and hash of the last block
+pub fn tip() -> Option<(u64, [u8; 32])> {
+    BlockStorage::last().map(|(index, block)| (index, hash_value(&block)))
+}
+
+pub fn get_blocks(requests: Vec<GetBlocksRequest>) -> GetBlocksResult {
+    let mut budget = MAX_BLOCKS_PER_RESPONSE;
+    let mut blocks = Vec::new();
+
+    for request in requests {
+        let (Ok(start), Ok(length)) = (
+            u64::try_from(&request.start.0),
+            u64::try_from(&request.length.0),
+        ) else {
+            continue;
+        };
+        let length = length.min(budget);
+        budget -= length;
+
+        blocks.extend(
+            BlockStorage::range(start, length)
+                .into_iter()
+                .map(|(id, block)| BlockWithId {
+                    id: Nat::from(id),
+                    block,
+                }),
+        );
+    }
+
+    GetBlocksResult {
+        log_length: Nat::from(BlockStorage::count()),
+        blocks,
+        archived_blocks: Vec::new(),
+    }
+}
+
+pub fn supported_block_types() -> Vec<SupportedBlockType> {
+    let standard = |block_type: &str, standard: &str| SupportedBlockType {
+        block_type: block_type.to_string(),
+        url: format!(
+            "https://github.com/dfinity/ICRC-1/tree/main/standards/{}",
+            standard
+        ),
+    };
+
+    vec![
+        standard("1mint", "ICRC-1"),
+        standard("1burn", "ICRC-1"),
+        standard("1xfer", "ICRC-1"),
+        standard("2xfer", "ICRC-2"),
+        standard("2approve", "ICRC-2"),
+        SupportedBlockType {
+            block_type: FEE_BLOCK_TYPE.to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+    ]
+}
+
+/// Certificate over the tip; only available in query calls and once a block exists
+pub fn tip_certificate() -> Option<DataCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let (index, hash) = tip()?;
+
+    let mut hash_tree = Vec::new();
+    tip_tree(index, &hash).encode_cbor(&mut hash_tree);
+
+    Some(DataCertificate {
+        certificate,
+        hash_tree,
+    })
+}
+
+/// Publishes the tip's hash tree root as the canister's certified data
+pub fn certify_tip() {
+    if let Some((index, hash)) = tip() {
+        set_certified_data(&tip_tree(index, &hash).digest());
+    }
+}
+
+// Certified data only exists inside a canister; unit tests run natively
+#[cfg(target_arch = "wasm32")]
+fn set_certified_data(digest: &[u8; 32]) {
+    ic_cdk::api::certified_data_set(digest);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn set_certified_data(_digest: &[u8; 32]) {}
+
+/// Representation-independent hash of a value, as defined by ICRC-3
+pub fn hash_value(value: &ICRC3Value) -> [u8; 32] {
+    match value {
+        ICRC3Value::Blob(bytes) => sha256(&[bytes]),
+        ICRC3Value::Text(text) => sha256(&[text.as_bytes()]),
+        ICRC3Value::Nat(nat) => {
+            let mut leb128 = Vec::new();
+            nat.encode(&mut leb128)
+                .expect("writing to a Vec can't fail");
+            sha256(&[&leb128])
+        }
+        ICRC3Value::Int(int) => {
+            let mut sleb128 = Vec::new();
+            int.encode(&mut sleb128)
+                .expect("writing to a Vec can't fail");
+            sha256(&[&sleb128])
+        }
+        ICRC3Value::Array(items) => {
+            let hashes: Vec<[u8; 32]> = items.iter().map(hash_value).collect();
+            let parts: Vec<&[u8]> = hashes.iter().map(|hash| hash.as_slice()).collect();
+            sha256(&parts)
+        }
+        ICRC3Value::Map(entries) => {
+            let mut pairs: Vec<Vec<u8>> = entries
+                .iter()
+                .map(|(key, value)| [sha256(&[key.as_bytes()]), hash_value(value)].concat())
+                .collect();
+            pairs.sort();
+            let parts: Vec<&[u8]> = pairs.iter().map(|pair| pair.as_slice()).collect();
+            sha256(&parts)
+        }
+    }
+}
+
+/// The subset of the IC hash tree needed to certify the tip
+enum HashTree {
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(&'static [u8], Box<HashTree>),
+    Leaf(Vec<u8>),
+}
+
+impl HashTree {
+    fn digest(&self) -> [u8; 32] {
+        match self {
+            HashTree::Fork(left, right) => sha256(&[
+                &domain_separator("ic-hashtree-fork"),
+                &left.digest(),
+                &right.digest(),
+            ]),
+            HashTree::Labeled(label, subtree) => sha256(&[
+                &domain_separator("ic-hashtree-labeled"),
+                label,
+                &subtree.digest(),
+            ]),
+            HashTree::Leaf(value) => sha256(&[&domain_separator("ic-hashtree-leaf"), value]),
+        }
+    }
+
+    /// CBOR encoding as expected by certificate verifiers, self-describe tag included
+    fn encode_cbor(&self, out: &mut Vec<u8>) {
+        if out.is_empty() {
+            out.extend_from_slice(&[0xd9, 0xd9, 0xf7]);
+        }
+        match self {
+            HashTree::Fork(left, right) => {
+                out.extend_from_slice(&[0x83, 0x01]);
+                left.encode_cbor(out);
+                right.encode_cbor(out);
+            }
+            HashTree::Labeled(label, subtree) => {
+                out.extend_from_slice(&[0x83, 0x02]);
+                cbor_bytes(label, out);
+                subtree.encode_cbor(out);
+            }
+            HashTree::Leaf(value) => {
+                out.extend_from_slice(&[0x82, 0x03]);
+                cbor_bytes(value, out);
+            }
+        }
+    }
+}
+
+/// `last_block_hash` sorts before `last_block_index`, as the hash tree requires
+fn tip_tree(index: u64, hash: &[u8; 32]) -> HashTree {
+    let mut leb128 = Vec::new();
+    Nat::from(index)
+        .encode(&mut leb128)
+        .expect("writing to a Vec can't fail");
+
+    HashTree::Fork(
+        Box::new(HashTree::Labeled(
+            b"last_block_hash",
+            Box::new(HashTree::Leaf(hash.to_vec())),
+        )),
+        Box::new(HashTree::Labeled(
+            b"last_block_index",
+            Box::new(HashTree::Leaf(leb128)),
+        )),
+    )
+}
+
+fn cbor_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    let len = bytes.len();
+    match len {
+        0..=23 => out.push(0x40 | len as u8),
+        24..=0xff => out.extend_from_slice(&[0x58, len as u8]),
+        _ => {
+            out.push(0x59);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(bytes);
+}
+
+fn domain_separator(name: &str) -> Vec<u8> {
+    let mut separator = vec![name.len() as u8];
+    separator.extend_from_slice(name.as_bytes());
+    separator
+}
+
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn field(name: &str, value: ICRC3Value) -> (String, ICRC3Value) {
+    (name.to_string(), value)
+}
+
+fn text(value: &str) -> ICRC3Value {
+    ICRC3Value::Text(value.to_string())
+}
+
+fn nat(value: u64) -> ICRC3Value {
+    ICRC3Value::Nat(Nat::from(value))
+}
+
+fn account(owner: Principal) -> ICRC3Value {
+    ICRC3Value::Array(vec![ICRC3Value::Blob(owner.as_slice().to_vec())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Int;
+
+    #[test]
+    fn test_hash_value_vectors() {
+        // Test vectors from the ICRC-3 specification
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Nat(Nat::from(42u64)))),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+        );
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Int(Int::from(-42)))),
+            "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc"
+        );
+        assert_eq!(
+            hex::encode(hash_value(&text("Hello, World!"))),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(
+            hex::encode(hash_value(&ICRC3Value::Array(vec![
+                nat(3),
+                text("foo"),
+                ICRC3Value::Blob(vec![5, 6]),
+            ]))),
+            "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6"
+        );
+    }
+
+    #[test]
+    fn test_blocks_are_hash_chained() {
+        let alice = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+
+        let mint = BlockOp::Mint {
+            ustbill_id: "1",
+            to: alice,
+            amount: 10,
+        };
+        assert_eq!(append(mint, 1_000).unwrap(), 0);
+        let (_, genesis_hash) = tip().unwrap();
+
+        let fee = BlockOp::Fee {
+            ustbill_id: "1",
+            from: alice,
+            amount: 5,
+        };
+        assert_eq!(append(fee, 2_000).unwrap(), 1);
+
+        let ICRC3Value::Map(block) = BlockStorage::get(1).unwrap() else {
+            panic!("blocks are maps");
+        };
+        assert!(block.contains(&field("phash", ICRC3Value::Blob(genesis_hash.to_vec()))));
+        assert!(block.contains(&field("btype", text(FEE_BLOCK_TYPE))));
+
+        let result = get_blocks(vec![GetBlocksRequest {
+            start: Nat::from(1u64),
+            length: Nat::from(10u64),
+        }]);
+        assert_eq!(result.log_length, Nat::from(2u64));
+        assert_eq!(result.blocks.len(), 1);
+        assert_eq!(result.blocks[0].id, Nat::from(1u64));
+    }
+
+    #[test]
+    fn test_tip_tree() {
+        let mut cbor = Vec::new();
+        tip_tree(1, &[0xab; 32]).encode_cbor(&mut cbor);
+
+        assert_eq!(&cbor[..5], &[0xd9, 0xd9, 0xf7, 0x83, 0x01]);
+        assert_eq!(
+            hex::encode(tip_tree(1, &[0xab; 32]).digest()),
+            "40a154e39e4a49d338d1fb9f863390a256295e2f8f6f052faa821ee2f0a16a8d"
+        );
+    }
+}
@@ -2,26 +2,47 @@
 #![warn(unused_variables)]
 
 // Module declarations
+mod accounting;
+mod calendar;
 mod errors;
 mod guard;
 mod handlers;
+mod icrc;
+mod icrc3;
+mod market;
+mod maturity;
+mod money;
+mod oracle;
+mod orderbook;
+mod pricing;
+mod quotes;
+mod settlement;
 mod storage;
 mod store;
+mod timers;
+mod treasury;
 mod types;
 mod utils;
+mod yield_distribution;
 
 // Re-export types for easier access
 pub use errors::*;
 pub use storage::*;
 pub use types::*;
 
-use crate::utils::get_current_timestamp;
-use candid::Principal;
-use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use candid::{Nat, Principal};
+use ic_cdk::management_canister::{HttpRequestResult, TransformArgs};
 use ic_cdk::{query, update};
 use std::collections::HashMap;
-use crate::storage::VerifiedPurchasesLedgerStorage;
-pub use storage::*;
+
+use calendar::DayCount;
+use money::{Money, Rate, Rounding, DAYS_PER_YEAR};
+
+/// How long Treasury rate history is kept
+const RATE_HISTORY_RETENTION_DAYS: u64 = 2 * 365;
+
+/// Most journal entries returned per `get_journal` call
+const MAX_JOURNAL_PAGE: u64 = 500;
 
 // ╔══════════════════════════════════════════════════════════════════════════════╗
 // ║                  VERIFIED BROKER PURCHASE FUNCTIONS                        ║
@@ -34,7 +55,7 @@ pub async fn admin_add_broker_purchase_record(
     broker_txn_id: String,
     ustbill_type: String,
 ) -> Result<()> {
-    guard::assert_admin()?;
+    guard::assert_permission(Permission::ManageBills)?;
 
     let purchase = VerifiedBrokerPurchase {
         amount,
@@ -61,7 +82,7 @@ pub fn get_all_verified_broker_purchases() -> Vec<VerifiedBrokerPurchase> {
 #[update]
 pub async fn create_ustbill(ustbill_data: USTBillCreateRequest) -> Result<USTBill> {
     // Validate admin access
-    guard::assert_admin()?;
+    guard::assert_permission(Permission::ManageBills)?;
 
     // Validate input data
     validate_ustbill_data(&ustbill_data)?;
@@ -74,7 +95,7 @@ pub async fn create_ustbill(ustbill_data: USTBillCreateRequest) -> Result<USTBil
         face_value: ustbill_data.face_value,
         purchase_price: ustbill_data.purchase_price,
         maturity_date: ustbill_data.maturity_date,
-        annual_yield: ustbill_data.annual_yield,
+        annual_yield: Rate::from_f64(ustbill_data.annual_yield)?,
         total_tokens: ustbill_data.total_tokens,
         tokens_sold: 0,
         status: USTBillStatus::Active,
@@ -82,6 +103,9 @@ pub async fn create_ustbill(ustbill_data: USTBillCreateRequest) -> Result<USTBil
         updated_at: current_time,
         issuer: ustbill_data.issuer,
         bill_type: ustbill_data.bill_type,
+        market_price_per_token: None,
+        market_yield: None,
+        last_priced_at: None,
     };
 
     // Store in database
@@ -156,7 +180,7 @@ pub async fn register_user(user_data: UserRegistrationRequest) -> Result<User> {
         principal,
         email: user_data.email,
         kyc_status: KYCStatus::Pending,
-        wallet_balance: 0,
+        balances: vec![],
         total_invested: 0,
         total_yield_earned: 0,
         created_at: current_time,
@@ -175,7 +199,7 @@ pub async fn register_user(user_data: UserRegistrationRequest) -> Result<User> {
 #[update]
 pub async fn update_kyc_status(principal: Principal, status: KYCStatus) -> Result<()> {
     // Validate admin access
-    guard::assert_admin()?;
+    guard::assert_permission(Permission::ManageKyc)?;
 
     let mut user = UserStorage::get(&principal)?;
     user.kyc_status = status;
@@ -192,142 +216,351 @@ pub fn get_user_profile(principal: Principal) -> Result<User> {
     UserStorage::get(&principal)
 }
 
-/// Deposits funds to user wallet
+/// Gets the account the caller deposits into, on any settlement asset's ledger, before
+/// calling `notify_deposit`
+#[query]
+pub fn get_deposit_account() -> Result<Account> {
+    let principal = ic_cdk::api::msg_caller();
+    guard::validate_principal(&principal)?;
+    Ok(settlement::deposit_account(ic_cdk::api::canister_self(), &principal))
+}
+
+/// Gets the BTC address whose deposits the ckBTC minter mints into the caller's deposit account
 #[update]
-pub async fn deposit_funds(amount: u64) -> Result<u64> {
+pub async fn get_btc_deposit_address() -> Result<String> {
     let principal = ic_cdk::api::msg_caller();
-    let mut user = UserStorage::get(&principal)?;
+    guard::validate_principal(&principal)?;
+    settlement::btc_deposit_address(ic_cdk::api::canister_self(), &principal).await
+}
 
-    // Validate amount
-    if amount == 0 {
-        return Err(BitcoinUSTBillsError::InvalidAmount);
-    }
+/// Credits tokens sent to the caller's deposit account on the asset's ledger to their wallet
+#[update]
+pub async fn notify_deposit(asset: String) -> Result<DepositReceipt> {
+    let principal = ic_cdk::api::msg_caller();
+    let asset = settlement::asset(&asset)?;
+    settlement::notify_deposit(
+        &settlement::IcrcLedger::new(&asset),
+        ic_cdk::api::canister_self(),
+        principal,
+        &asset,
+        get_current_timestamp(),
+    )
+    .await
+}
 
-    // Update user balance
-    user.wallet_balance += amount;
-    user.updated_at = get_current_timestamp();
+/// Requests a withdrawal of `amount` ledger units of an asset from the caller's wallet, to
+/// their own account by default. The amount is locked until it is paid out, which happens
+/// at once below the auto-approve limit and after Treasurer approval above it.
+#[update]
+pub async fn withdraw_funds(
+    asset: String,
+    amount: u64,
+    to: Option<Account>,
+) -> Result<Withdrawal> {
+    let principal = ic_cdk::api::msg_caller();
+    let to = to.unwrap_or(Account {
+        owner: principal,
+        subaccount: None,
+    });
+    let asset = settlement::asset(&asset)?;
+    settlement::request_withdrawal(
+        &settlement::IcrcLedger::new(&asset),
+        principal,
+        &asset,
+        amount,
+        to,
+        get_current_timestamp(),
+    )
+    .await
+}
+
+/// Cancels one of the caller's withdrawals that is still pending approval, unlocking the funds
+#[update]
+pub fn cancel_withdrawal(withdrawal_id: String) -> Result<Withdrawal> {
+    settlement::cancel_withdrawal(
+        ic_cdk::api::msg_caller(),
+        &withdrawal_id,
+        get_current_timestamp(),
+    )
+}
 
-    UserStorage::update(user.clone())?;
+/// Gets the caller's withdrawals
+#[query]
+pub fn get_my_withdrawals() -> Vec<Withdrawal> {
+    WithdrawalStorage::get_by_owner(&ic_cdk::api::msg_caller())
+}
 
-    // Record transaction
-    let transaction = Transaction {
-        id: generate_id(),
-        user_principal: principal,
-        transaction_type: TransactionType::Deposit,
-        amount,
-        ustbill_id: None,
-        holding_id: None,
-        timestamp: get_current_timestamp(),
-        status: TransactionStatus::Completed,
-        fees: 0,
-        description: "Wallet deposit".to_string(),
-    };
+/// Gets the withdrawals waiting for approval (Treasurer)
+#[query]
+pub fn get_pending_withdrawals() -> Result<Vec<Withdrawal>> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    Ok(WithdrawalStorage::get_by_status(&WithdrawalStatus::PendingApproval))
+}
 
-    TransactionStorage::insert(transaction)?;
+/// Approves and pays out a pending withdrawal requested by someone else (Treasurer)
+#[update]
+pub async fn approve_withdrawal(withdrawal_id: String) -> Result<Withdrawal> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    let asset = settlement::asset(&WithdrawalStorage::get(&withdrawal_id)?.asset)?;
+    settlement::approve_withdrawal(
+        &settlement::IcrcLedger::new(&asset),
+        &withdrawal_id,
+        ic_cdk::api::msg_caller(),
+        get_current_timestamp(),
+    )
+    .await
+}
 
-    Ok(user.wallet_balance)
+/// Rejects a pending withdrawal, refunding the wallet (Treasurer)
+#[update]
+pub fn reject_withdrawal(withdrawal_id: String, reason: String) -> Result<Withdrawal> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    settlement::reject_withdrawal(
+        &withdrawal_id,
+        ic_cdk::api::msg_caller(),
+        reason,
+        get_current_timestamp(),
+    )
 }
 
-/// Withdraws funds from user wallet
+// ╔══════════════════════════════════════════════════════════════════════════════╗
+// ║                  TRADING CANISTER FUNCTIONS                        ║
+// ╚══════════════════════════════════════════════════════════════════════════════╝
+
+/// Buys US Treasury Bill tokens, paying in the cash asset unless another asset is named
 #[update]
-pub async fn withdraw_funds(amount: u64) -> Result<u64> {
+pub async fn buy_ustbill_tokens(
+    ustbill_id: String,
+    token_amount: u64,
+    pay_with: Option<String>,
+) -> Result<TokenHolding> {
+    assert_trading_enabled()?;
     let principal = ic_cdk::api::msg_caller();
-    let mut user = UserStorage::get(&principal)?;
+    let ustbill = USTBillStorage::get(&ustbill_id)?;
+    let (cost, fees) = price_purchase(&ustbill, token_amount, &PlatformConfigStorage::get())?;
 
-    // Validate amount
-    if amount == 0 {
-        return Err(BitcoinUSTBillsError::InvalidAmount);
+    let (batch, holding) = stage_purchase(
+        principal,
+        ustbill,
+        token_amount,
+        cost,
+        fees,
+        pay_with,
+        get_current_timestamp(),
+    )?;
+    batch.commit()?;
+
+    Ok(holding)
+}
+
+/// Buys as many whole tokens as `amount_cents` affords, the fee within the amount or on top
+/// of it by `fee_mode`. Only the cost of the tokens bought is debited; the rest of the amount
+/// stays in the wallet.
+#[update]
+pub async fn buy_ustbill_by_amount(
+    ustbill_id: String,
+    amount_cents: u64,
+    fee_mode: FeeMode,
+) -> Result<AmountPurchase> {
+    assert_trading_enabled()?;
+    let principal = ic_cdk::api::msg_caller();
+    let config = PlatformConfigStorage::get();
+    if amount_cents < config.minimum_investment {
+        return Err(BitcoinUSTBillsError::MinimumInvestmentNotMet);
     }
 
-    if user.wallet_balance < amount {
+    let ustbill = USTBillStorage::get(&ustbill_id)?;
+    check_purchasable(&ustbill, 1)?;
+    let token_amount = pricing::affordable_tokens(
+        &ustbill,
+        amount_cents,
+        fee_mode,
+        config.platform_fee_percentage,
+    )?;
+    if token_amount == 0 {
         return Err(BitcoinUSTBillsError::InsufficientFunds);
     }
+    let (cost, fee) = price_purchase(&ustbill, token_amount, &config)?;
+    let total_cost = Money::from_cents(cost)
+        .checked_add(Money::from_cents(fee))?
+        .cents();
+    let spent = match fee_mode {
+        FeeMode::Inclusive => total_cost,
+        FeeMode::Exclusive => cost,
+    };
 
-    // Update user balance
-    user.wallet_balance -= amount;
-    user.updated_at = get_current_timestamp();
+    let (batch, holding) = stage_purchase(
+        principal,
+        ustbill,
+        token_amount,
+        cost,
+        fee,
+        None,
+        get_current_timestamp(),
+    )?;
+    batch.commit()?;
+
+    Ok(AmountPurchase {
+        holding,
+        amount: amount_cents,
+        fee_mode,
+        token_amount,
+        cost,
+        fee,
+        total_cost,
+        residue: amount_cents - spent,
+    })
+}
 
-    UserStorage::update(user.clone())?;
+/// Prices tokens for the caller and holds the price, fee included, until the quote expires
+#[update]
+pub async fn get_purchase_quote(
+    ustbill_id: String,
+    token_amount: u64,
+    pay_with: Option<String>,
+) -> Result<PurchaseQuote> {
+    assert_trading_enabled()?;
+    let principal = ic_cdk::api::msg_caller();
+    let ustbill = USTBillStorage::get(&ustbill_id)?;
 
-    // Record transaction
-    let transaction = Transaction {
-        id: generate_id(),
-        user_principal: principal,
-        transaction_type: TransactionType::Withdrawal,
-        amount,
-        ustbill_id: None,
-        holding_id: None,
-        timestamp: get_current_timestamp(),
-        status: TransactionStatus::Completed,
-        fees: 0,
-        description: "Wallet withdrawal".to_string(),
+    let key = match QuoteStorage::signing_key() {
+        Some(key) => key,
+        None => {
+            let key = ic_cdk::management_canister::raw_rand().await.map_err(|e| {
+                BitcoinUSTBillsError::SystemError(format!("raw_rand failed: {}", e))
+            })?;
+            // Another quote may have set the key while this one waited
+            QuoteStorage::signing_key().unwrap_or_else(|| {
+                QuoteStorage::set_signing_key(key.clone());
+                key
+            })
+        }
     };
 
-    TransactionStorage::insert(transaction)?;
-
-    Ok(user.wallet_balance)
+    quotes::issue(
+        &key,
+        principal,
+        &ustbill,
+        token_amount,
+        pay_with,
+        get_current_timestamp(),
+    )
 }
 
-// ╔══════════════════════════════════════════════════════════════════════════════╗
-// ║                  TRADING CANISTER FUNCTIONS                        ║
-// ╚══════════════════════════════════════════════════════════════════════════════╝
-
-/// Buys US Treasury Bill tokens
+/// Buys at a quoted price, refusing if the quote has expired or costs more than `max_total_cost`
 #[update]
-pub async fn buy_ustbill_tokens(ustbill_id: String, token_amount: u64) -> Result<TokenHolding> {
+pub async fn buy_with_quote(quote_id: String, max_total_cost: u64) -> Result<TokenHolding> {
+    assert_trading_enabled()?;
     let principal = ic_cdk::api::msg_caller();
-    let mut user = UserStorage::get(&principal)?;
+    let key = QuoteStorage::signing_key().ok_or(BitcoinUSTBillsError::QuoteNotFound)?;
+    let quote = quotes::redeemable(
+        &key,
+        &quote_id,
+        principal,
+        max_total_cost,
+        get_current_timestamp(),
+    )?;
 
-    // Validate user eligibility
-    if !user.is_eligible_for_trading() {
-        return Err(BitcoinUSTBillsError::TradingNotAllowed);
-    }
+    // The quoted price stands, but the bill and the limits are checked again
+    let ustbill = USTBillStorage::get(&quote.ustbill_id)?;
+    check_purchasable(&ustbill, quote.token_amount)?;
+    check_investment_limits(quote.cost, &PlatformConfigStorage::get())?;
 
-    // Get UST Bill
-    let mut ustbill = USTBillStorage::get(&ustbill_id)?;
+    let (mut batch, holding) = stage_purchase(
+        principal,
+        ustbill,
+        quote.token_amount,
+        quote.cost,
+        quote.fee,
+        quote.pay_with,
+        get_current_timestamp(),
+    )?;
+    batch.then(move || QuoteStorage::remove(&quote_id));
+    batch.commit()?;
+
+    Ok(holding)
+}
 
-    // Validate UST Bill availability
+/// Prices `token_amount` tokens of a bill, returning the cost and the platform fee
+pub fn price_purchase(
+    ustbill: &USTBill,
+    token_amount: u64,
+    config: &PlatformConfig,
+) -> Result<(u64, u64)> {
+    check_purchasable(ustbill, token_amount)?;
+    let cost = calculate_purchase_cost_internal(ustbill, token_amount)?;
+    check_investment_limits(cost, config)?;
+    let fees = utils::calculate_fees(cost, config.platform_fee_percentage)?;
+    Ok((cost, fees))
+}
+
+fn check_purchasable(ustbill: &USTBill, token_amount: u64) -> Result<()> {
     if !ustbill.is_available_for_purchase() {
         return Err(BitcoinUSTBillsError::USTBillSoldOut);
     }
-
     if ustbill.available_tokens() < token_amount {
         return Err(BitcoinUSTBillsError::InsufficientTokens);
     }
+    Ok(())
+}
 
-    // Calculate purchase cost
-    let cost = calculate_purchase_cost_internal(&ustbill, token_amount)?;
-    let config = PlatformConfigStorage::get();
-
-    // Validate investment limits
+fn check_investment_limits(cost: u64, config: &PlatformConfig) -> Result<()> {
     if cost < config.minimum_investment {
         return Err(BitcoinUSTBillsError::MinimumInvestmentNotMet);
     }
-
     if cost > config.maximum_investment {
         return Err(BitcoinUSTBillsError::MaximumInvestmentExceeded);
     }
+    Ok(())
+}
 
-    // Check user balance
-    if user.wallet_balance < cost {
-        return Err(BitcoinUSTBillsError::InsufficientFunds);
-    }
+/// Stages a purchase at an agreed cost and fee: the wallet debit, the bill's sold tokens,
+/// the new holding and its transactions. Nothing is written until the batch commits.
+fn stage_purchase(
+    principal: Principal,
+    mut ustbill: USTBill,
+    token_amount: u64,
+    cost: u64,
+    fees: u64,
+    pay_with: Option<String>,
+    now: u64,
+) -> Result<(WriteBatch, TokenHolding)> {
+    let mut user = UserStorage::get(&principal)?;
 
-    // Calculate fees
-    let fees = (cost as f64 * config.platform_fee_percentage) as u64;
-    let total_cost = cost + fees;
+    // Validate user eligibility
+    if !user.is_eligible_for_trading() {
+        return Err(BitcoinUSTBillsError::TradingNotAllowed);
+    }
 
-    // Update user balance
-    user.wallet_balance -= total_cost;
+    let ustbill_id = ustbill.id.clone();
+    let total_cost = Money::from_cents(cost)
+        .checked_add(Money::from_cents(fees))?
+        .cents();
+
+    // Debit the wallet, converting from the payment asset at its USD price
+    let (paid, paid_with) = match pay_with {
+        Some(asset) => {
+            let units = settlement::debit_value(&mut user, &asset, total_cost, now)?;
+            let paid = format!(", paid with {} {} ledger units", units, asset);
+            (
+                paid,
+                Some((asset, units)).filter(|(asset, _)| !settlement::is_cash(asset)),
+            )
+        }
+        None => {
+            settlement::debit_cash(&mut user, total_cost)?;
+            (String::new(), None)
+        }
+    };
     user.total_invested += cost;
-    user.updated_at = get_current_timestamp();
+    user.updated_at = now;
 
     // Update UST Bill
     ustbill.tokens_sold += token_amount;
     if ustbill.tokens_sold >= ustbill.total_tokens {
         ustbill.status = USTBillStatus::SoldOut;
     }
-    ustbill.updated_at = get_current_timestamp();
+    ustbill.updated_at = now;
 
     // Create holding
     let holding_id = generate_id();
@@ -337,11 +570,13 @@ pub async fn buy_ustbill_tokens(ustbill_id: String, token_amount: u64) -> Result
         ustbill_id: ustbill_id.clone(),
         tokens_owned: token_amount,
         purchase_price_per_token: cost / token_amount,
-        purchase_date: get_current_timestamp(),
+        cost_basis: cost,
+        purchase_date: now,
         yield_option: YieldOption::Maturity,
         status: HoldingStatus::Active,
         current_value: cost,
-        projected_yield: calculate_projected_yield(&ustbill, cost),
+        projected_yield: calculate_projected_yield(&ustbill, cost, now)?,
+        yield_paid: 0,
     };
 
     // Record transaction
@@ -352,12 +587,12 @@ pub async fn buy_ustbill_tokens(ustbill_id: String, token_amount: u64) -> Result
         amount: cost,
         ustbill_id: Some(ustbill_id.clone()),
         holding_id: Some(holding_id.clone()),
-        timestamp: get_current_timestamp(),
+        timestamp: now,
         status: TransactionStatus::Completed,
         fees,
         description: format!(
-            "Purchase of {} tokens from UST Bill {}",
-            token_amount, ustbill_id
+            "Purchase of {} tokens from UST Bill {}{}",
+            token_amount, ustbill_id, paid
         ),
     };
 
@@ -369,24 +604,219 @@ pub async fn buy_ustbill_tokens(ustbill_id: String, token_amount: u64) -> Result
         amount: fees,
         ustbill_id: Some(ustbill_id.clone()),
         holding_id: Some(holding_id.clone()),
-        timestamp: get_current_timestamp(),
+        timestamp: now,
         status: TransactionStatus::Completed,
         fees: 0,
         description: "Platform fee".to_string(),
     };
 
-    // Save all updates
-    UserStorage::update(user)?;
-    USTBillStorage::update(ustbill)?;
-    HoldingStorage::insert(holding.clone())?;
-    TransactionStorage::insert(transaction)?;
-    TransactionStorage::insert(fee_transaction)?;
+    // Stage every write so a failure leaves nothing half-applied
+    let reference = transaction.id.clone();
+    let mut batch = WriteBatch::new();
+    batch.update_user(user);
+    batch.update_ustbill(ustbill);
+    batch.insert_holding(holding.clone());
+    batch.insert_transaction(transaction);
+    batch.insert_transaction(fee_transaction);
+    batch.then(move || {
+        let paid_with = paid_with
+            .as_ref()
+            .map(|(asset, units)| (asset.as_str(), *units));
+        accounting::purchase(principal, cost, fees, paid_with, &reference, now)
+    });
+    batch.then(move || {
+        let mint = icrc3::BlockOp::Mint {
+            ustbill_id: &ustbill_id,
+            to: principal,
+            amount: token_amount,
+        };
+        icrc3::append_trade(mint, principal, fees, now * 1_000_000_000)?;
+        Ok(())
+    });
+    batch.then(move || {
+        TradingMetricsStorage::update_volume(cost, now)?;
+        TradingMetricsStorage::update_price(cost / token_amount, now)
+    });
+
+    Ok((batch, holding))
+}
 
-    // Update trading metrics
-    TradingMetricsStorage::update_volume(cost)?;
-    TradingMetricsStorage::update_price(cost / token_amount)?;
+/// Sells tokens from a holding back to the UST Bill pool at accrued value
+#[update]
+pub async fn sell_holding(holding_id: String, token_amount: u64) -> Result<TokenHolding> {
+    assert_trading_enabled()?;
+    sell_tokens(
+        ic_cdk::api::msg_caller(),
+        &holding_id,
+        token_amount,
+        get_current_timestamp(),
+    )
+}
 
-    Ok(holding)
+/// Sells `token_amount` tokens of one of `principal`'s holdings back to the bill's pool,
+/// returning the sold holding
+fn sell_tokens(
+    principal: Principal,
+    holding_id: &str,
+    token_amount: u64,
+    current_time: u64,
+) -> Result<TokenHolding> {
+    let mut user = UserStorage::get(&principal)?;
+
+    // Validate user eligibility
+    if !user.is_eligible_for_trading() {
+        return Err(BitcoinUSTBillsError::TradingNotAllowed);
+    }
+
+    let mut holding = HoldingStorage::get(holding_id)?;
+
+    // Only the owner can sell a holding
+    if holding.user_principal != principal {
+        return Err(BitcoinUSTBillsError::Unauthorized);
+    }
+
+    match holding.status {
+        HoldingStatus::Active => {}
+        HoldingStatus::Sold => return Err(BitcoinUSTBillsError::HoldingAlreadySold),
+        HoldingStatus::Matured => return Err(BitcoinUSTBillsError::HoldingMatured),
+        HoldingStatus::Cancelled => return Err(BitcoinUSTBillsError::InvalidHoldingData),
+    }
+
+    if token_amount == 0 {
+        return Err(BitcoinUSTBillsError::InvalidTokenAmount);
+    }
+
+    // Tokens committed to open ask orders cannot be sold directly
+    if token_amount > orderbook::available_tokens(&holding, current_time) {
+        return Err(BitcoinUSTBillsError::InsufficientTokens);
+    }
+
+    let mut ustbill = USTBillStorage::get(&holding.ustbill_id)?;
+
+    // Matured bills are redeemed at face value, not sold
+    if ustbill.maturity_date <= current_time {
+        return Err(BitcoinUSTBillsError::USTBillMatured);
+    }
+
+    // Price the tokens at their accrued value
+    let holding_value = calculate_holding_value_internal(&holding, &ustbill, current_time)?;
+    let proceeds = pro_rata(holding_value, token_amount, holding.tokens_owned)?;
+    let cost_basis = pro_rata(holding.cost_basis, token_amount, holding.tokens_owned)?;
+
+    let config = PlatformConfigStorage::get();
+    let fees = utils::calculate_fees(proceeds, config.platform_fee_percentage)?;
+    let net_proceeds = proceeds.saturating_sub(fees);
+
+    // Split off the sold tokens into their own holding on partial sells
+    let sold_holding = if token_amount < holding.tokens_owned {
+        let sold_value = pro_rata(holding.current_value, token_amount, holding.tokens_owned)?;
+        let sold_projected_yield =
+            pro_rata(holding.projected_yield, token_amount, holding.tokens_owned)?;
+        let sold_yield_paid = pro_rata(holding.yield_paid, token_amount, holding.tokens_owned)?;
+
+        holding.tokens_owned -= token_amount;
+        holding.cost_basis -= cost_basis;
+        holding.current_value -= sold_value;
+        holding.projected_yield -= sold_projected_yield;
+        holding.yield_paid -= sold_yield_paid;
+
+        TokenHolding {
+            id: generate_id(),
+            user_principal: principal,
+            ustbill_id: holding.ustbill_id.clone(),
+            tokens_owned: token_amount,
+            purchase_price_per_token: holding.purchase_price_per_token,
+            cost_basis,
+            purchase_date: holding.purchase_date,
+            yield_option: holding.yield_option.clone(),
+            status: HoldingStatus::Sold,
+            current_value: proceeds,
+            projected_yield: 0,
+            yield_paid: sold_yield_paid,
+        }
+    } else {
+        holding.status = HoldingStatus::Sold;
+        holding.current_value = proceeds;
+        holding.projected_yield = 0;
+        holding.clone()
+    };
+
+    // Update user balance
+    settlement::credit_cash(&mut user, net_proceeds);
+    user.total_invested = user.total_invested.saturating_sub(cost_basis);
+    // Gains count net of the platform fee
+    user.total_yield_earned += net_proceeds.saturating_sub(cost_basis);
+    user.updated_at = current_time;
+
+    // Return tokens to the UST Bill pool
+    ustbill.tokens_sold -= token_amount;
+    if ustbill.status == USTBillStatus::SoldOut && ustbill.available_tokens() > 0 {
+        ustbill.status = USTBillStatus::Active;
+    }
+    ustbill.updated_at = current_time;
+
+    // Record transaction
+    let transaction = Transaction {
+        id: generate_id(),
+        user_principal: principal,
+        transaction_type: TransactionType::Sale,
+        amount: proceeds,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: Some(sold_holding.id.clone()),
+        timestamp: current_time,
+        status: TransactionStatus::Completed,
+        fees,
+        description: format!(
+            "Sale of {} tokens from UST Bill {}",
+            token_amount, ustbill.id
+        ),
+    };
+
+    // Record fees transaction
+    let fee_transaction = Transaction {
+        id: generate_id(),
+        user_principal: principal,
+        transaction_type: TransactionType::Fee,
+        amount: fees,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: Some(sold_holding.id.clone()),
+        timestamp: current_time,
+        status: TransactionStatus::Completed,
+        fees: 0,
+        description: "Platform fee".to_string(),
+    };
+
+    // Stage every write so a failure leaves nothing half-applied
+    let reference = transaction.id.clone();
+    let ustbill_id = ustbill.id.clone();
+    let mut batch = WriteBatch::new();
+    batch.update_user(user);
+    batch.update_ustbill(ustbill);
+    batch.update_holding(holding);
+    if sold_holding.id != holding_id {
+        batch.insert_holding(sold_holding.clone());
+    }
+    batch.insert_transaction(transaction);
+    batch.insert_transaction(fee_transaction);
+    batch.then(move || {
+        accounting::sale(principal, cost_basis, proceeds, fees, &reference, current_time)
+    });
+    batch.then(move || {
+        let burn = icrc3::BlockOp::Burn {
+            ustbill_id: &ustbill_id,
+            from: principal,
+            amount: token_amount,
+        };
+        icrc3::append_trade(burn, principal, fees, current_time * 1_000_000_000)?;
+        Ok(())
+    });
+    batch.then(move || {
+        TradingMetricsStorage::update_volume(proceeds, current_time)?;
+        TradingMetricsStorage::update_price(proceeds / token_amount, current_time)
+    });
+    batch.commit()?;
+
+    Ok(sold_holding)
 }
 
 /// Calculates purchase cost for tokens
@@ -396,22 +826,21 @@ pub fn calculate_purchase_cost(ustbill_id: String, token_amount: u64) -> Result<
     calculate_purchase_cost_internal(&ustbill, token_amount)
 }
 
+/// Prices a bill by Treasury conventions as of `as_of` (Unix timestamp)
+#[query]
+pub fn quote_price(ustbill_id: String, as_of: u64) -> Result<PriceQuote> {
+    let ustbill = USTBillStorage::get(&ustbill_id)?;
+    let discount_rate = market::discount_rate_for(&ustbill)?;
+    pricing::quote(&ustbill, discount_rate, as_of)
+}
+
 /// Calculates current value of a holding
 #[query]
 pub fn calculate_current_value(holding_id: String) -> Result<u64> {
     let holding = HoldingStorage::get(&holding_id)?;
     let ustbill = USTBillStorage::get(&holding.ustbill_id)?;
 
-    // Calculate based on current market conditions and time to maturity
-    let _days_to_maturity = ustbill.days_to_maturity();
-    let purchase_date = holding.purchase_date;
-    let current_time = get_current_timestamp();
-    let days_held = (current_time - purchase_date) / 86400; // Convert to days
-
-    // Calculate accrued yield
-    let accrued_yield = holding.calculate_current_yield(ustbill.annual_yield, days_held);
-
-    Ok(holding.current_value + accrued_yield)
+    calculate_holding_value_internal(&holding, &ustbill, get_current_timestamp())
 }
 
 /// Retrieves user holdings
@@ -420,6 +849,158 @@ pub fn get_user_holdings(principal: Principal) -> Vec<TokenHolding> {
     HoldingStorage::get_by_user(&principal)
 }
 
+// ╔══════════════════════════════════════════════════════════════════════════════╗
+// ║                  ORDER BOOK FUNCTIONS                        ║
+// ╚══════════════════════════════════════════════════════════════════════════════╝
+
+/// Places a bid or ask limit order and matches it against the book
+#[update]
+pub async fn place_order(request: OrderRequest) -> Result<Order> {
+    assert_trading_enabled()?;
+    let principal = ic_cdk::api::msg_caller();
+    orderbook::place_order(principal, request)
+}
+
+/// Cancels one of the caller's open orders
+#[update]
+pub async fn cancel_order(order_id: String) -> Result<Order> {
+    let principal = ic_cdk::api::msg_caller();
+    orderbook::cancel_order(principal, &order_id)
+}
+
+/// Expires every open order past its expiry time (Support)
+#[update]
+pub async fn expire_orders() -> Result<u64> {
+    guard::assert_permission(Permission::ManageOrders)?;
+    orderbook::expire_orders(get_current_timestamp())
+}
+
+/// Retrieves an order by ID
+#[query]
+pub fn get_order(order_id: String) -> Result<Order> {
+    OrderStorage::get(&order_id)
+}
+
+/// Retrieves all orders placed by a user
+#[query]
+pub fn get_user_orders(principal: Principal) -> Vec<Order> {
+    OrderStorage::get_by_user(&principal)
+}
+
+/// Gets the aggregated order book for a US Treasury Bill
+#[query]
+pub fn get_order_book(ustbill_id: String, depth: usize) -> Result<OrderBookSnapshot> {
+    USTBillStorage::get(&ustbill_id)?;
+    Ok(orderbook::snapshot(&ustbill_id, depth))
+}
+
+// ╔══════════════════════════════════════════════════════════════════════════════╗
+// ║                  BILL TOKENS (one token per UST Bill)                        ║
+// ╚══════════════════════════════════════════════════════════════════════════════╝
+
+// One canister serves every bill's token, so each call names the bill. That makes these
+// ICRC-1/ICRC-2 lookalikes rather than the standard methods; the block log is ICRC-3.
+
+#[query]
+pub fn token_name(ustbill_id: String) -> Result<String> {
+    Ok(icrc::token_name(&USTBillStorage::get(&ustbill_id)?))
+}
+
+#[query]
+pub fn token_symbol(ustbill_id: String) -> Result<String> {
+    Ok(icrc::token_symbol(&USTBillStorage::get(&ustbill_id)?))
+}
+
+#[query]
+pub fn token_decimals() -> u8 {
+    icrc::DECIMALS
+}
+
+#[query]
+pub fn token_fee() -> Nat {
+    Nat::from(icrc::TRANSFER_FEE)
+}
+
+#[query]
+pub fn token_total_supply(ustbill_id: String) -> Result<Nat> {
+    Ok(Nat::from(USTBillStorage::get(&ustbill_id)?.total_tokens))
+}
+
+#[query]
+pub fn token_metadata(ustbill_id: String) -> Result<Vec<(String, MetadataValue)>> {
+    Ok(icrc::metadata(&USTBillStorage::get(&ustbill_id)?))
+}
+
+/// Tokens are minted by purchases from the platform, which holds unsold supply
+#[query]
+pub fn token_minting_account() -> Option<Account> {
+    Some(Account {
+        owner: ic_cdk::api::canister_self(),
+        subaccount: None,
+    })
+}
+
+#[query]
+pub fn token_balance_of(ustbill_id: String, account: Account) -> Nat {
+    Nat::from(icrc::balance_of(&ustbill_id, &account, ic_cdk::api::time()))
+}
+
+/// Transfers tokens by reassigning the caller's holdings; the recipient must be KYC-verified
+#[update]
+pub fn token_transfer(
+    ustbill_id: String,
+    arg: TransferArg,
+) -> std::result::Result<Nat, TransferError> {
+    icrc::transfer(&ustbill_id, ic_cdk::api::msg_caller(), arg, ic_cdk::api::time())
+}
+
+#[update]
+pub fn token_approve(
+    ustbill_id: String,
+    args: ApproveArgs,
+) -> std::result::Result<Nat, ApproveError> {
+    icrc::approve(&ustbill_id, ic_cdk::api::msg_caller(), args, ic_cdk::api::time())
+}
+
+#[update]
+pub fn token_transfer_from(
+    ustbill_id: String,
+    args: TransferFromArgs,
+) -> std::result::Result<Nat, TransferFromError> {
+    icrc::transfer_from(&ustbill_id, ic_cdk::api::msg_caller(), args, ic_cdk::api::time())
+}
+
+#[query]
+pub fn token_allowance(ustbill_id: String, args: AllowanceArgs) -> Allowance {
+    icrc::allowance(&ustbill_id, &args, ic_cdk::api::time())
+}
+
+#[query]
+pub fn icrc10_supported_standards() -> Vec<StandardRecord> {
+    icrc::supported_standards()
+}
+
+#[query]
+pub fn icrc3_get_blocks(args: Vec<GetBlocksRequest>) -> GetBlocksResult {
+    icrc3::get_blocks(args)
+}
+
+#[query]
+pub fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    icrc3::tip_certificate()
+}
+
+/// Blocks are never archived, so there are no archive canisters
+#[query]
+pub fn icrc3_get_archives(_args: GetArchivesArgs) -> Vec<ICRC3ArchiveInfo> {
+    Vec::new()
+}
+
+#[query]
+pub fn icrc3_supported_block_types() -> Vec<SupportedBlockType> {
+    icrc3::supported_block_types()
+}
+
 // ╔══════════════════════════════════════════════════════════════════════════════╗
 // ║                  YIELD CANISTER FUNCTIONS                        ║
 // ╚══════════════════════════════════════════════════════════════════════════════╝
@@ -434,9 +1015,7 @@ pub async fn calculate_maturity_yield(holding_id: String) -> Result<u64> {
     let current_time = get_current_timestamp();
     if ustbill.maturity_date <= current_time {
         // Calculate full yield
-        let purchase_value = holding.tokens_owned * holding.purchase_price_per_token;
-        let face_value = (ustbill.face_value * holding.tokens_owned) / ustbill.total_tokens;
-        let yield_amount = face_value - purchase_value;
+        let (_, yield_amount) = maturity::maturity_payout(&holding, &ustbill)?;
 
         Ok(yield_amount)
     } else {
@@ -444,6 +1023,26 @@ pub async fn calculate_maturity_yield(holding_id: String) -> Result<u64> {
     }
 }
 
+/// Settles matured bills immediately instead of waiting for the timer (Treasurer)
+#[update]
+pub async fn run_maturity_processing_now() -> Result<MaturityReport> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    maturity::process_maturities(get_current_timestamp())
+}
+
+/// Accrues and pays out due yield immediately instead of waiting for the timer (Treasurer)
+#[update]
+pub async fn run_yield_distribution_now() -> Result<YieldRunReport> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    yield_distribution::distribute_yield(get_current_timestamp())
+}
+
+/// Retrieves the per-holding, per-period yield records of a user
+#[query]
+pub fn get_yield_history(principal: Principal) -> Vec<YieldDistribution> {
+    YieldDistributionStorage::get_by_user(&principal)
+}
+
 /// Gets yield projection for a holding
 #[query]
 pub fn get_yield_projection(holding_id: String) -> Result<YieldProjection> {
@@ -452,13 +1051,19 @@ pub fn get_yield_projection(holding_id: String) -> Result<YieldProjection> {
 
     let current_value = holding.current_value;
     let days_to_maturity = ustbill.days_to_maturity();
-    let annual_yield_rate = ustbill.annual_yield;
+    let annual_yield_rate = ustbill.annual_yield.to_f64();
 
     // Calculate projected yield
-    let projected_yield =
-        (current_value as f64 * annual_yield_rate * days_to_maturity as f64 / 365.0) as u64;
+    let projected_yield = Money::from_cents(current_value)
+        .interest(
+            ustbill.annual_yield,
+            days_to_maturity,
+            DAYS_PER_YEAR,
+            Rounding::Floor,
+        )?
+        .cents();
     let yield_percentage = if current_value > 0 {
-        (projected_yield as f64 / current_value as f64) * 100.0
+        Rate::from_ratio(projected_yield, current_value, Rounding::HalfEven)?.to_f64() * 100.0
     } else {
         0.0
     };
@@ -477,63 +1082,132 @@ pub fn get_yield_projection(holding_id: String) -> Result<YieldProjection> {
 // ║                  EXTERNAL API INTEGRATION                        ║
 // ╚══════════════════════════════════════════════════════════════════════════════╝
 
-/// Fetches current Treasury rates from external API
+/// Fetches current Treasury rates from the configured rate source (Treasurer)
 #[update]
 pub async fn fetch_treasury_rates() -> Result<Vec<TreasuryRate>> {
-    // For now, return a mock response until we can properly configure HTTP requests
-    // This is a placeholder implementation
-    let mock_rates = vec![TreasuryRate {
-        record_date: "2024-01-01".to_string(),
-        security_type: "T-Bill".to_string(),
-        security_desc: "13-Week Treasury Bill".to_string(),
-        rate_date: "2024-01-01".to_string(),
-        rate: 5.26,
-        cusip: "912796RF6".to_string(),
-    }];
-
-    // Store rates in database
-    TreasuryRateStorage::clear()?;
-    for rate in &mock_rates {
-        TreasuryRateStorage::insert(rate.clone())?;
+    guard::assert_permission(Permission::ManageMarketData)?;
+    refresh_treasury_rates().await
+}
+
+/// Gets the configured Treasury rate source
+#[query]
+pub fn get_rate_source() -> RateSource {
+    RateSourceStorage::get()
+}
+
+/// Sets the Treasury rate source, e.g. a canned response on local replicas (Treasurer)
+#[update]
+pub async fn set_rate_source(source: RateSource) -> Result<()> {
+    guard::assert_permission(Permission::ManageMarketData)?;
+
+    if let RateSource::TreasuryApi { url } = &source {
+        treasury::validate_rates_url(url)?;
+    }
+    RateSourceStorage::update(source)
+}
+
+/// Gets the rate history of a CUSIP between two ISO dates (inclusive)
+#[query]
+pub fn get_rate_history(cusip: String, from: String, to: String) -> Result<Vec<TreasuryRate>> {
+    utils::parse_iso_date(&from)?;
+    utils::parse_iso_date(&to)?;
+    Ok(TreasuryRateStorage::get_history(&cusip, &from, &to))
+}
+
+/// Gets the most recent rate for a security, e.g. "13-Week Treasury Bill"
+#[query]
+pub fn get_latest_rate(security_desc: String) -> Result<TreasuryRate> {
+    TreasuryRateStorage::get_latest_by_desc(&security_desc)
+        .ok_or(BitcoinUSTBillsError::TreasuryRateNotFound)
+}
+
+/// Gets the health of the Treasury rate feed
+#[query]
+pub fn get_rate_feed_status() -> RateFeedStatus {
+    let mut status = RateFeedStatusStorage::get();
+    let refresh_interval = PlatformConfigStorage::get().treasury_api_refresh_interval;
+
+    status.refresh_interval = refresh_interval;
+    status.is_stale = match status.last_success {
+        Some(last_success) => {
+            get_current_timestamp().saturating_sub(last_success) > 2 * refresh_interval
+        }
+        None => true,
+    };
+
+    status
+}
+
+/// Gets the latest USD price of every oracle-priced settlement asset
+#[query]
+pub fn get_asset_prices() -> Vec<AssetPrice> {
+    AssetPriceStorage::get_all()
+}
+
+/// Overrides the USD price, in cents per whole token, of an oracle-priced settlement asset
+/// until the next oracle refresh (Treasurer)
+#[update]
+pub fn set_asset_price(asset: String, price_cents: u64) -> Result<()> {
+    guard::assert_permission(Permission::ManageMarketData)?;
+
+    if settlement::asset(&asset)?.pricing != UsdPricing::Oracle {
+        return Err(BitcoinUSTBillsError::validation_error(format!(
+            "{} is pegged",
+            asset
+        )));
     }
+    if price_cents == 0 {
+        return Err(BitcoinUSTBillsError::InvalidAmount);
+    }
+    AssetPriceStorage::update(AssetPrice {
+        asset,
+        price_cents,
+        updated_at: get_current_timestamp(),
+    })
+}
+
+/// Gets the last accepted BTC/USD price with its timestamp and the sources behind it
+#[query]
+pub fn get_btc_usd_price() -> Result<BtcUsdPrice> {
+    oracle::btc_usd_price(get_current_timestamp())
+}
+
+/// Refreshes the BTC/USD price from the oracle sources now (Treasurer)
+#[update]
+pub async fn refresh_btc_usd_price() -> Result<BtcUsdPrice> {
+    guard::assert_permission(Permission::ManageMarketData)?;
+    oracle::refresh_btc_usd_price(get_current_timestamp()).await
+}
+
+/// Gets the BTC/USD oracle sources and thresholds
+#[query]
+pub fn get_oracle_config() -> OracleConfig {
+    OracleConfigStorage::get()
+}
+
+/// Replaces the BTC/USD oracle sources and thresholds (Treasurer)
+#[update]
+pub fn set_oracle_config(config: OracleConfig) -> Result<()> {
+    guard::assert_permission(Permission::ManageMarketData)?;
+    oracle::validate_config(&config)?;
+
+    let previous = OracleConfigStorage::get();
+    let refresh_interval = config.refresh_interval_seconds;
+    OracleConfigStorage::update(config)?;
 
-    Ok(mock_rates)
-
-    // TODO: Implement actual HTTP request once we have proper configuration
-    // let url = "https://api.fiscaldata.treasury.gov/services/api/v1/accounting/od/rates_of_exchange";
-    //
-    // let request = HttpRequestType {
-    //     url: url.to_string(),
-    //     method: HttpMethod::GET,
-    //     headers: vec![],
-    //     body: None,
-    //     transform: Some(TransformContext::from_name("transform_treasury_response".to_string(), vec![])),
-    // };
-    //
-    // match http_request(request, 10_000_000_000).await {
-    //     Ok((response,)) => {
-    //         let rates = parse_treasury_response(response)?;
-    //
-    //         // Store rates in database
-    //         TreasuryRateStorage::clear()?;
-    //         for rate in &rates {
-    //             TreasuryRateStorage::insert(rate.clone())?;
-    //         }
-    //
-    //         Ok(rates)
-    //     }
-    //     Err(e) => Err(BitcoinUSTBillsError::http_request_error(format!("HTTP request failed: {:?}", e))),
-    // }
-}
-
-/// Updates UST Bill market data
-#[update]
-pub async fn update_ustbill_market_data() -> Result<()> {
-    let _rates = fetch_treasury_rates().await?;
-    // Update logic would go here to sync with external data
+    if refresh_interval != previous.refresh_interval_seconds {
+        timers::schedule_price_refresh(refresh_interval);
+    }
     Ok(())
 }
 
+/// Updates UST Bill market data by repricing bills and holdings from fresh rates (Treasurer)
+#[update]
+pub async fn update_ustbill_market_data() -> Result<Vec<MarketPriceUpdate>> {
+    guard::assert_permission(Permission::ManageMarketData)?;
+    refresh_market_data().await
+}
+
 // ╔══════════════════════════════════════════════════════════════════════════════╗
 // ║                  PLATFORM MANAGEMENT                        ║
 // ╚══════════════════════════════════════════════════════════════════════════════╝
@@ -544,11 +1218,62 @@ pub fn get_platform_config() -> PlatformConfig {
     PlatformConfigStorage::get()
 }
 
-/// Updates platform configuration (admin only)
+/// Updates platform configuration (SuperAdmin only)
 #[update]
 pub async fn update_platform_config(config: PlatformConfig) -> Result<()> {
-    guard::assert_admin()?;
-    PlatformConfigStorage::update(config)
+    guard::assert_permission(Permission::ManagePlatform)?;
+    validate_platform_config(&config)?;
+
+    let previous = PlatformConfigStorage::get();
+    let refresh_interval = config.treasury_api_refresh_interval;
+    PlatformConfigStorage::update(config)?;
+
+    if refresh_interval != previous.treasury_api_refresh_interval {
+        timers::schedule_rate_refresh(refresh_interval);
+    }
+
+    Ok(())
+}
+
+/// Gets the settlement assets and the cash asset
+#[query]
+pub fn get_settlement_config() -> SettlementConfig {
+    SettlementConfigStorage::get()
+}
+
+/// Replaces the settlement assets; adding an ICRC ledger needs no code change (Treasurer)
+#[update]
+pub fn set_settlement_config(config: SettlementConfig) -> Result<()> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    settlement::validate_config(&config)?;
+    SettlementConfigStorage::update(config)
+}
+
+/// Gets the withdrawal approval thresholds and the platform fee account
+#[query]
+pub fn get_withdrawal_policy() -> WithdrawalPolicy {
+    WithdrawalPolicyStorage::get()
+}
+
+/// Sets the withdrawal approval thresholds and the platform fee account. Kept from
+/// Treasurers, who approve the withdrawals above them and withdraw the fees (SuperAdmin only)
+#[update]
+pub fn set_withdrawal_policy(policy: WithdrawalPolicy) -> Result<()> {
+    guard::assert_permission(Permission::ManagePlatform)?;
+    WithdrawalPolicyStorage::update(policy)
+}
+
+/// Gets the feature toggles
+#[query]
+pub fn get_feature_flags() -> FeatureFlags {
+    FeatureFlagsStorage::get()
+}
+
+/// Updates the feature toggles (SuperAdmin only)
+#[update]
+pub fn set_feature_flags(flags: FeatureFlags) -> Result<()> {
+    guard::assert_permission(Permission::ManagePlatform)?;
+    FeatureFlagsStorage::update(flags)
 }
 
 /// Gets trading metrics
@@ -563,6 +1288,126 @@ pub fn get_storage_stats() -> HashMap<String, u64> {
     storage::get_storage_stats()
 }
 
+// ╔══════════════════════════════════════════════════════════════════════════════╗
+// ║                  ACCOUNTING                        ║
+// ╚══════════════════════════════════════════════════════════════════════════════╝
+
+/// Gets the caller's cash, holdings-at-cost and other settlement asset accounts, as
+/// derived from the journal
+#[query]
+pub fn get_my_account_balances() -> Vec<AccountBalance> {
+    let principal = ic_cdk::api::msg_caller();
+    let mut accounts = vec![
+        LedgerAccount::UserCash(principal),
+        LedgerAccount::UserHoldings(principal),
+    ];
+    for asset in SettlementConfigStorage::get().assets {
+        if !settlement::is_cash(&asset.symbol) {
+            accounts.push(LedgerAccount::UserAsset(principal, asset.symbol));
+        }
+    }
+    accounts.iter().map(accounting::balance).collect()
+}
+
+/// Gets every account's totals; the balances net to zero (Auditor or SuperAdmin)
+#[query]
+pub fn get_trial_balance() -> Result<TrialBalance> {
+    guard::assert_permission(Permission::ViewAuditData)?;
+    Ok(accounting::trial_balance())
+}
+
+/// Checks the journal against stored wallet, holding and escrow balances, failing with
+/// every account that has drifted (Auditor or SuperAdmin)
+#[query]
+pub fn reconcile_journal() -> Result<()> {
+    guard::assert_permission(Permission::ViewAuditData)?;
+    accounting::reconcile()
+}
+
+/// Gets journal entries in posting order, from `offset` (Auditor or SuperAdmin)
+#[query]
+pub fn get_journal(offset: u64, limit: u64) -> Result<Vec<JournalEntry>> {
+    guard::assert_permission(Permission::ViewAuditData)?;
+    Ok(JournalStorage::get_range(offset, limit.min(MAX_JOURNAL_PAGE)))
+}
+
+/// Gets the fees collected in `asset`, the cash asset unless given, between `from` and
+/// `to`, by bill and fee type, with what was withdrawn and what remains (Auditor or
+/// SuperAdmin)
+#[query]
+pub fn get_fee_revenue_report(
+    from: u64,
+    to: u64,
+    asset: Option<String>,
+) -> Result<FeeRevenueReport> {
+    guard::assert_permission(Permission::ViewAuditData)?;
+    if from > to {
+        return Err(BitcoinUSTBillsError::InvalidDate);
+    }
+    let asset = settlement::asset(&asset.unwrap_or(SettlementConfigStorage::get().cash_asset))?;
+    Ok(accounting::fee_revenue_report(&asset.symbol, from, to))
+}
+
+/// Pays fee revenue collected in `asset`, the cash asset unless given, to the platform fee
+/// account set in the withdrawal policy. All of what remains is paid unless `amount` is
+/// given, in cents for the cash asset and ledger units otherwise (Treasurer or SuperAdmin).
+#[update]
+pub async fn withdraw_platform_fees(
+    amount: Option<u64>,
+    asset: Option<String>,
+) -> Result<FeeWithdrawal> {
+    guard::assert_permission(Permission::ManageTreasury)?;
+    let asset = settlement::asset(&asset.unwrap_or(SettlementConfigStorage::get().cash_asset))?;
+    settlement::withdraw_platform_fees(
+        &settlement::IcrcLedger::new(&asset),
+        &asset,
+        amount.unwrap_or_else(|| accounting::available_fees(&asset.symbol)),
+        get_current_timestamp(),
+    )
+    .await
+}
+
+// ╔══════════════════════════════════════════════════════════════════════════════╗
+// ║                  ACCESS CONTROL                        ║
+// ╚══════════════════════════════════════════════════════════════════════════════╝
+
+/// Grants a role to a principal (SuperAdmin only)
+#[update]
+pub fn grant_role(principal: Principal, role: Role) -> Result<()> {
+    guard::assert_permission(Permission::ManageRoles)?;
+
+    guard::grant_role(principal, role)?;
+    Ok(())
+}
+
+/// Revokes a role from a principal (SuperAdmin only); the last SuperAdmin can't be revoked
+#[update]
+pub fn revoke_role(principal: Principal, role: Role) -> Result<()> {
+    guard::assert_permission(Permission::ManageRoles)?;
+
+    guard::revoke_role(&principal, role)?;
+    Ok(())
+}
+
+/// Gets the roles held by a principal
+#[query]
+pub fn get_roles(principal: Principal) -> Vec<Role> {
+    RoleStorage::get(&principal)
+}
+
+/// Gets the caller's roles
+#[query]
+pub fn get_my_roles() -> Vec<Role> {
+    RoleStorage::get(&ic_cdk::api::msg_caller())
+}
+
+/// Gets every role assignment (Auditor or SuperAdmin)
+#[query]
+pub fn get_role_assignments() -> Result<Vec<RoleAssignment>> {
+    guard::assert_permission(Permission::ViewAuditData)?;
+    Ok(RoleStorage::get_all())
+}
+
 // ╔══════════════════════════════════════════════════════════════════════════════╗
 // ║                  HELPER FUNCTIONS                        ║
 // ╚══════════════════════════════════════════════════════════════════════════════╝
@@ -572,9 +1417,7 @@ pub fn validate_ustbill_data(data: &USTBillCreateRequest) -> Result<()> {
         return Err(BitcoinUSTBillsError::InvalidCUSIP);
     }
 
-    if data.annual_yield < 0.0 || data.annual_yield > 1.0 {
-        return Err(BitcoinUSTBillsError::InvalidYieldRate);
-    }
+    utils::validate_yield_rate(Rate::from_f64(data.annual_yield)?)?;
 
     if data.total_tokens == 0 {
         return Err(BitcoinUSTBillsError::InvalidTokenAmount);
@@ -588,6 +1431,42 @@ pub fn validate_ustbill_data(data: &USTBillCreateRequest) -> Result<()> {
     Ok(())
 }
 
+pub fn validate_platform_config(config: &PlatformConfig) -> Result<()> {
+    if config.platform_fee_percentage >= Rate::ONE {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "Platform fee percentage must be in [0, 1)",
+        ));
+    }
+
+    if config.minimum_investment == 0 || config.minimum_investment > config.maximum_investment {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "Investment limits must satisfy 0 < minimum <= maximum",
+        ));
+    }
+
+    if config.yield_distribution_frequency == 0 {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "Yield distribution frequency must be positive",
+        ));
+    }
+
+    if config.treasury_api_refresh_interval == 0 {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "Treasury API refresh interval must be positive",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Rejects trading calls while trading is switched off
+fn assert_trading_enabled() -> Result<()> {
+    if !FeatureFlagsStorage::get().trading_enabled {
+        return Err(BitcoinUSTBillsError::FeatureDisabled("trading".to_string()));
+    }
+    Ok(())
+}
+
 pub fn validate_user_data(data: &UserRegistrationRequest) -> Result<()> {
     if data.email.is_empty() || !data.email.contains('@') {
         return Err(BitcoinUSTBillsError::validation_error(
@@ -609,31 +1488,100 @@ pub fn calculate_purchase_cost_internal(ustbill: &USTBill, token_amount: u64) ->
         return Err(BitcoinUSTBillsError::InvalidTokenAmount);
     }
 
-    let cost_per_token = ustbill.purchase_price / ustbill.total_tokens;
-    Ok(cost_per_token * token_amount)
+    // The next tokens in line, so the tokens of a bill add up to its purchase price
+    pricing::tokens_price(
+        ustbill.purchase_price,
+        ustbill.total_tokens,
+        ustbill.tokens_sold,
+        token_amount,
+    )
 }
 
-pub fn calculate_projected_yield(ustbill: &USTBill, investment: u64) -> u64 {
-    let days_to_maturity = ustbill.days_to_maturity();
-    (investment as f64 * ustbill.annual_yield * days_to_maturity as f64 / 365.0) as u64
+/// Values a holding at its exact cost basis plus the yield accrued since purchase. This is
+/// what a sale back to the pool pays: the marked-to-market `current_value` is for display
+/// only, so the pool never buys back above or below the bill's accrual.
+pub fn calculate_holding_value_internal(
+    holding: &TokenHolding,
+    ustbill: &USTBill,
+    current_time: u64,
+) -> Result<u64> {
+    // Flexible holdings have been paid their yield up to the last distribution
+    let accrual_start = match holding.yield_option {
+        YieldOption::Flexible => yield_distribution::accrual_start(holding),
+        YieldOption::Maturity => calendar::settlement_date(holding.purchase_date),
+    };
+    let days_held = DayCount::days(accrual_start, current_time);
+
+    // Calculate accrued yield
+    let accrued_yield = holding.calculate_current_yield(ustbill.annual_yield, days_held)?;
+
+    let value =
+        Money::from_cents(holding.cost_basis).checked_add(Money::from_cents(accrued_yield))?;
+    Ok(value.cents())
+}
+
+/// Scales `amount` by `part / whole`, rounded down so the shares split off an amount
+/// never add up to more than it
+pub fn pro_rata(amount: u64, part: u64, whole: u64) -> Result<u64> {
+    if whole == 0 {
+        return Ok(0);
+    }
+    Ok(Money::from_cents(amount)
+        .mul_ratio(part, whole, Rounding::Floor)?
+        .cents())
 }
 
-fn parse_treasury_response(response: HttpResponse) -> Result<Vec<TreasuryRate>> {
-    let body = String::from_utf8(response.body)
-        .map_err(|_| BitcoinUSTBillsError::external_api_error("Invalid response body"))?;
+/// Simple-interest yield on `investment` from `now` to maturity, rounded down to the cent
+pub fn calculate_projected_yield(ustbill: &USTBill, investment: u64, now: u64) -> Result<u64> {
+    // A maturity on a weekend or holiday pays the next business day
+    let days_to_maturity = DayCount::days(now, calendar::payment_date(ustbill.maturity_date));
+    let projected = Money::from_cents(investment).interest(
+        ustbill.annual_yield,
+        days_to_maturity,
+        DAYS_PER_YEAR,
+        Rounding::Floor,
+    )?;
+    Ok(projected.cents())
+}
 
-    let api_response: TreasuryApiResponse = serde_json::from_str(&body).map_err(|_| {
-        BitcoinUSTBillsError::external_api_error("Failed to parse Treasury API response")
-    })?;
+/// Fetches Treasury rates, appends them to the rate history and drops records older than
+/// `RATE_HISTORY_RETENTION_DAYS`
+pub async fn refresh_treasury_rates() -> Result<Vec<TreasuryRate>> {
+    let rates = match treasury::fetch_rates().await {
+        Ok(rates) => rates,
+        Err(e) => {
+            RateFeedStatusStorage::record_failure(&e)?;
+            return Err(e);
+        }
+    };
+    RateFeedStatusStorage::record_success(rates.len() as u64)?;
 
-    Ok(api_response.data)
+    // Append to the rate history and drop records past the retention window
+    for rate in &rates {
+        TreasuryRateStorage::insert(rate.clone())?;
+    }
+    let retention_cutoff = get_current_timestamp()
+        .saturating_sub(RATE_HISTORY_RETENTION_DAYS * 86400);
+    TreasuryRateStorage::prune_before(&utils::timestamp_to_iso_date(retention_cutoff));
+
+    Ok(rates)
+}
+
+/// Fetches Treasury rates and marks every active UST Bill and its holdings to market
+pub async fn refresh_market_data() -> Result<Vec<MarketPriceUpdate>> {
+    refresh_treasury_rates().await?;
+    market::reprice_ustbills(get_current_timestamp())
 }
 
 #[query]
-fn transform_treasury_response(response: TransformArgs) -> HttpResponse {
-    let mut res = response.response;
-    res.headers.clear();
-    res
+fn transform_treasury_response(response: TransformArgs) -> HttpRequestResult {
+    treasury::transform(response)
+}
+
+/// Reduces a BTC/USD price outcall response to the price, for replica consensus
+#[query]
+fn transform_price_response(response: TransformArgs) -> HttpRequestResult {
+    oracle::transform(response)
 }
 
 // ╔══════════════════════════════════════════════════════════════════════════════╗
@@ -670,9 +1618,8 @@ pub fn test_func() -> Result<String> {
 
 #[update]
 pub fn add_to_list(p: Principal) -> Result<()> {
-    guard::assert_admin()?;
-    guard::add_to_list(p);
-    Ok(())
+    guard::assert_permission(Permission::ManageRoles)?;
+    guard::add_to_list(p)
 }
 
 #[test]
@@ -681,3 +1628,171 @@ fn generate_candid() {
     std::fs::write("../distributed/backend/backend.did", __export_service())
         .expect("Failed to write backend.did");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_750_000_000;
+
+    /// A KYC-verified investor with $2,000 in cash
+    fn investor() -> Principal {
+        let principal = Principal::from_slice(&[1]);
+        let mut user = User {
+            principal,
+            email: "investor@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![],
+            total_invested: 0,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        };
+        settlement::credit_cash(&mut user, 200_000);
+        UserStorage::insert(user).unwrap();
+        principal
+    }
+
+    /// A 13-week bill priced at $986.70 and split into 1,000 tokens
+    fn ustbill() -> USTBill {
+        let ustbill = USTBill {
+            id: generate_id(),
+            cusip: "912796RF8".to_string(),
+            face_value: 100_000,
+            purchase_price: 98_670,
+            maturity_date: NOW + 91 * calendar::SECONDS_PER_DAY,
+            annual_yield: Rate::from_nanos(54_065_221),
+            total_tokens: 1_000,
+            tokens_sold: 0,
+            status: USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        ustbill
+    }
+
+    fn buy(principal: Principal, ustbill: USTBill, token_amount: u64) -> TokenHolding {
+        let (cost, fees) =
+            price_purchase(&ustbill, token_amount, &PlatformConfigStorage::get()).unwrap();
+        let (batch, holding) =
+            stage_purchase(principal, ustbill, token_amount, cost, fees, None, NOW).unwrap();
+        batch.commit().unwrap();
+        holding
+    }
+
+    fn user_holdings(principal: Principal) -> i64 {
+        accounting::balance(&LedgerAccount::UserHoldings(principal)).balance
+    }
+
+    #[test]
+    fn test_buy_then_full_sale_clears_user_holdings() {
+        let principal = investor();
+        let holding = buy(principal, ustbill(), 1_000);
+
+        // 98.67 cents a token: the average is rounded down, the cost basis is not
+        assert_eq!(holding.purchase_price_per_token, 98);
+        assert_eq!(holding.cost_basis, 98_670);
+        assert_eq!(user_holdings(principal), -98_670);
+
+        sell_tokens(principal, &holding.id, 1_000, NOW).unwrap();
+        assert_eq!(user_holdings(principal), 0);
+        assert_eq!(UserStorage::get(&principal).unwrap().total_invested, 0);
+    }
+
+    #[test]
+    fn test_sale_pays_cost_plus_accrued_yield() {
+        let principal = investor();
+        let ustbill = ustbill();
+        let mut holding = buy(principal, ustbill.clone(), 1_000);
+
+        // Marking the holding to market does not change what the pool pays for it
+        holding.current_value = 99_500;
+        HoldingStorage::update(holding.clone()).unwrap();
+
+        // Ten days after the Monday settlement: 98_670 * 5.4065221% * 10 / 365 = 146.15
+        let sold_at = calendar::settlement_date(NOW) + 10 * calendar::SECONDS_PER_DAY + 3_600;
+        assert_eq!(
+            calculate_holding_value_internal(&holding, &ustbill, sold_at).unwrap(),
+            98_670 + 146
+        );
+        let sold = sell_tokens(principal, &holding.id, 1_000, sold_at).unwrap();
+        assert_eq!(sold.current_value, 98_816);
+    }
+
+    #[test]
+    fn test_full_sale_returns_tokens_to_the_pool() {
+        let principal = investor();
+        let ustbill = ustbill();
+        let holding = buy(principal, ustbill.clone(), 1_000);
+        assert_eq!(
+            USTBillStorage::get(&ustbill.id).unwrap().status,
+            USTBillStatus::SoldOut
+        );
+
+        let sold = sell_tokens(principal, &holding.id, 1_000, NOW).unwrap();
+        assert_eq!(sold.id, holding.id);
+        assert_eq!(sold.status, HoldingStatus::Sold);
+        assert_eq!(
+            HoldingStorage::get(&holding.id).unwrap().status,
+            HoldingStatus::Sold
+        );
+
+        let ustbill = USTBillStorage::get(&ustbill.id).unwrap();
+        assert_eq!(ustbill.tokens_sold, 0);
+        assert_eq!(ustbill.status, USTBillStatus::Active);
+
+        // Sold tokens are gone, so selling them again fails
+        assert!(matches!(
+            sell_tokens(principal, &holding.id, 1_000, NOW),
+            Err(BitcoinUSTBillsError::HoldingAlreadySold)
+        ));
+    }
+
+    #[test]
+    fn test_partial_sale_splits_the_holding_and_charges_the_fee() {
+        let principal = investor();
+        let ustbill = ustbill();
+        let holding = buy(principal, ustbill.clone(), 1_000);
+        let cash_before = settlement::cash_balance(&UserStorage::get(&principal).unwrap());
+
+        // Sixty days after settlement the holding is worth 98_670 + 876 accrued
+        let sold_at = calendar::settlement_date(NOW) + 60 * calendar::SECONDS_PER_DAY;
+        let sold = sell_tokens(principal, &holding.id, 300, sold_at).unwrap();
+
+        assert_ne!(sold.id, holding.id);
+        assert_eq!(sold.status, HoldingStatus::Sold);
+        assert_eq!((sold.tokens_owned, sold.cost_basis), (300, 29_601));
+        assert_eq!(sold.current_value, 29_863);
+
+        // The rest of the holding keeps the remainder of the cost basis exactly
+        let remaining = HoldingStorage::get(&holding.id).unwrap();
+        assert_eq!(remaining.status, HoldingStatus::Active);
+        assert_eq!((remaining.tokens_owned, remaining.cost_basis), (700, 69_069));
+        assert_eq!(USTBillStorage::get(&ustbill.id).unwrap().tokens_sold, 700);
+
+        // 0.5% of 29_863 is 149.315, leaving 29_714 for the seller
+        let fee = TransactionStorage::get_by_user(&principal)
+            .into_iter()
+            .find(|transaction| {
+                transaction.transaction_type == TransactionType::Fee
+                    && transaction.holding_id.as_deref() == Some(sold.id.as_str())
+            })
+            .unwrap();
+        assert_eq!(fee.amount, 149);
+
+        let user = UserStorage::get(&principal).unwrap();
+        assert_eq!(settlement::cash_balance(&user), cash_before + 29_714);
+        assert_eq!(user.total_invested, 69_069);
+        // The gain is what the seller keeps over cost: 29_714 - 29_601
+        assert_eq!(user.total_yield_earned, 113);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::calendar::{self, DayCount};
+use crate::errors::Result;
+use crate::money::{Rate, Rounding};
+use crate::pricing;
+use crate::storage::*;
+use crate::types::*;
+
+/// Reprices every tradable bill from the latest stored Treasury rate and marks
+/// its active holdings to market. The bill's `annual_yield` is what its holders bought
+/// at and keeps accruing; the market yield is stored beside it for display.
+pub fn reprice_ustbills(now: u64) -> Result<Vec<MarketPriceUpdate>> {
+    let mut updates = Vec::new();
+
+    let tradable = USTBillStorage::get_all().into_iter().filter(|ustbill| {
+        matches!(
+            ustbill.status,
+            USTBillStatus::Active | USTBillStatus::SoldOut
+        ) && ustbill.maturity_date > now
+    });
+
+    for mut ustbill in tradable {
+        let Some(rate) = latest_rate_for(&ustbill) else {
+            continue;
+        };
+
+        let days_to_maturity = pricing::days_to_maturity(&ustbill, now);
+        let discount_rate = treasury_discount_rate(&rate)?;
+        let Ok(bill_price) =
+            pricing::discount_price(ustbill.face_value, discount_rate, days_to_maturity)
+        else {
+            continue;
+        };
+
+        let new_price_per_token = crate::pro_rata(bill_price, 1, ustbill.total_tokens)?;
+        let market_yield = pricing::discount_to_investment_yield(discount_rate, days_to_maturity)?;
+
+        let update = MarketPriceUpdate {
+            ustbill_id: ustbill.id.clone(),
+            cusip: ustbill.cusip.clone(),
+            rate_date: rate.rate_date.clone(),
+            previous_price_per_token: ustbill.market_price_per_token,
+            new_price_per_token,
+            previous_market_yield: ustbill.market_yield.map(Rate::to_f64),
+            new_market_yield: market_yield.to_f64(),
+        };
+
+        if update.previous_price_per_token != Some(new_price_per_token) {
+            ic_cdk::println!(
+                "Repriced UST Bill {} ({}): {:?} -> {} cents/token, market yield {:?} -> {:.4}",
+                ustbill.id,
+                ustbill.cusip,
+                update.previous_price_per_token,
+                new_price_per_token,
+                update.previous_market_yield,
+                update.new_market_yield
+            );
+        }
+
+        // A bill and its holdings are repriced together or not at all
+        let mut batch = WriteBatch::new();
+        for mut holding in HoldingStorage::get_by_ustbill(&ustbill.id) {
+            if holding.is_active() {
+                holding.current_value =
+                    crate::pro_rata(bill_price, holding.tokens_owned, ustbill.total_tokens)?;
+                batch.update_holding(holding);
+            }
+        }
+
+        ustbill.market_price_per_token = Some(new_price_per_token);
+        ustbill.market_yield = Some(market_yield);
+        ustbill.last_priced_at = Some(now);
+        ustbill.updated_at = now;
+        batch.update_ustbill(ustbill);
+        batch.commit()?;
+
+        updates.push(update);
+    }
+
+    Ok(updates)
+}
+
+/// Bank discount rate a bill is quoted at: the latest Treasury rate for it, or else the
+/// rate implied by its offering price over its full term
+pub fn discount_rate_for(ustbill: &USTBill) -> Result<Rate> {
+    match latest_rate_for(ustbill) {
+        Some(rate) => treasury_discount_rate(&rate),
+        None => pricing::discount_rate(
+            ustbill.face_value,
+            ustbill.purchase_price,
+            DayCount::days(
+                calendar::settlement_date(ustbill.created_at),
+                calendar::payment_date(ustbill.maturity_date),
+            ),
+        ),
+    }
+}
+
+/// Fiscal Data quotes rates in percent
+fn treasury_discount_rate(rate: &TreasuryRate) -> Result<Rate> {
+    let percent = Rate::from_f64(rate.rate)?;
+    Rate::from_ratio(percent.nanos(), 100 * Rate::SCALE, Rounding::HalfEven)
+}
+
+/// Latest rate for the bill's CUSIP, falling back to the latest rate for its tenor
+fn latest_rate_for(ustbill: &USTBill) -> Option<TreasuryRate> {
+    TreasuryRateStorage::get_by_cusip(&ustbill.cusip)
+        .into_iter()
+        .max_by(|a, b| a.rate_date.cmp(&b.rate_date))
+        .or_else(|| {
+            // bill_type is e.g. "13-week", matching "13-Week Treasury Bill"
+            TreasuryRateStorage::get_latest_by_desc(&format!("{} Treasury Bill", ustbill.bill_type))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    const NOW: u64 = 1_750_000_000;
+
+    #[test]
+    fn test_repricing_keeps_the_purchase_yield() {
+        let owner = Principal::from_slice(&[1]);
+        UserStorage::insert(User {
+            principal: owner,
+            email: "investor@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![],
+            total_invested: 98_670,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        })
+        .unwrap();
+        let purchase_yield = Rate::from_nanos(54_065_221);
+        let ustbill = USTBill {
+            id: generate_id(),
+            cusip: "912796RF8".to_string(),
+            face_value: 100_000,
+            purchase_price: 98_670,
+            maturity_date: NOW + 91 * calendar::SECONDS_PER_DAY,
+            annual_yield: purchase_yield,
+            total_tokens: 1_000,
+            tokens_sold: 1_000,
+            status: USTBillStatus::SoldOut,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        let holding = TokenHolding {
+            id: generate_id(),
+            user_principal: owner,
+            ustbill_id: ustbill.id.clone(),
+            tokens_owned: 1_000,
+            purchase_price_per_token: 98,
+            cost_basis: 98_670,
+            purchase_date: NOW,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 98_670,
+            projected_yield: 0,
+            yield_paid: 0,
+        };
+        HoldingStorage::insert(holding.clone()).unwrap();
+        TreasuryRateStorage::insert(TreasuryRate {
+            record_date: "2025-06-15".to_string(),
+            security_type: "Bill".to_string(),
+            security_desc: "13-Week Treasury Bill".to_string(),
+            rate_date: "2025-06-15".to_string(),
+            rate: 4.0,
+            cusip: ustbill.cusip.clone(),
+        })
+        .unwrap();
+
+        let update = reprice_ustbills(NOW).unwrap().remove(0);
+        assert_eq!(update.previous_market_yield, None);
+
+        // Holders keep accruing at the yield they bought at; the market yield is shown beside it
+        let repriced = USTBillStorage::get(&ustbill.id).unwrap();
+        assert_eq!(repriced.annual_yield, purchase_yield);
+        let market_yield = repriced.market_yield.unwrap();
+        assert_ne!(market_yield, purchase_yield);
+        assert_eq!(market_yield.to_f64(), update.new_market_yield);
+
+        // $1000 at a 4% discount for 91 days, actual/360
+        let marked = HoldingStorage::get(&holding.id).unwrap();
+        assert_eq!(marked.current_value, 98_989);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::accounting;
+use crate::calendar;
+use crate::errors::Result;
+use crate::icrc3::{self, BlockOp};
+use crate::orderbook;
+use crate::settlement;
+use crate::storage::*;
+use crate::types::*;
+
+/// Maximum number of holdings settled in a single message
+const MATURITY_BATCH_SIZE: usize = 100;
+
+/// Settles holdings of every bill past the day it pays, its maturity date rolled to the
+/// next business day, and marks the bill matured.
+///
+/// Settled holdings move to `Matured`, so a run that stops at the batch limit can
+/// simply be repeated; the bill itself only matures once none of its holdings are left.
+pub fn process_maturities(now: u64) -> Result<MaturityReport> {
+    let mut report = MaturityReport::default();
+    let mut budget = MATURITY_BATCH_SIZE;
+
+    let due: Vec<USTBill> = USTBillStorage::get_all()
+        .into_iter()
+        .filter(|ustbill| {
+            matches!(
+                ustbill.status,
+                USTBillStatus::Active | USTBillStatus::SoldOut
+            ) && calendar::payment_date(ustbill.maturity_date) <= now
+        })
+        .collect();
+
+    for mut ustbill in due {
+        // No further trading once the bill has reached maturity
+        orderbook::cancel_orders_for_ustbill(&ustbill.id)?;
+
+        let holdings: Vec<TokenHolding> = HoldingStorage::get_by_ustbill(&ustbill.id)
+            .into_iter()
+            .filter(|holding| holding.is_active())
+            .collect();
+
+        if holdings.len() > budget {
+            for holding in holdings.into_iter().take(budget) {
+                report.total_paid += settle_holding(holding, &ustbill, now)?;
+                report.holdings_settled += 1;
+            }
+            report.pending = true;
+            break;
+        }
+
+        budget -= holdings.len();
+        for holding in holdings {
+            report.total_paid += settle_holding(holding, &ustbill, now)?;
+            report.holdings_settled += 1;
+        }
+
+        ustbill.status = USTBillStatus::Matured;
+        ustbill.updated_at = now;
+        USTBillStorage::update(ustbill)?;
+        report.bills_matured += 1;
+    }
+
+    Ok(report)
+}
+
+/// Redeems a holding at its pro-rata share of face value, returning the amount paid
+fn settle_holding(mut holding: TokenHolding, ustbill: &USTBill, now: u64) -> Result<u64> {
+    let (face_value, full_yield) = maturity_payout(&holding, ustbill)?;
+
+    // Flexible holdings already received part of their yield as periodic payouts
+    let paid_yield = holding.yield_paid.min(full_yield);
+    let payout = face_value - paid_yield;
+    let yield_amount = full_yield - paid_yield;
+    let purchase_value = holding.cost_basis;
+
+    let mut user = UserStorage::get(&holding.user_principal)?;
+    settlement::credit_cash(&mut user, payout);
+    user.total_invested = user.total_invested.saturating_sub(purchase_value);
+    user.total_yield_earned += yield_amount;
+    user.updated_at = now;
+
+    holding.status = HoldingStatus::Matured;
+    holding.current_value = payout;
+    holding.projected_yield = yield_amount;
+
+    let transaction = Transaction {
+        id: generate_id(),
+        user_principal: holding.user_principal,
+        transaction_type: TransactionType::YieldDistribution,
+        amount: payout,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: Some(holding.id.clone()),
+        timestamp: now,
+        status: TransactionStatus::Completed,
+        fees: 0,
+        description: format!(
+            "Maturity redemption of {} tokens from UST Bill {} (yield {})",
+            holding.tokens_owned, ustbill.id, yield_amount
+        ),
+    };
+
+    let (holding_owner, tokens_redeemed) = (holding.user_principal, holding.tokens_owned);
+    let (reference, ustbill_id) = (transaction.id.clone(), ustbill.id.clone());
+
+    let mut batch = WriteBatch::new();
+    batch.update_user(user);
+    batch.update_holding(holding);
+    batch.insert_transaction(transaction);
+    batch.then(move || {
+        accounting::redemption(
+            holding_owner,
+            purchase_value,
+            face_value,
+            paid_yield,
+            &reference,
+            now,
+        )
+    });
+    batch.then(move || {
+        let redemption = BlockOp::Burn {
+            ustbill_id: &ustbill_id,
+            from: holding_owner,
+            amount: tokens_redeemed,
+        };
+        icrc3::append(redemption, now * 1_000_000_000)?;
+        Ok(())
+    });
+    batch.commit()?;
+
+    Ok(payout)
+}
+
+/// Face value owed for a holding at maturity and the yield over its purchase value
+pub fn maturity_payout(holding: &TokenHolding, ustbill: &USTBill) -> Result<(u64, u64)> {
+    let face_value = crate::pro_rata(
+        ustbill.face_value,
+        holding.tokens_owned,
+        ustbill.total_tokens,
+    )?;
+    Ok((face_value, face_value.saturating_sub(holding.cost_basis)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Rate;
+    use candid::Principal;
+
+    fn fixtures() -> (USTBill, TokenHolding) {
+        let ustbill = USTBill {
+            id: "1".to_string(),
+            cusip: "912796RF8".to_string(),
+            face_value: 100_000,
+            purchase_price: 95_000,
+            maturity_date: 0,
+            annual_yield: Rate::from_nanos(52_600_000),
+            total_tokens: 1_000,
+            tokens_sold: 250,
+            status: USTBillStatus::SoldOut,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        let holding = TokenHolding {
+            id: "2".to_string(),
+            user_principal: Principal::anonymous(),
+            ustbill_id: "1".to_string(),
+            tokens_owned: 250,
+            purchase_price_per_token: 95,
+            cost_basis: 23_750,
+            purchase_date: 0,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 23_750,
+            projected_yield: 0,
+            yield_paid: 0,
+        };
+        (ustbill, holding)
+    }
+
+    #[test]
+    fn test_maturity_payout() {
+        let (ustbill, holding) = fixtures();
+        assert_eq!(
+            maturity_payout(&holding, &ustbill).unwrap(),
+            (25_000, 1_250)
+        );
+    }
+
+    #[test]
+    fn test_process_maturities_redeems_holdings() {
+        let (ustbill, holding) = fixtures();
+        UserStorage::insert(User {
+            principal: holding.user_principal,
+            email: "investor@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![],
+            total_invested: 23_750,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        })
+        .unwrap();
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        HoldingStorage::insert(holding.clone()).unwrap();
+
+        let report = process_maturities(1_000_000).unwrap();
+        assert_eq!(
+            (
+                report.bills_matured,
+                report.holdings_settled,
+                report.total_paid
+            ),
+            (1, 1, 25_000)
+        );
+        assert_eq!(
+            USTBillStorage::get(&ustbill.id).unwrap().status,
+            USTBillStatus::Matured
+        );
+        assert_eq!(
+            HoldingStorage::get(&holding.id).unwrap().status,
+            HoldingStatus::Matured
+        );
+
+        let user = UserStorage::get(&holding.user_principal).unwrap();
+        assert_eq!(settlement::cash_balance(&user), 25_000);
+        assert_eq!((user.total_invested, user.total_yield_earned), (0, 1_250));
+    }
+
+    #[test]
+    fn test_paid_yield_follows_tokens_split_off_a_holding() {
+        let (alice, bob) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        for principal in [alice, bob] {
+            UserStorage::insert(User {
+                principal,
+                email: "investor@example.com".to_string(),
+                kyc_status: KYCStatus::Verified,
+                balances: vec![],
+                total_invested: 0,
+                total_yield_earned: 0,
+                created_at: 0,
+                updated_at: 0,
+                is_active: true,
+                phone_number: None,
+                country: "US".to_string(),
+            })
+            .unwrap();
+        }
+        let (ustbill, holding) = fixtures();
+        let ustbill = USTBill {
+            maturity_date: 1_800_000_000,
+            status: USTBillStatus::Active,
+            ..ustbill
+        };
+        let holding = TokenHolding {
+            user_principal: alice,
+            purchase_date: 1_700_000_000,
+            yield_option: YieldOption::Flexible,
+            ..holding
+        };
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        HoldingStorage::insert(holding.clone()).unwrap();
+
+        // A month of yield is paid out, then half the tokens go to bob
+        let now = 1_700_000_000 + 31 * calendar::SECONDS_PER_DAY;
+        let paid = crate::yield_distribution::distribute_yield(now)
+            .unwrap()
+            .total_paid_out;
+        assert!(paid > 0);
+        let half = TransferArg {
+            from_subaccount: None,
+            to: Account {
+                owner: bob,
+                subaccount: None,
+            },
+            amount: candid::Nat::from(125u64),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        crate::icrc::transfer(&ustbill.id, alice, half, now * 1_000_000_000).unwrap();
+        let split = &HoldingStorage::get_by_user(&bob)[0];
+        assert_eq!(split.yield_paid, paid / 2);
+        assert_eq!(
+            HoldingStorage::get(&holding.id).unwrap().yield_paid,
+            paid - paid / 2
+        );
+
+        // Each half is paid face value less the yield already paid on it
+        process_maturities(1_900_000_000).unwrap();
+        let cash = |principal| settlement::cash_balance(&UserStorage::get(&principal).unwrap());
+        assert_eq!(cash(alice), paid + 12_500 - (paid - paid / 2));
+        assert_eq!(cash(bob), 12_500 - paid / 2);
+    }
+}
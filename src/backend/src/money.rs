@@ -0,0 +1,334 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{BitcoinUSTBillsError, Result};
+
+/// Days in the year that annual rates accrue over
+pub const DAYS_PER_YEAR: u64 = 365;
+
+/// How a result that falls between two representable values is settled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    HalfEven, // Banker's rounding: ties go to the even neighbour
+    HalfUp,   // Ties go away from zero
+    Floor,    // Always down
+}
+
+/// Divides with the given rounding; the denominator must be positive
+fn div_round(numerator: u128, denominator: u128, rounding: Rounding) -> u128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    let round_up = match rounding {
+        Rounding::Floor => false,
+        Rounding::HalfUp => remainder * 2 >= denominator,
+        Rounding::HalfEven => {
+            remainder * 2 > denominator || (remainder * 2 == denominator && quotient % 2 == 1)
+        }
+    };
+    quotient + round_up as u128
+}
+
+fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| BitcoinUSTBillsError::ArithmeticOverflow)
+}
+
+/// A non-negative rate with nine decimal places; 5.26% is 52_600_000. Stored and sent
+/// over Candid as its nanounits.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize, Serialize,
+)]
+pub struct Rate(u64);
+
+impl Rate {
+    /// Units of a rate of 1 (100%)
+    pub const SCALE: u64 = 1_000_000_000;
+    pub const ZERO: Rate = Rate(0);
+    pub const ONE: Rate = Rate(Self::SCALE);
+
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Rate(nanos)
+    }
+
+    pub fn from_bps(basis_points: u64) -> Result<Self> {
+        basis_points
+            .checked_mul(Self::SCALE / 10_000)
+            .map(Rate)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    /// Converts a stored decimal rate such as 0.0526, to the nearest nanounit. Rates are
+    /// converted once, at the edge; everything after is exact.
+    pub fn from_f64(value: f64) -> Result<Self> {
+        if !value.is_finite() || value < 0.0 {
+            return Err(BitcoinUSTBillsError::InvalidYieldRate);
+        }
+        let nanos = (value * Self::SCALE as f64).round();
+        if nanos >= u64::MAX as f64 {
+            return Err(BitcoinUSTBillsError::ArithmeticOverflow);
+        }
+        Ok(Rate(nanos as u64))
+    }
+
+    /// `part / whole` as a rate
+    pub fn from_ratio(part: u64, whole: u64, rounding: Rounding) -> Result<Self> {
+        if whole == 0 {
+            return Err(BitcoinUSTBillsError::InvalidAmount);
+        }
+        let nanos = div_round(part as u128 * Self::SCALE as u128, whole as u128, rounding);
+        to_u64(nanos).map(Rate)
+    }
+
+    pub fn nanos(self) -> u64 {
+        self.0
+    }
+
+    /// For API fields that are still floats; never feed the result back into arithmetic
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::SCALE as f64
+    }
+
+    pub fn checked_add(self, other: Rate) -> Result<Rate> {
+        self.0
+            .checked_add(other.0)
+            .map(Rate)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    pub fn checked_sub(self, other: Rate) -> Result<Rate> {
+        self.0
+            .checked_sub(other.0)
+            .map(Rate)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    pub fn checked_mul(self, other: Rate, rounding: Rounding) -> Result<Rate> {
+        let nanos = div_round(
+            self.0 as u128 * other.0 as u128,
+            Self::SCALE as u128,
+            rounding,
+        );
+        to_u64(nanos).map(Rate)
+    }
+}
+
+/// An amount of US dollars in whole cents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Money(u64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_cents(cents: u64) -> Self {
+        Money(cents)
+    }
+
+    pub fn cents(self) -> u64 {
+        self.0
+    }
+
+    /// Parses `"1234.5"`, `"$1,234.56"` and the like. More than two decimal places is an
+    /// error rather than a silent rounding.
+    pub fn parse(amount: &str) -> Result<Self> {
+        let invalid = || BitcoinUSTBillsError::validation_error("Invalid amount format");
+        let cleaned: String = amount
+            .trim()
+            .trim_start_matches('$')
+            .chars()
+            .filter(|c| *c != ',')
+            .collect();
+        let (dollars, cents) = match cleaned.split_once('.') {
+            Some((dollars, cents)) => (dollars, cents),
+            None => (cleaned.as_str(), ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (dollars.is_empty() && cents.is_empty())
+            || cents.len() > 2
+            || !is_digits(dollars)
+            || !is_digits(cents)
+        {
+            return Err(invalid());
+        }
+
+        let dollars: u64 = if dollars.is_empty() {
+            0
+        } else {
+            dollars
+                .parse()
+                .map_err(|_| BitcoinUSTBillsError::ArithmeticOverflow)?
+        };
+        let cents: u64 = format!("{:0<2}", cents).parse().map_err(|_| invalid())?;
+        dollars
+            .checked_mul(100)
+            .and_then(|total| total.checked_add(cents))
+            .map(Money)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money> {
+        self.0
+            .checked_add(other.0)
+            .map(Money)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money> {
+        self.0
+            .checked_sub(other.0)
+            .map(Money)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Result<Money> {
+        self.0
+            .checked_mul(factor)
+            .map(Money)
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)
+    }
+
+    /// The amount times `rate`, e.g. a fee
+    pub fn mul_rate(self, rate: Rate, rounding: Rounding) -> Result<Money> {
+        let cents = div_round(
+            self.0 as u128 * rate.0 as u128,
+            Rate::SCALE as u128,
+            rounding,
+        );
+        to_u64(cents).map(Money)
+    }
+
+    /// The amount times `numerator / denominator`
+    pub fn mul_ratio(self, numerator: u64, denominator: u64, rounding: Rounding) -> Result<Money> {
+        if denominator == 0 {
+            return Err(BitcoinUSTBillsError::InvalidAmount);
+        }
+        let cents = div_round(
+            self.0 as u128 * numerator as u128,
+            denominator as u128,
+            rounding,
+        );
+        to_u64(cents).map(Money)
+    }
+
+    /// Simple interest on the amount at `annual_rate` over `days` of a `basis_days` year,
+    /// rounded once at the end
+    pub fn interest(
+        self,
+        annual_rate: Rate,
+        days: u64,
+        basis_days: u64,
+        rounding: Rounding,
+    ) -> Result<Money> {
+        if basis_days == 0 {
+            return Err(BitcoinUSTBillsError::InvalidAmount);
+        }
+        let numerator = (self.0 as u128)
+            .checked_mul(annual_rate.0 as u128)
+            .and_then(|product| product.checked_mul(days as u128))
+            .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+        let cents = div_round(
+            numerator,
+            Rate::SCALE as u128 * basis_days as u128,
+            rounding,
+        );
+        to_u64(cents).map(Money)
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "${}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding_modes() {
+        // 2.5, 3.5 and 2.6 cents, in tenths of a cent
+        let tenths = [25, 35, 26];
+        let round = |rounding| {
+            tenths
+                .iter()
+                .map(|tenths| {
+                    Money::from_cents(*tenths)
+                        .mul_ratio(1, 10, rounding)
+                        .unwrap()
+                        .cents()
+                })
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(round(Rounding::HalfEven), vec![2, 4, 3]);
+        assert_eq!(round(Rounding::HalfUp), vec![3, 4, 3]);
+        assert_eq!(round(Rounding::Floor), vec![2, 3, 2]);
+    }
+
+    #[test]
+    fn test_fee_and_interest_are_exact() {
+        let fee_rate = Rate::from_f64(0.005).unwrap();
+        assert_eq!(fee_rate, Rate::from_bps(50).unwrap());
+        // 0.5% of $98.67 is 49.335 cents
+        let cost = Money::from_cents(9_867);
+        assert_eq!(
+            cost.mul_rate(fee_rate, Rounding::HalfEven).unwrap().cents(),
+            49
+        );
+        // 0.5% of $97.00 is exactly 48.5 cents
+        let cost = Money::from_cents(9_700);
+        assert_eq!(
+            cost.mul_rate(fee_rate, Rounding::HalfEven).unwrap().cents(),
+            48
+        );
+        assert_eq!(
+            cost.mul_rate(fee_rate, Rounding::HalfUp).unwrap().cents(),
+            49
+        );
+        assert_eq!(
+            cost.mul_rate(fee_rate, Rounding::Floor).unwrap().cents(),
+            48
+        );
+
+        // 5.26% for 91 days on $1,000
+        let rate = Rate::from_f64(0.0526).unwrap();
+        assert_eq!(rate.nanos(), 52_600_000);
+        let interest = Money::from_cents(100_000)
+            .interest(rate, 91, DAYS_PER_YEAR, Rounding::Floor)
+            .unwrap();
+        assert_eq!(interest.cents(), 1_311); // 1311.45...
+
+        assert_eq!(
+            Rate::from_ratio(1, 3, Rounding::HalfEven).unwrap().nanos(),
+            333_333_333
+        );
+    }
+
+    #[test]
+    fn test_overflow_is_an_error() {
+        let max = Money::from_cents(u64::MAX);
+        assert!(matches!(
+            max.checked_add(Money::from_cents(1)),
+            Err(BitcoinUSTBillsError::ArithmeticOverflow)
+        ));
+        assert!(max.checked_mul(2).is_err());
+        assert!(max
+            .mul_rate(Rate::from_bps(20_000).unwrap(), Rounding::Floor)
+            .is_err());
+        assert!(Money::ZERO.checked_sub(Money::from_cents(1)).is_err());
+        assert!(Rate::from_f64(-0.01).is_err());
+        assert!(Rate::from_f64(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Money::parse("$1,234.56").unwrap().cents(), 123_456);
+        assert_eq!(Money::parse("0.1").unwrap().cents(), 10);
+        assert_eq!(Money::parse("19.99").unwrap().cents(), 1_999);
+        assert_eq!(Money::parse(".5").unwrap().cents(), 50);
+        assert_eq!(Money::parse("7").unwrap().cents(), 700);
+        assert!(Money::parse("1.005").is_err());
+        assert!(Money::parse("-1").is_err());
+        assert!(Money::parse("abc").is_err());
+        assert!(Money::parse("$").is_err());
+        assert_eq!(Money::from_cents(150).to_string(), "$1.50");
+    }
+}
@@ -0,0 +1,446 @@
+use candid::Reserved;
+use ic_cdk::call::Call;
+use ic_cdk::management_canister::{
+    http_request, transform_context_from_query, HttpHeader, HttpMethod, HttpRequestArgs,
+    HttpRequestResult, TransformArgs,
+};
+
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::storage::*;
+use crate::types::*;
+
+/// Name the BTC/USD price is reported under when no source is usable
+const PAIR: &str = "BTC/USD";
+
+/// Cycles attached to `get_exchange_rate`; the exchange rate canister refunds what it
+/// doesn't charge
+const XRC_CYCLES: u128 = 1_000_000_000;
+
+/// Upper bound on a price outcall response, which also bounds its cycle cost
+const MAX_RESPONSE_BYTES: u64 = 10_000;
+
+/// Name of the query method replicas use to normalise price outcall responses
+const TRANSFORM_METHOD: &str = "transform_price_response";
+
+const MAX_BPS: u64 = 10_000;
+
+impl PriceSource {
+    pub fn name(&self) -> String {
+        match self {
+            PriceSource::ExchangeRateCanister { .. } => "exchange-rate-canister".to_string(),
+            PriceSource::HttpsJson { name, .. } | PriceSource::Fixed { name, .. } => name.clone(),
+        }
+    }
+}
+
+/// Checks an oracle config before it is stored. Refreshes must come often enough that an
+/// accepted price can't go stale before the next one.
+pub fn validate_config(config: &OracleConfig) -> Result<()> {
+    if config.min_sources == 0 || config.min_sources as usize > config.sources.len() {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "min_sources must be between 1 and the number of sources",
+        ));
+    }
+    if config.max_deviation_bps == 0 || config.max_deviation_bps > MAX_BPS {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "max_deviation_bps must be between 1 and 10000",
+        ));
+    }
+    if config.refresh_interval_seconds == 0
+        || config.refresh_interval_seconds >= config.max_age_seconds
+    {
+        return Err(BitcoinUSTBillsError::validation_error(
+            "The refresh interval must be non-zero and shorter than max_age_seconds",
+        ));
+    }
+
+    for source in &config.sources {
+        match source {
+            PriceSource::ExchangeRateCanister { .. } => {}
+            PriceSource::HttpsJson { url, json_path, .. } => {
+                if url
+                    .strip_prefix("https://")
+                    .is_none_or(|rest| rest.is_empty())
+                {
+                    return Err(BitcoinUSTBillsError::validation_error(format!(
+                        "Price source {} must be an https:// URL",
+                        source.name()
+                    )));
+                }
+                if json_path.is_empty() {
+                    return Err(BitcoinUSTBillsError::validation_error(format!(
+                        "Price source {} has no JSON path",
+                        source.name()
+                    )));
+                }
+            }
+            PriceSource::Fixed { price_cents, .. } => {
+                if *price_cents == 0 {
+                    return Err(BitcoinUSTBillsError::validation_error(format!(
+                        "Price source {} is fixed at zero",
+                        source.name()
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Refuses an oracle price older than the configured window
+pub fn ensure_fresh(price: &AssetPrice, now: u64) -> Result<()> {
+    if now.saturating_sub(price.updated_at) > OracleConfigStorage::get().max_age_seconds {
+        return Err(BitcoinUSTBillsError::StalePrice(price.asset.clone()));
+    }
+    Ok(())
+}
+
+/// The last accepted BTC/USD price, flagged stale once older than the configured window
+pub fn btc_usd_price(now: u64) -> Result<BtcUsdPrice> {
+    let mut price = BtcUsdPriceStorage::get()
+        .ok_or_else(|| BitcoinUSTBillsError::AssetPriceUnavailable(PAIR.to_string()))?;
+    price.is_stale =
+        now.saturating_sub(price.updated_at) > OracleConfigStorage::get().max_age_seconds;
+    Ok(price)
+}
+
+/// Queries every source, aggregates the answers and, if enough sources agree, stores the
+/// price and reprices the assets that track BTC/USD. On failure the previous price is
+/// kept and goes stale on its own.
+pub async fn refresh_btc_usd_price(now: u64) -> Result<BtcUsdPrice> {
+    let config = OracleConfigStorage::get();
+
+    let mut observations = Vec::new();
+    let mut rejected = Vec::new();
+    for source in &config.sources {
+        match fetch(source, now).await {
+            Ok(observation) => observations.push(observation),
+            Err(e) => rejected.push(format!("{}: {}", source.name(), e)),
+        }
+    }
+
+    let price = aggregate(&config, observations, rejected, now)?;
+    for asset in &config.priced_assets {
+        AssetPriceStorage::update(AssetPrice {
+            asset: asset.clone(),
+            price_cents: price.price_cents,
+            updated_at: price.updated_at,
+        })?;
+    }
+    BtcUsdPriceStorage::update(price.clone())?;
+    Ok(price)
+}
+
+/// Drops observations older than the window, takes the median, discards sources further
+/// than `max_deviation_bps` from it and, if at least `min_sources` remain, returns the
+/// median of those
+pub fn aggregate(
+    config: &OracleConfig,
+    observations: Vec<SourcePrice>,
+    mut rejected: Vec<String>,
+    now: u64,
+) -> Result<BtcUsdPrice> {
+    let (fresh, stale): (Vec<_>, Vec<_>) = observations
+        .into_iter()
+        .partition(|o| now.saturating_sub(o.observed_at) <= config.max_age_seconds);
+    rejected.extend(
+        stale
+            .iter()
+            .map(|o| format!("{}: observed at {}, too old", o.source, o.observed_at)),
+    );
+
+    let accepted = match median(fresh.iter().map(|o| o.price_cents).collect()) {
+        Some(reference) => {
+            let (accepted, outliers): (Vec<_>, Vec<_>) = fresh
+                .into_iter()
+                .partition(|o| deviation_bps(o.price_cents, reference) <= config.max_deviation_bps);
+            rejected.extend(outliers.iter().map(|o| {
+                format!(
+                    "{}: {} cents is more than {} bps from the median of {}",
+                    o.source, o.price_cents, config.max_deviation_bps, reference
+                )
+            }));
+            accepted
+        }
+        None => Vec::new(),
+    };
+
+    if accepted.len() < config.min_sources as usize {
+        return Err(BitcoinUSTBillsError::external_api_error(format!(
+            "{} of {} required {} sources agree ({})",
+            accepted.len(),
+            config.min_sources,
+            PAIR,
+            rejected.join("; ")
+        )));
+    }
+
+    Ok(BtcUsdPrice {
+        price_cents: median(accepted.iter().map(|o| o.price_cents).collect()).unwrap_or(0),
+        updated_at: accepted.iter().map(|o| o.observed_at).min().unwrap_or(now),
+        sources: accepted,
+        rejected,
+        is_stale: false,
+    })
+}
+
+/// Middle value, or the mean of the two middle values; None for no values
+fn median(mut prices: Vec<u64>) -> Option<u64> {
+    prices.sort_unstable();
+    let mid = prices.len() / 2;
+    match prices.len() {
+        0 => None,
+        n if n % 2 == 1 => Some(prices[mid]),
+        _ => Some(((prices[mid - 1] as u128 + prices[mid] as u128) / 2) as u64),
+    }
+}
+
+/// Distance of `price` from `reference` in basis points of the reference
+fn deviation_bps(price: u64, reference: u64) -> u64 {
+    if reference == 0 {
+        return u64::MAX;
+    }
+    let distance = price.abs_diff(reference) as u128;
+    u64::try_from(distance * MAX_BPS as u128 / reference as u128).unwrap_or(u64::MAX)
+}
+
+async fn fetch(source: &PriceSource, now: u64) -> Result<SourcePrice> {
+    let (price_cents, observed_at) = match source {
+        PriceSource::ExchangeRateCanister { canister_id } => xrc_price(*canister_id).await?,
+        PriceSource::HttpsJson { url, json_path, .. } => {
+            (https_price(url.clone(), json_path).await?, now)
+        }
+        PriceSource::Fixed { price_cents, .. } => (*price_cents, now),
+    };
+
+    if price_cents == 0 {
+        return Err(BitcoinUSTBillsError::external_api_error("price is zero"));
+    }
+    Ok(SourcePrice {
+        source: source.name(),
+        price_cents,
+        observed_at,
+    })
+}
+
+/// BTC/USD from the exchange rate canister, with the time it was sampled
+async fn xrc_price(canister_id: candid::Principal) -> Result<(u64, u64)> {
+    let asset = |symbol: &str, class| XrcAsset {
+        symbol: symbol.to_string(),
+        class,
+    };
+    let request = GetExchangeRateRequest {
+        base_asset: asset("BTC", XrcAssetClass::Cryptocurrency),
+        quote_asset: asset("USD", XrcAssetClass::FiatCurrency),
+        timestamp: None,
+    };
+
+    let method = "get_exchange_rate";
+    let response = Call::unbounded_wait(canister_id, method)
+        .with_arg(request)
+        .with_cycles(XRC_CYCLES)
+        .await
+        .map_err(|e| {
+            BitcoinUSTBillsError::external_api_error(format!("{} failed: {}", method, e))
+        })?;
+    let result: std::result::Result<ExchangeRate, Reserved> = response.candid().map_err(|e| {
+        BitcoinUSTBillsError::external_api_error(format!("{} returned: {}", method, e))
+    })?;
+    let rate = result.map_err(|_| {
+        BitcoinUSTBillsError::external_api_error(format!("{} returned an error", method))
+    })?;
+
+    let scale = 10u128
+        .checked_pow(rate.metadata.decimals)
+        .ok_or_else(|| BitcoinUSTBillsError::external_api_error("rate decimals out of range"))?;
+    let price_cents = u64::try_from(rate.rate as u128 * 100 / scale)
+        .map_err(|_| BitcoinUSTBillsError::external_api_error("rate out of range"))?;
+    Ok((price_cents, rate.timestamp))
+}
+
+/// Reads the price at `json_path` of an HTTPS JSON endpoint. The transform reduces the
+/// body to the price in cents so that every replica sees the same response.
+async fn https_price(url: String, json_path: &[String]) -> Result<u64> {
+    let context = serde_json::to_vec(json_path)
+        .map_err(|e| BitcoinUSTBillsError::SerializationError(e.to_string()))?;
+    let request = HttpRequestArgs {
+        url,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        method: HttpMethod::GET,
+        headers: vec![HttpHeader {
+            name: "Accept".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: None,
+        transform: Some(transform_context_from_query(
+            TRANSFORM_METHOD.to_string(),
+            context,
+        )),
+    };
+
+    let response = http_request(&request).await.map_err(|e| {
+        BitcoinUSTBillsError::http_request_error(format!("HTTP request failed: {}", e))
+    })?;
+    if response.status != 200u32 {
+        return Err(BitcoinUSTBillsError::http_request_error(format!(
+            "returned status {}",
+            response.status
+        )));
+    }
+
+    std::str::from_utf8(&response.body)
+        .ok()
+        .and_then(|body| body.parse::<u64>().ok())
+        .ok_or_else(|| BitcoinUSTBillsError::external_api_error("no price in the response"))
+}
+
+/// Strips headers and replaces the body with the price in cents, or an empty body when
+/// there is no price at the path carried in the transform context
+pub fn transform(args: TransformArgs) -> HttpRequestResult {
+    let mut response = args.response;
+    response.headers.clear();
+    response.body = serde_json::from_slice::<Vec<String>>(&args.context)
+        .ok()
+        .and_then(|json_path| extract_price_cents(&response.body, &json_path).ok())
+        .map(|price_cents| price_cents.to_string().into_bytes())
+        .unwrap_or_default();
+    response
+}
+
+/// The price at `json_path`, a number or numeric string in dollars, in cents. Exchanges
+/// quote to the cent and move between replica requests, so the price is rounded to the
+/// whole dollar for the replicas to agree.
+pub fn extract_price_cents(body: &[u8], json_path: &[String]) -> Result<u64> {
+    let not_found = || BitcoinUSTBillsError::external_api_error("no price at the JSON path");
+    let json: serde_json::Value = serde_json::from_slice(body).map_err(|_| not_found())?;
+
+    let mut value = &json;
+    for key in json_path {
+        value = match key.parse::<usize>() {
+            Ok(index) if value.is_array() => value.get(index),
+            _ => value.get(key.as_str()),
+        }
+        .ok_or_else(not_found)?;
+    }
+
+    let dollars = match value {
+        serde_json::Value::String(text) => text.parse::<f64>().ok(),
+        number => number.as_f64(),
+    }
+    .filter(|dollars| dollars.is_finite() && *dollars > 0.0)
+    .ok_or_else(not_found)?;
+
+    Ok(dollars.round() as u64 * 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn observation(source: &str, price_cents: u64, observed_at: u64) -> SourcePrice {
+        SourcePrice {
+            source: source.to_string(),
+            price_cents,
+            observed_at,
+        }
+    }
+
+    fn path(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_aggregate_takes_the_median_of_agreeing_sources() {
+        let config = OracleConfig::default();
+        let observations = vec![
+            observation("exchange-rate-canister", 6_000_000, NOW - 60),
+            observation("coinbase", 6_010_000, NOW),
+            observation("kraken", 6_500_000, NOW),
+            observation("stale", 6_005_000, NOW - config.max_age_seconds - 1),
+        ];
+
+        let price = aggregate(&config, observations, vec![], NOW).unwrap();
+        // Kraken is 8% above the median of the three fresh sources and is discarded
+        assert_eq!(price.price_cents, 6_005_000);
+        assert_eq!(price.updated_at, NOW - 60);
+        assert_eq!(price.sources.len(), 2);
+        assert_eq!(price.rejected.len(), 2);
+        assert!(price.rejected.iter().any(|r| r.starts_with("kraken")));
+        assert!(price.rejected.iter().any(|r| r.starts_with("stale")));
+
+        // One agreeing source is below the quorum
+        let observations = vec![
+            observation("coinbase", 6_000_000, NOW),
+            observation("kraken", 7_000_000, NOW),
+        ];
+        assert!(matches!(
+            aggregate(&config, observations, vec!["xrc: down".to_string()], NOW),
+            Err(BitcoinUSTBillsError::ExternalAPIError(_))
+        ));
+        assert!(aggregate(&config, vec![], vec![], NOW).is_err());
+    }
+
+    #[test]
+    fn test_stale_prices_are_refused() {
+        let price = AssetPrice {
+            asset: "ckBTC".to_string(),
+            price_cents: 6_000_000,
+            updated_at: NOW,
+        };
+        let max_age = OracleConfigStorage::get().max_age_seconds;
+
+        assert!(ensure_fresh(&price, NOW + max_age).is_ok());
+        assert!(matches!(
+            ensure_fresh(&price, NOW + max_age + 1),
+            Err(BitcoinUSTBillsError::StalePrice(asset)) if asset == "ckBTC"
+        ));
+    }
+
+    #[test]
+    fn test_extract_price_cents() {
+        let coinbase = br#"{"data":{"amount":"60123.45","base":"BTC","currency":"USD"}}"#;
+        assert_eq!(
+            extract_price_cents(coinbase, &path(&["data", "amount"])).unwrap(),
+            6_012_300
+        );
+
+        let kraken = br#"{"error":[],"result":{"XXBTZUSD":{"c":["60123.60000","0.001"]}}}"#;
+        assert_eq!(
+            extract_price_cents(kraken, &path(&["result", "XXBTZUSD", "c", "0"])).unwrap(),
+            6_012_400
+        );
+
+        assert!(extract_price_cents(br#"{"price": 60000}"#, &path(&["price"])).is_ok());
+        assert!(extract_price_cents(br#"{"price": "n/a"}"#, &path(&["price"])).is_err());
+        assert!(extract_price_cents(br#"{"price": -1}"#, &path(&["price"])).is_err());
+        assert!(extract_price_cents(b"<html>", &path(&["price"])).is_err());
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = OracleConfig::default();
+        assert!(validate_config(&config).is_ok());
+
+        config.min_sources = 4;
+        assert!(validate_config(&config).is_err());
+
+        config = OracleConfig {
+            refresh_interval_seconds: config.max_age_seconds,
+            ..OracleConfig::default()
+        };
+        assert!(validate_config(&config).is_err());
+
+        config = OracleConfig {
+            sources: vec![PriceSource::HttpsJson {
+                name: "plain".to_string(),
+                url: "http://example.com/btc".to_string(),
+                json_path: path(&["price"]),
+            }],
+            min_sources: 1,
+            ..OracleConfig::default()
+        };
+        assert!(validate_config(&config).is_err());
+    }
+}
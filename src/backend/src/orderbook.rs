@@ -0,0 +1,678 @@
+use candid::Principal;
+use std::cmp::Ordering;
+
+use crate::accounting;
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::icrc3::{self, BlockOp};
+use crate::money::Money;
+use crate::settlement;
+use crate::storage::*;
+use crate::types::*;
+
+/// Validates, escrows and stores a new limit order, then matches it against the book
+pub fn place_order(owner: Principal, request: OrderRequest) -> Result<Order> {
+    let mut user = UserStorage::get(&owner)?;
+    if !user.is_eligible_for_trading() {
+        return Err(BitcoinUSTBillsError::TradingNotAllowed);
+    }
+
+    if request.price_per_token == 0 {
+        return Err(BitcoinUSTBillsError::InvalidOrderPrice);
+    }
+
+    if request.token_amount == 0 {
+        return Err(BitcoinUSTBillsError::InvalidTokenAmount);
+    }
+
+    let current_time = get_current_timestamp();
+    if request
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= current_time)
+    {
+        return Err(BitcoinUSTBillsError::InvalidDate);
+    }
+
+    let ustbill = USTBillStorage::get(&request.ustbill_id)?;
+    match ustbill.status {
+        USTBillStatus::Active | USTBillStatus::SoldOut => {}
+        USTBillStatus::Matured => return Err(BitcoinUSTBillsError::USTBillMatured),
+        USTBillStatus::Cancelled => return Err(BitcoinUSTBillsError::USTBillCancelled),
+    }
+    if ustbill.maturity_date <= current_time {
+        return Err(BitcoinUSTBillsError::USTBillMatured);
+    }
+
+    // Stage every write, fills included, so a failure leaves nothing half-applied
+    let mut batch = WriteBatch::new();
+    let mut escrowed_amount = 0;
+    let mut holding_id = None;
+
+    match request.side {
+        OrderSide::Bid => {
+            // Lock the full bid value in the order until it fills or is released
+            escrowed_amount = request
+                .price_per_token
+                .checked_mul(request.token_amount)
+                .ok_or(BitcoinUSTBillsError::InvalidAmount)?;
+
+            settlement::debit_cash(&mut user, escrowed_amount)?;
+            user.updated_at = current_time;
+            batch.update_user(user);
+        }
+        OrderSide::Ask => {
+            let id = request.holding_id.clone().ok_or_else(|| {
+                BitcoinUSTBillsError::validation_error("Ask orders require a holding")
+            })?;
+            let holding = HoldingStorage::get(&id)?;
+
+            if holding.user_principal != owner {
+                return Err(BitcoinUSTBillsError::Unauthorized);
+            }
+
+            if !holding.is_active() || holding.ustbill_id != request.ustbill_id {
+                return Err(BitcoinUSTBillsError::InvalidHoldingData);
+            }
+
+            if available_tokens(&holding, current_time) < request.token_amount {
+                return Err(BitcoinUSTBillsError::InsufficientTokens);
+            }
+
+            holding_id = Some(id);
+        }
+    }
+
+    let order = Order {
+        id: generate_id(),
+        ustbill_id: request.ustbill_id,
+        owner,
+        side: request.side,
+        price_per_token: request.price_per_token,
+        token_amount: request.token_amount,
+        filled_amount: 0,
+        holding_id,
+        escrowed_amount,
+        status: OrderStatus::Open,
+        created_at: current_time,
+        updated_at: current_time,
+        expires_at: request.expires_at,
+    };
+
+    batch.insert_order(order.clone());
+    let (escrowed_amount, reference) = (order.escrowed_amount, order.id.clone());
+    batch.then(move || accounting::escrow(owner, escrowed_amount, &reference, current_time));
+
+    let order = match_order(&mut batch, order, &ustbill)?;
+    batch.commit()?;
+    Ok(order)
+}
+
+/// Cancels an open order on behalf of its owner and releases its escrow
+pub fn cancel_order(owner: Principal, order_id: &str) -> Result<Order> {
+    let order = OrderStorage::get(order_id)?;
+
+    if order.owner != owner {
+        return Err(BitcoinUSTBillsError::Unauthorized);
+    }
+
+    if !order.is_open() {
+        return Err(BitcoinUSTBillsError::OrderNotOpen);
+    }
+
+    let mut batch = WriteBatch::new();
+    let order = release_order(&mut batch, order, OrderStatus::Cancelled)?;
+    batch.commit()?;
+    Ok(order)
+}
+
+/// Marks every open order past its expiry as expired, returning how many were released
+pub fn expire_orders(now: u64) -> Result<u64> {
+    let mut batch = WriteBatch::new();
+    let mut expired = 0;
+    for order in OrderStorage::get_open() {
+        if order.is_expired(now) {
+            release_order(&mut batch, order, OrderStatus::Expired)?;
+            expired += 1;
+        }
+    }
+    batch.commit()?;
+    Ok(expired)
+}
+
+/// Cancels every open order on a bill, e.g. once it has matured
+pub fn cancel_orders_for_ustbill(ustbill_id: &str) -> Result<u64> {
+    let orders = OrderStorage::get_open_by_ustbill(ustbill_id);
+    let cancelled = orders.len() as u64;
+    let mut batch = WriteBatch::new();
+    for order in orders {
+        release_order(&mut batch, order, OrderStatus::Cancelled)?;
+    }
+    batch.commit()?;
+    Ok(cancelled)
+}
+
+/// Aggregates the open, unexpired orders of a bill into price levels
+pub fn snapshot(ustbill_id: &str, depth: usize) -> OrderBookSnapshot {
+    let now = get_current_timestamp();
+    let orders: Vec<Order> = OrderStorage::get_open_by_ustbill(ustbill_id)
+        .into_iter()
+        .filter(|order| !order.is_expired(now))
+        .collect();
+
+    OrderBookSnapshot {
+        ustbill_id: ustbill_id.to_string(),
+        bids: aggregate_levels(&orders, OrderSide::Bid, depth),
+        asks: aggregate_levels(&orders, OrderSide::Ask, depth),
+        timestamp: now,
+    }
+}
+
+/// Tokens of a holding not yet committed to open, unexpired ask orders
+pub fn available_tokens(holding: &TokenHolding, now: u64) -> u64 {
+    holding
+        .tokens_owned
+        .saturating_sub(OrderStorage::reserved_tokens(&holding.id, now))
+}
+
+/// Crosses a freshly placed order with resting orders using price-time priority
+fn match_order(batch: &mut WriteBatch, mut taker: Order, ustbill: &USTBill) -> Result<Order> {
+    let now = get_current_timestamp();
+    let maker_side = match taker.side {
+        OrderSide::Bid => OrderSide::Ask,
+        OrderSide::Ask => OrderSide::Bid,
+    };
+
+    let mut book: Vec<Order> = OrderStorage::get_open_by_ustbill(&taker.ustbill_id)
+        .into_iter()
+        .filter(|order| order.side == maker_side && order.owner != taker.owner)
+        .collect();
+    book.sort_by(priority);
+
+    for mut maker in book {
+        if taker.remaining() == 0 {
+            break;
+        }
+
+        if maker.is_expired(now) {
+            release_order(batch, maker, OrderStatus::Expired)?;
+            continue;
+        }
+
+        if !crosses(&taker, &maker) {
+            break;
+        }
+
+        // The maker may have lost trading eligibility since placing the order
+        if !batch.user(&maker.owner)?.is_eligible_for_trading() {
+            release_order(batch, maker, OrderStatus::Cancelled)?;
+            continue;
+        }
+
+        let token_amount = taker.remaining().min(maker.remaining());
+
+        // Asks can go stale if their holding was sold or redeemed in the meantime
+        let ask = match taker.side {
+            OrderSide::Bid => &maker,
+            OrderSide::Ask => &taker,
+        };
+        let seller_holding = match ask_holding(batch, ask, token_amount) {
+            Ok(holding) => holding,
+            Err(_) if maker.side == OrderSide::Ask => {
+                release_order(batch, maker, OrderStatus::Cancelled)?;
+                continue;
+            }
+            Err(_) => break,
+        };
+
+        let price_per_token = maker.price_per_token;
+        let (bid, ask) = match taker.side {
+            OrderSide::Bid => (&mut taker, &mut maker),
+            OrderSide::Ask => (&mut maker, &mut taker),
+        };
+        execute_fill(
+            batch,
+            bid,
+            ask,
+            seller_holding,
+            token_amount,
+            price_per_token,
+            ustbill,
+            now,
+        )?;
+        batch.update_order(maker);
+    }
+
+    batch.update_order(taker.clone());
+    Ok(taker)
+}
+
+/// Settles a single fill: moves tokens to the buyer and cents to the seller
+#[allow(clippy::too_many_arguments)]
+fn execute_fill(
+    batch: &mut WriteBatch,
+    bid: &mut Order,
+    ask: &mut Order,
+    mut seller_holding: TokenHolding,
+    token_amount: u64,
+    price_per_token: u64,
+    ustbill: &USTBill,
+    now: u64,
+) -> Result<()> {
+    let value = Money::from_cents(price_per_token)
+        .checked_mul(token_amount)?
+        .cents();
+    let config = PlatformConfigStorage::get();
+    let fees = crate::utils::calculate_fees(value, config.platform_fee_percentage)?;
+
+    // Buyer: consume escrow at the bid price and refund any price improvement
+    let mut buyer = batch.user(&bid.owner)?;
+    let escrow_used = Money::from_cents(bid.price_per_token)
+        .checked_mul(token_amount)?
+        .cents();
+    bid.escrowed_amount -= escrow_used;
+    settlement::credit_cash(&mut buyer, escrow_used - value);
+    buyer.total_invested += value;
+    buyer.updated_at = now;
+
+    // Seller: transfer tokens out of the holding and credit net proceeds
+    let mut seller = batch.user(&ask.owner)?;
+    let cost_basis = crate::pro_rata(
+        seller_holding.cost_basis,
+        token_amount,
+        seller_holding.tokens_owned,
+    )?;
+    let sold_value = crate::pro_rata(
+        seller_holding.current_value,
+        token_amount,
+        seller_holding.tokens_owned,
+    )?;
+    let sold_projected_yield = crate::pro_rata(
+        seller_holding.projected_yield,
+        token_amount,
+        seller_holding.tokens_owned,
+    )?;
+    // Yield already paid on the sold tokens went to the seller; the buyer starts afresh
+    let sold_yield_paid = crate::pro_rata(
+        seller_holding.yield_paid,
+        token_amount,
+        seller_holding.tokens_owned,
+    )?;
+    seller_holding.tokens_owned -= token_amount;
+    seller_holding.cost_basis -= cost_basis;
+    seller_holding.current_value -= sold_value;
+    seller_holding.projected_yield -= sold_projected_yield;
+    seller_holding.yield_paid -= sold_yield_paid;
+    if seller_holding.tokens_owned == 0 {
+        seller_holding.status = HoldingStatus::Sold;
+    }
+
+    settlement::credit_cash(&mut seller, value.saturating_sub(fees));
+    seller.total_invested = seller.total_invested.saturating_sub(cost_basis);
+    // The gain is what the seller keeps over cost, as on a sale back to the pool
+    seller.total_yield_earned += value.saturating_sub(fees).saturating_sub(cost_basis);
+    seller.updated_at = now;
+
+    let buyer_holding = TokenHolding {
+        id: generate_id(),
+        user_principal: bid.owner,
+        ustbill_id: ustbill.id.clone(),
+        tokens_owned: token_amount,
+        purchase_price_per_token: price_per_token,
+        cost_basis: value,
+        purchase_date: now,
+        // The tokens keep the yield schedule they were sold with, as on an ICRC transfer
+        yield_option: seller_holding.yield_option.clone(),
+        status: HoldingStatus::Active,
+        current_value: value,
+        projected_yield: crate::calculate_projected_yield(ustbill, value, now)?,
+        yield_paid: 0,
+    };
+
+    for order in [&mut *bid, &mut *ask] {
+        order.filled_amount += token_amount;
+        order.status = if order.remaining() == 0 {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        order.updated_at = now;
+    }
+
+    let purchase_transaction = Transaction {
+        id: generate_id(),
+        user_principal: bid.owner,
+        transaction_type: TransactionType::Purchase,
+        amount: value,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: Some(buyer_holding.id.clone()),
+        timestamp: now,
+        status: TransactionStatus::Completed,
+        fees: 0,
+        description: format!(
+            "Order book purchase of {} tokens from UST Bill {} (order {})",
+            token_amount, ustbill.id, bid.id
+        ),
+    };
+
+    let sale_transaction = Transaction {
+        id: generate_id(),
+        user_principal: ask.owner,
+        transaction_type: TransactionType::Sale,
+        amount: value,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: Some(seller_holding.id.clone()),
+        timestamp: now,
+        status: TransactionStatus::Completed,
+        fees,
+        description: format!(
+            "Order book sale of {} tokens from UST Bill {} (order {})",
+            token_amount, ustbill.id, ask.id
+        ),
+    };
+
+    let fee_transaction = Transaction {
+        id: generate_id(),
+        user_principal: ask.owner,
+        transaction_type: TransactionType::Fee,
+        amount: fees,
+        ustbill_id: Some(ustbill.id.clone()),
+        holding_id: Some(seller_holding.id.clone()),
+        timestamp: now,
+        status: TransactionStatus::Completed,
+        fees: 0,
+        description: "Platform fee".to_string(),
+    };
+
+    let (buyer_principal, seller_principal) = (bid.owner, ask.owner);
+    let reference = purchase_transaction.id.clone();
+    batch.update_user(buyer);
+    batch.update_user(seller);
+    batch.update_holding(seller_holding);
+    batch.insert_holding(buyer_holding);
+    batch.insert_transaction(purchase_transaction);
+    batch.insert_transaction(sale_transaction);
+    batch.insert_transaction(fee_transaction);
+    batch.then(move || {
+        accounting::fill(
+            buyer_principal,
+            seller_principal,
+            escrow_used,
+            value,
+            fees,
+            cost_basis,
+            &reference,
+            now,
+        )
+    });
+
+    let ustbill_id = ustbill.id.clone();
+    batch.then(move || {
+        let transfer = BlockOp::Transfer {
+            ustbill_id: &ustbill_id,
+            from: seller_principal,
+            to: buyer_principal,
+            spender: None,
+            amount: token_amount,
+            memo: None,
+            created_at_time: None,
+        };
+        icrc3::append_trade(transfer, seller_principal, fees, now * 1_000_000_000)?;
+        Ok(())
+    });
+    batch.then(move || {
+        TradingMetricsStorage::update_volume(value, now)?;
+        TradingMetricsStorage::update_price(price_per_token, now)
+    });
+
+    Ok(())
+}
+
+/// Loads the holding backing an ask and checks it can still deliver the tokens
+fn ask_holding(batch: &WriteBatch, ask: &Order, token_amount: u64) -> Result<TokenHolding> {
+    let holding_id = ask
+        .holding_id
+        .as_deref()
+        .ok_or(BitcoinUSTBillsError::InvalidHoldingData)?;
+    let holding = batch.holding(holding_id)?;
+
+    if holding.user_principal != ask.owner || !holding.is_active() {
+        return Err(BitcoinUSTBillsError::InvalidHoldingData);
+    }
+
+    if holding.tokens_owned < token_amount {
+        return Err(BitcoinUSTBillsError::InsufficientTokens);
+    }
+
+    Ok(holding)
+}
+
+/// Closes an order with the given status, refunding any escrow still held
+fn release_order(batch: &mut WriteBatch, mut order: Order, status: OrderStatus) -> Result<Order> {
+    let now = get_current_timestamp();
+    if order.escrowed_amount > 0 {
+        let mut user = batch.user(&order.owner)?;
+        settlement::credit_cash(&mut user, order.escrowed_amount);
+        user.updated_at = now;
+        batch.update_user(user);
+
+        let (owner, escrowed_amount, reference) =
+            (order.owner, order.escrowed_amount, order.id.clone());
+        batch.then(move || accounting::escrow_release(owner, escrowed_amount, &reference, now));
+        order.escrowed_amount = 0;
+    }
+
+    order.status = status;
+    order.updated_at = now;
+    batch.update_order(order.clone());
+
+    Ok(order)
+}
+
+/// Whether a taker order is willing to trade at the maker's price
+pub fn crosses(taker: &Order, maker: &Order) -> bool {
+    match taker.side {
+        OrderSide::Bid => taker.price_per_token >= maker.price_per_token,
+        OrderSide::Ask => taker.price_per_token <= maker.price_per_token,
+    }
+}
+
+/// Price-time priority: best price first, then the earliest order
+pub fn priority(a: &Order, b: &Order) -> Ordering {
+    let by_price = match a.side {
+        OrderSide::Bid => b.price_per_token.cmp(&a.price_per_token),
+        OrderSide::Ask => a.price_per_token.cmp(&b.price_per_token),
+    };
+    by_price
+        .then(a.created_at.cmp(&b.created_at))
+        .then(sequence(a).cmp(&sequence(b)))
+}
+
+/// Order ids come from the monotonic id counter, so they break ties within a second
+fn sequence(order: &Order) -> u64 {
+    order.id.parse().unwrap_or(u64::MAX)
+}
+
+fn aggregate_levels(orders: &[Order], side: OrderSide, depth: usize) -> Vec<OrderBookLevel> {
+    let mut side_orders: Vec<&Order> = orders.iter().filter(|order| order.side == side).collect();
+    side_orders.sort_by(|a, b| priority(a, b));
+
+    let mut levels: Vec<OrderBookLevel> = Vec::new();
+    for order in side_orders {
+        match levels.last_mut() {
+            Some(level) if level.price_per_token == order.price_per_token => {
+                level.token_amount += order.remaining();
+                level.order_count += 1;
+            }
+            _ => {
+                if levels.len() == depth {
+                    break;
+                }
+                levels.push(OrderBookLevel {
+                    price_per_token: order.price_per_token,
+                    token_amount: order.remaining(),
+                    order_count: 1,
+                });
+            }
+        }
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: &str, side: OrderSide, price: u64, amount: u64, created_at: u64) -> Order {
+        Order {
+            id: id.to_string(),
+            ustbill_id: "1".to_string(),
+            owner: Principal::anonymous(),
+            side,
+            price_per_token: price,
+            token_amount: amount,
+            filled_amount: 0,
+            holding_id: None,
+            escrowed_amount: 0,
+            status: OrderStatus::Open,
+            created_at,
+            updated_at: created_at,
+            expires_at: None,
+        }
+    }
+
+    fn user(principal: Principal) -> User {
+        User {
+            principal,
+            email: "trader@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![],
+            total_invested: 0,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fill_keeps_yield_option_and_nets_fees_from_gain() {
+        let now = 1_750_000_000;
+        let (buyer, seller) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        UserStorage::insert(user(buyer)).unwrap();
+        UserStorage::insert(User {
+            total_invested: 9_800,
+            ..user(seller)
+        })
+        .unwrap();
+
+        let ustbill = USTBill {
+            id: generate_id(),
+            cusip: "912796RF8".to_string(),
+            face_value: 100_000,
+            purchase_price: 98_000,
+            maturity_date: now + 91 * crate::calendar::SECONDS_PER_DAY,
+            annual_yield: crate::money::Rate::from_nanos(54_065_221),
+            total_tokens: 100,
+            tokens_sold: 10,
+            status: USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        let holding = TokenHolding {
+            id: generate_id(),
+            user_principal: seller,
+            ustbill_id: ustbill.id.clone(),
+            tokens_owned: 10,
+            purchase_price_per_token: 980,
+            cost_basis: 9_800,
+            purchase_date: now,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 9_800,
+            projected_yield: 0,
+            yield_paid: 0,
+        };
+        HoldingStorage::insert(holding.clone()).unwrap();
+
+        let mut bid = Order {
+            owner: buyer,
+            escrowed_amount: 9_900,
+            ..order("1", OrderSide::Bid, 990, 10, now)
+        };
+        let mut ask = Order {
+            owner: seller,
+            holding_id: Some(holding.id.clone()),
+            ..order("2", OrderSide::Ask, 990, 10, now)
+        };
+        let mut batch = WriteBatch::new();
+        execute_fill(&mut batch, &mut bid, &mut ask, holding, 10, 990, &ustbill, now).unwrap();
+        batch.commit().unwrap();
+
+        let bought = &HoldingStorage::get_by_user(&buyer)[0];
+        assert_eq!((bought.tokens_owned, bought.cost_basis), (10, 9_900));
+        assert_eq!(bought.yield_option, YieldOption::Maturity);
+
+        // 0.5% of 9_900 is 49.5, rounded to 50; the seller keeps 9_850 for a 9_800 cost
+        let seller = UserStorage::get(&seller).unwrap();
+        assert_eq!(settlement::cash_balance(&seller), 9_850);
+        assert_eq!((seller.total_invested, seller.total_yield_earned), (0, 50));
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let mut asks = [
+            order("3", OrderSide::Ask, 101, 5, 10),
+            order("2", OrderSide::Ask, 100, 5, 20),
+            order("1", OrderSide::Ask, 100, 5, 20),
+        ];
+        asks.sort_by(priority);
+        let ids: Vec<&str> = asks.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+
+        let mut bids = [
+            order("4", OrderSide::Bid, 99, 5, 10),
+            order("5", OrderSide::Bid, 100, 5, 30),
+        ];
+        bids.sort_by(priority);
+        assert_eq!(bids[0].id, "5");
+    }
+
+    #[test]
+    fn test_crosses() {
+        let bid = order("1", OrderSide::Bid, 100, 5, 0);
+        assert!(crosses(&bid, &order("2", OrderSide::Ask, 100, 5, 0)));
+        assert!(!crosses(&bid, &order("3", OrderSide::Ask, 101, 5, 0)));
+
+        let ask = order("4", OrderSide::Ask, 100, 5, 0);
+        assert!(crosses(&ask, &order("5", OrderSide::Bid, 102, 5, 0)));
+        assert!(!crosses(&ask, &order("6", OrderSide::Bid, 99, 5, 0)));
+    }
+
+    #[test]
+    fn test_aggregate_levels() {
+        let mut partially_filled = order("3", OrderSide::Ask, 100, 10, 0);
+        partially_filled.filled_amount = 4;
+        let orders = vec![
+            order("1", OrderSide::Ask, 101, 5, 0),
+            order("2", OrderSide::Ask, 100, 5, 0),
+            partially_filled,
+            order("4", OrderSide::Bid, 98, 5, 0),
+        ];
+
+        let asks = aggregate_levels(&orders, OrderSide::Ask, 10);
+        assert_eq!(asks.len(), 2);
+        assert_eq!(asks[0].price_per_token, 100);
+        assert_eq!(asks[0].token_amount, 11);
+        assert_eq!(asks[0].order_count, 2);
+
+        let top = aggregate_levels(&orders, OrderSide::Ask, 1);
+        assert_eq!(top.len(), 1);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::calendar::{self, DayCount};
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::money::{Money, Rate, Rounding};
+use crate::types::{FeeMode, PriceQuote, USTBill};
+use crate::utils;
+
+/// Bills are discounted actual/360
+pub const DISCOUNT_DAY_COUNT: DayCount = DayCount::Actual360;
+
+/// Investment (coupon-equivalent) yields are quoted actual/365
+pub const INVESTMENT_DAY_COUNT: DayCount = DayCount::Actual365;
+
+/// Price of a bill from its bank discount rate: face × (1 − rate × days / 360), to the
+/// nearest cent with ties to even. Refused when the discount would consume the face value.
+pub fn discount_price(face_value: u64, discount_rate: Rate, days_to_maturity: u64) -> Result<u64> {
+    let discount = Money::from_cents(face_value).interest(
+        discount_rate,
+        days_to_maturity,
+        DISCOUNT_DAY_COUNT.basis_days(),
+        Rounding::HalfEven,
+    )?;
+    if discount.cents() >= face_value && face_value > 0 {
+        return Err(BitcoinUSTBillsError::InvalidYieldRate);
+    }
+    Ok(face_value - discount.cents())
+}
+
+/// Bank discount rate implied by a price: (face − price) / face × 360 / days
+pub fn discount_rate(face_value: u64, price: u64, days_to_maturity: u64) -> Result<Rate> {
+    if price > face_value || days_to_maturity == 0 {
+        return Err(BitcoinUSTBillsError::InvalidAmount);
+    }
+    let discount = (face_value - price)
+        .checked_mul(DISCOUNT_DAY_COUNT.basis_days())
+        .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+    let scaled_face = face_value
+        .checked_mul(days_to_maturity)
+        .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+    Rate::from_ratio(discount, scaled_face, Rounding::HalfEven)
+}
+
+/// Investment yield of buying at `price` and receiving face value at maturity:
+/// (face − price) / price × basis / days
+pub fn investment_yield(
+    face_value: u64,
+    price: u64,
+    days_to_maturity: u64,
+    day_count: DayCount,
+) -> Result<Rate> {
+    if price == 0 || price > face_value || days_to_maturity == 0 {
+        return Err(BitcoinUSTBillsError::InvalidAmount);
+    }
+    let gain = (face_value - price)
+        .checked_mul(day_count.basis_days())
+        .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+    let scaled_price = price
+        .checked_mul(days_to_maturity)
+        .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+    Rate::from_ratio(gain, scaled_price, Rounding::HalfEven)
+}
+
+/// Investment yield implied by a bank discount rate: 365 × d / (360 − d × days), computed
+/// without pricing a particular face value
+pub fn discount_to_investment_yield(discount_rate: Rate, days_to_maturity: u64) -> Result<Rate> {
+    let discounted_days = discount_rate
+        .nanos()
+        .checked_mul(days_to_maturity)
+        .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+    let basis = DISCOUNT_DAY_COUNT.basis_days() * Rate::SCALE;
+    if discounted_days >= basis {
+        return Err(BitcoinUSTBillsError::InvalidYieldRate);
+    }
+    let numerator = discount_rate
+        .nanos()
+        .checked_mul(INVESTMENT_DAY_COUNT.basis_days())
+        .ok_or(BitcoinUSTBillsError::ArithmeticOverflow)?;
+    Rate::from_ratio(numerator, basis - discounted_days, Rounding::HalfEven)
+}
+
+/// Cost of `token_amount` tokens starting at token `first_token` (counting from zero) of a
+/// bill priced `total_price` and split into `total_tokens`. The first
+/// `total_price % total_tokens` tokens carry one cent more than the rest, so all the tokens
+/// add up to the total exactly.
+pub fn tokens_price(
+    total_price: u64,
+    total_tokens: u64,
+    first_token: u64,
+    token_amount: u64,
+) -> Result<u64> {
+    if total_tokens == 0 || token_amount == 0 {
+        return Err(BitcoinUSTBillsError::InvalidTokenAmount);
+    }
+    let end = first_token
+        .checked_add(token_amount)
+        .filter(|end| *end <= total_tokens)
+        .ok_or(BitcoinUSTBillsError::InsufficientTokens)?;
+
+    let base = Money::from_cents(total_price / total_tokens);
+    let remainder = total_price % total_tokens;
+    let extra_cents = remainder.min(end).saturating_sub(first_token);
+    Ok(base
+        .checked_mul(token_amount)?
+        .checked_add(Money::from_cents(extra_cents))?
+        .cents())
+}
+
+/// Most whole tokens the next buyer of a bill can get for `amount`, by `fee_mode`, at the
+/// remainder-allocated token prices of `tokens_price`
+pub fn affordable_tokens(
+    ustbill: &USTBill,
+    amount: u64,
+    fee_mode: FeeMode,
+    fee_rate: Rate,
+) -> Result<u64> {
+    let fits = |token_amount: u64| -> Result<bool> {
+        let cost = tokens_price(
+            ustbill.purchase_price,
+            ustbill.total_tokens,
+            ustbill.tokens_sold,
+            token_amount,
+        )?;
+        let spent = match fee_mode {
+            FeeMode::Inclusive => Money::from_cents(cost)
+                .checked_add(Money::from_cents(utils::calculate_fees(cost, fee_rate)?))?
+                .cents(),
+            FeeMode::Exclusive => cost,
+        };
+        Ok(spent <= amount)
+    };
+
+    // Cost only grows with the token count, so search for the last count that fits
+    let (mut low, mut high) = (0, ustbill.available_tokens());
+    while low < high {
+        let middle = low + (high - low).div_ceil(2);
+        if fits(middle)? {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    Ok(low)
+}
+
+/// Days a bill traded at `trade_time` is held: from T+1 settlement to the day it pays
+pub fn days_to_maturity(ustbill: &USTBill, trade_time: u64) -> u64 {
+    DayCount::days(
+        calendar::settlement_date(trade_time),
+        calendar::payment_date(ustbill.maturity_date),
+    )
+}
+
+/// Prices a bill by Treasury conventions for a trade at `as_of`, at `discount_rate`
+pub fn quote(ustbill: &USTBill, discount_rate: Rate, as_of: u64) -> Result<PriceQuote> {
+    let days_to_maturity = days_to_maturity(ustbill, as_of);
+    let price = discount_price(ustbill.face_value, discount_rate, days_to_maturity)?;
+    let investment_yield = if days_to_maturity > 0 {
+        investment_yield(
+            ustbill.face_value,
+            price,
+            days_to_maturity,
+            INVESTMENT_DAY_COUNT,
+        )?
+    } else {
+        Rate::ZERO
+    };
+    let next_token_cost = match ustbill.available_tokens() {
+        0 => None,
+        _ => Some(tokens_price(
+            ustbill.purchase_price,
+            ustbill.total_tokens,
+            ustbill.tokens_sold,
+            1,
+        )?),
+    };
+
+    Ok(PriceQuote {
+        ustbill_id: ustbill.id.clone(),
+        as_of,
+        settlement_date: calendar::settlement_date(as_of),
+        days_to_maturity,
+        discount_rate: discount_rate.to_f64(),
+        investment_yield: investment_yield.to_f64(),
+        price,
+        price_per_token: price / ustbill.total_tokens.max(1),
+        next_token_cost,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discount_price() {
+        // 13-week bill at a 5.26% discount: $1000 * (1 - 0.0526 * 91 / 360) = $986.70
+        let rate = Rate::from_f64(0.0526).unwrap();
+        assert_eq!(discount_price(100_000, rate, 91).unwrap(), 98_670);
+        assert_eq!(discount_price(100_000, rate, 0).unwrap(), 100_000);
+        assert!(discount_price(100_000, Rate::from_f64(4.0).unwrap(), 91).is_err());
+
+        // Back again, less the rounding to a whole cent, which reprices the same
+        let implied = discount_rate(100_000, 98_670, 91).unwrap();
+        assert_eq!(implied.nanos(), 52_615_385);
+        assert_eq!(discount_price(100_000, implied, 91).unwrap(), 98_670);
+    }
+
+    #[test]
+    fn test_investment_yield() {
+        let rate = Rate::from_f64(0.0526).unwrap();
+        let yield_rate = discount_to_investment_yield(rate, 91).unwrap();
+        assert_eq!(yield_rate.nanos(), 54_049_200);
+
+        // The same bill priced in cents yields within the cent's rounding
+        let from_price = investment_yield(100_000, 98_670, 91, INVESTMENT_DAY_COUNT).unwrap();
+        assert_eq!(from_price.nanos(), 54_065_221);
+        // On an actual/360 basis the same return is a lower rate
+        let actual_360 = investment_yield(100_000, 98_670, 91, DayCount::Actual360).unwrap();
+        assert!(actual_360 < from_price);
+    }
+
+    #[test]
+    fn test_affordable_tokens() {
+        let ustbill = USTBill {
+            id: "1".to_string(),
+            cusip: "912796RF8".to_string(),
+            face_value: 100_000,
+            purchase_price: 98_670,
+            maturity_date: 0,
+            annual_yield: Rate::from_nanos(52_600_000),
+            total_tokens: 1_000,
+            tokens_sold: 660,
+            status: crate::types::USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        let fee_rate = Rate::from_bps(50).unwrap();
+
+        // The remainder cent is on ten more tokens, then they cost 98 cents: $24.70 buys
+        // 10 + 15 for $24.60
+        assert_eq!(
+            affordable_tokens(&ustbill, 2_470, FeeMode::Exclusive, fee_rate).unwrap(),
+            25
+        );
+        // With the fee inside, 25 tokens come to $24.72, so 24 for $23.74
+        assert_eq!(
+            affordable_tokens(&ustbill, 2_470, FeeMode::Inclusive, fee_rate).unwrap(),
+            24
+        );
+        assert_eq!(
+            affordable_tokens(&ustbill, 98, FeeMode::Exclusive, fee_rate).unwrap(),
+            0
+        );
+        // Never more than are left
+        assert_eq!(
+            affordable_tokens(&ustbill, 1_000_000, FeeMode::Inclusive, fee_rate).unwrap(),
+            340
+        );
+    }
+
+    #[test]
+    fn test_token_prices_add_up_to_the_bill() {
+        // $986.70 over 7 tokens: $140.95 each with 5 cents left over
+        let total = 98_670;
+        let prices: Vec<u64> = (0..7)
+            .map(|token| tokens_price(total, 7, token, 1).unwrap())
+            .collect();
+        assert_eq!(
+            prices,
+            vec![14_096, 14_096, 14_096, 14_096, 14_096, 14_095, 14_095]
+        );
+        assert_eq!(prices.iter().sum::<u64>(), total);
+
+        // Buying in lots costs the same as buying one at a time
+        assert_eq!(
+            tokens_price(total, 7, 0, 3).unwrap() + tokens_price(total, 7, 3, 4).unwrap(),
+            total
+        );
+        assert_eq!(tokens_price(total, 7, 4, 2).unwrap(), 14_096 + 14_095);
+        assert!(matches!(
+            tokens_price(total, 7, 5, 3),
+            Err(BitcoinUSTBillsError::InsufficientTokens)
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+use candid::Principal;
+use sha2::{Digest, Sha256};
+
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::money::Money;
+use crate::storage::*;
+use crate::types::*;
+
+/// How long a quote holds its price
+pub const QUOTE_VALIDITY_SECONDS: u64 = 60;
+
+/// Prices `token_amount` tokens of a bill for `owner` and holds the price for
+/// `QUOTE_VALIDITY_SECONDS`. The quote id carries a signature over its terms.
+pub fn issue(
+    key: &[u8],
+    owner: Principal,
+    ustbill: &USTBill,
+    token_amount: u64,
+    pay_with: Option<String>,
+    now: u64,
+) -> Result<PurchaseQuote> {
+    let config = PlatformConfigStorage::get();
+    let (cost, fee) = crate::price_purchase(ustbill, token_amount, &config)?;
+    let total_cost = Money::from_cents(cost)
+        .checked_add(Money::from_cents(fee))?
+        .cents();
+
+    QuoteStorage::remove_expired(now);
+    let mut quote = PurchaseQuote {
+        id: generate_id(),
+        owner,
+        ustbill_id: ustbill.id.clone(),
+        token_amount,
+        pay_with,
+        cost,
+        fee,
+        total_cost,
+        quoted_at: now,
+        expires_at: now + QUOTE_VALIDITY_SECONDS,
+    };
+    quote.id = format!("{}-{}", quote.id, signature(key, &quote));
+    QuoteStorage::insert(quote.clone())?;
+    Ok(quote)
+}
+
+/// The quote `owner` may buy with now, provided it costs no more than `max_total_cost`
+pub fn redeemable(
+    key: &[u8],
+    quote_id: &str,
+    owner: Principal,
+    max_total_cost: u64,
+    now: u64,
+) -> Result<PurchaseQuote> {
+    let quote = QuoteStorage::get(quote_id)?;
+    // Someone else's quote, or one whose terms no longer match its signature, is no quote
+    if quote.owner != owner || !is_authentic(key, &quote) {
+        return Err(BitcoinUSTBillsError::QuoteNotFound);
+    }
+    if quote.expires_at <= now {
+        return Err(BitcoinUSTBillsError::QuoteExpired);
+    }
+    if quote.total_cost > max_total_cost {
+        return Err(BitcoinUSTBillsError::PriceLimitExceeded);
+    }
+    Ok(quote)
+}
+
+/// Hex HMAC-SHA256 of the quote's terms, its id holding just the sequence number
+fn signature(key: &[u8], quote: &PurchaseQuote) -> String {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(candid::encode_one(quote).unwrap())
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize();
+    hex::encode(outer)
+}
+
+fn is_authentic(key: &[u8], quote: &PurchaseQuote) -> bool {
+    let Some((sequence, signed)) = quote.id.split_once('-') else {
+        return false;
+    };
+    let terms = PurchaseQuote {
+        id: sequence.to_string(),
+        ..quote.clone()
+    };
+    signature(key, &terms) == signed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Rate;
+
+    #[test]
+    fn test_quotes_hold_their_terms() {
+        let key = [7u8; 32];
+        let owner = Principal::from_slice(&[1]);
+        let ustbill = USTBill {
+            id: "quoted-bill".to_string(),
+            cusip: "912796RF8".to_string(),
+            face_value: 100_000,
+            purchase_price: 98_670,
+            maturity_date: 1_900_000_000,
+            annual_yield: Rate::from_nanos(52_600_000),
+            total_tokens: 1_000,
+            tokens_sold: 0,
+            status: USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        let now = 1_000;
+
+        // 100 tokens at 99 cents, the first 670 carrying the remainder cent, plus 0.5%
+        let quote = issue(&key, owner, &ustbill, 100, None, now).unwrap();
+        assert_eq!(
+            (quote.cost, quote.fee, quote.total_cost),
+            (9_900, 50, 9_950)
+        );
+        assert_eq!(quote.expires_at, now + QUOTE_VALIDITY_SECONDS);
+
+        let redeem = |quote_id: &str, owner, max_total_cost, now| {
+            redeemable(&key, quote_id, owner, max_total_cost, now)
+        };
+        assert!(redeem(&quote.id, owner, 9_950, now + 59).is_ok());
+        assert!(matches!(
+            redeem(&quote.id, owner, 9_949, now),
+            Err(BitcoinUSTBillsError::PriceLimitExceeded)
+        ));
+        assert!(matches!(
+            redeem(&quote.id, owner, 9_950, now + QUOTE_VALIDITY_SECONDS),
+            Err(BitcoinUSTBillsError::QuoteExpired)
+        ));
+        assert!(matches!(
+            redeem(&quote.id, Principal::anonymous(), 9_950, now),
+            Err(BitcoinUSTBillsError::QuoteNotFound)
+        ));
+        assert!(matches!(
+            redeemable(&[8u8; 32], &quote.id, owner, 9_950, now),
+            Err(BitcoinUSTBillsError::QuoteNotFound)
+        ));
+
+        // Terms changed behind the signature's back are rejected
+        QuoteStorage::insert(PurchaseQuote {
+            total_cost: 1,
+            ..quote.clone()
+        })
+        .unwrap();
+        assert!(matches!(
+            redeem(&quote.id, owner, 9_950, now),
+            Err(BitcoinUSTBillsError::QuoteNotFound)
+        ));
+
+        // Expired quotes are cleared out when the next one is issued
+        issue(&key, owner, &ustbill, 2, None, now + QUOTE_VALIDITY_SECONDS).unwrap();
+        assert!(QuoteStorage::get(&quote.id).is_err());
+    }
+}
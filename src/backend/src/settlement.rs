@@ -0,0 +1,1069 @@
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::call::Call;
+use serde::de::DeserializeOwned;
+
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::{accounting, oracle};
+use crate::storage::*;
+use crate::types::*;
+
+/// Largest supported ledger precision; 10^19 overflows u64
+const MAX_DECIMALS: u8 = 18;
+
+/// The ICRC-1 ledger calls behind deposits and withdrawals, so the flows can be
+/// exercised against a stand-in ledger
+#[allow(async_fn_in_trait)]
+pub trait Icrc1Ledger {
+    async fn fee(&self) -> Result<u64>;
+    async fn balance_of(&self, account: Account) -> Result<u64>;
+    /// Returns the block index of the transfer
+    async fn transfer(&self, arg: TransferArg) -> Result<Nat>;
+}
+
+/// A settlement asset's ledger. Calls wait unboundedly, so a failed call is known
+/// not to have moved funds.
+pub struct IcrcLedger {
+    canister_id: Principal,
+}
+
+impl IcrcLedger {
+    pub fn new(asset: &SettlementAsset) -> Self {
+        Self {
+            canister_id: asset.ledger_canister_id,
+        }
+    }
+}
+
+impl Icrc1Ledger for IcrcLedger {
+    async fn fee(&self) -> Result<u64> {
+        let method = "icrc1_fee";
+        let fee: Nat = call(Call::unbounded_wait(self.canister_id, method), method).await?;
+        nat_to_u64(&fee)
+    }
+
+    async fn balance_of(&self, account: Account) -> Result<u64> {
+        let method = "icrc1_balance_of";
+        let balance: Nat = call(
+            Call::unbounded_wait(self.canister_id, method).with_arg(account),
+            method,
+        )
+        .await?;
+        nat_to_u64(&balance)
+    }
+
+    async fn transfer(&self, arg: TransferArg) -> Result<Nat> {
+        let method = "icrc1_transfer";
+        let result: std::result::Result<Nat, TransferError> = call(
+            Call::unbounded_wait(self.canister_id, method).with_arg(arg),
+            method,
+        )
+        .await?;
+        result.map_err(|e| {
+            BitcoinUSTBillsError::ledger_error(format!("{} rejected: {:?}", method, e))
+        })
+    }
+}
+
+async fn call<R: CandidType + DeserializeOwned>(call: Call<'_, '_>, method: &str) -> Result<R> {
+    let response = call
+        .await
+        .map_err(|e| BitcoinUSTBillsError::ledger_error(format!("{} failed: {}", method, e)))?;
+    response
+        .candid()
+        .map_err(|e| BitcoinUSTBillsError::ledger_error(format!("{} returned: {}", method, e)))
+}
+
+fn nat_to_u64(value: &Nat) -> Result<u64> {
+    u64::try_from(&value.0)
+        .map_err(|_| BitcoinUSTBillsError::ledger_error(format!("amount {} exceeds u64", value)))
+}
+
+/// Looks up a configured settlement asset by symbol
+pub fn asset(symbol: &str) -> Result<SettlementAsset> {
+    SettlementConfigStorage::get()
+        .assets
+        .into_iter()
+        .find(|asset| asset.symbol == symbol)
+        .ok_or_else(|| BitcoinUSTBillsError::UnknownAsset(symbol.to_string()))
+}
+
+/// Checks a settlement config before it is stored. The cash asset must be pegged at a
+/// whole number of ledger units per cent, so cents convert to it exactly.
+pub fn validate_config(config: &SettlementConfig) -> Result<()> {
+    for (i, asset) in config.assets.iter().enumerate() {
+        if asset.symbol.is_empty() || config.assets[..i].iter().any(|a| a.symbol == asset.symbol) {
+            return Err(BitcoinUSTBillsError::validation_error(
+                "Settlement asset symbols must be unique and non-empty",
+            ));
+        }
+        if asset.decimals > MAX_DECIMALS {
+            return Err(BitcoinUSTBillsError::validation_error(format!(
+                "{} has more than {} decimals",
+                asset.symbol, MAX_DECIMALS
+            )));
+        }
+        if asset.pricing == (UsdPricing::Pegged { cents_per_unit: 0 }) {
+            return Err(BitcoinUSTBillsError::validation_error(format!(
+                "{} is pegged at zero",
+                asset.symbol
+            )));
+        }
+    }
+
+    match config.assets.iter().find(|a| a.symbol == config.cash_asset) {
+        Some(cash) => match cash.pricing {
+            UsdPricing::Pegged { cents_per_unit } if unit(cash).is_multiple_of(cents_per_unit) => {
+                Ok(())
+            }
+            _ => Err(BitcoinUSTBillsError::validation_error(
+                "The cash asset must be pegged at whole ledger units per cent",
+            )),
+        },
+        None => Err(BitcoinUSTBillsError::UnknownAsset(
+            config.cash_asset.clone(),
+        )),
+    }
+}
+
+/// Ledger units per whole token
+fn unit(asset: &SettlementAsset) -> u64 {
+    10u64.pow(asset.decimals as u32)
+}
+
+/// USD cents per whole token. Oracle prices older than the oracle's window are refused.
+pub fn usd_price(asset: &SettlementAsset, now: u64) -> Result<u64> {
+    match asset.pricing {
+        UsdPricing::Pegged { cents_per_unit } => Ok(cents_per_unit),
+        UsdPricing::Oracle => {
+            let price = AssetPriceStorage::get(&asset.symbol)
+                .filter(|price| price.price_cents > 0)
+                .ok_or_else(|| BitcoinUSTBillsError::AssetPriceUnavailable(asset.symbol.clone()))?;
+            oracle::ensure_fresh(&price, now)?;
+            Ok(price.price_cents)
+        }
+    }
+}
+
+/// USD value of `amount` ledger units in cents, rounded down
+pub fn usd_value(asset: &SettlementAsset, amount: u64, now: u64) -> Result<u64> {
+    crate::pro_rata(amount, usd_price(asset, now)?, unit(asset))
+}
+
+/// Ledger units worth `cents`, rounded up
+pub fn units_for(asset: &SettlementAsset, cents: u64, now: u64) -> Result<u64> {
+    let price_cents = usd_price(asset, now)? as u128;
+    let units = (cents as u128 * unit(asset) as u128).div_ceil(price_cents);
+    u64::try_from(units).map_err(|_| BitcoinUSTBillsError::InvalidAmount)
+}
+
+/// The cash asset's symbol and ledger units per cent
+fn cash_asset() -> (String, u64) {
+    let config = SettlementConfigStorage::get();
+    let cash = config
+        .assets
+        .iter()
+        .find(|asset| asset.symbol == config.cash_asset)
+        .expect("settlement config is validated before it is stored");
+    match cash.pricing {
+        UsdPricing::Pegged { cents_per_unit } => (cash.symbol.clone(), unit(cash) / cents_per_unit),
+        UsdPricing::Oracle => unreachable!("the cash asset is validated to be pegged"),
+    }
+}
+
+/// Whether `symbol` is the cash asset, which the journal books in cents
+pub fn is_cash(symbol: &str) -> bool {
+    SettlementConfigStorage::get().cash_asset == symbol
+}
+
+/// `amount` ledger units of `symbol` as the journal books them: in cents for the cash
+/// asset, whose movements are kept to whole cents, and as they are for any other
+fn booked(symbol: &str, amount: u64) -> u64 {
+    if is_cash(symbol) {
+        amount / cash_asset().1
+    } else {
+        amount
+    }
+}
+
+/// Cash balance in cents
+pub fn cash_balance(user: &User) -> u64 {
+    let (symbol, units_per_cent) = cash_asset();
+    user.balance(&symbol) / units_per_cent
+}
+
+pub fn credit_cash(user: &mut User, cents: u64) {
+    let (symbol, units_per_cent) = cash_asset();
+    user.credit(&symbol, cents * units_per_cent);
+}
+
+pub fn debit_cash(user: &mut User, cents: u64) -> Result<()> {
+    let (symbol, units_per_cent) = cash_asset();
+    if !user.debit(&symbol, cents * units_per_cent) {
+        return Err(BitcoinUSTBillsError::InsufficientFunds);
+    }
+    Ok(())
+}
+
+/// Debits `cents` worth of `symbol` from the wallet, returning the ledger units taken.
+/// Assets other than cash are converted at their USD price, rounding against the payer.
+pub fn debit_value(user: &mut User, symbol: &str, cents: u64, now: u64) -> Result<u64> {
+    let asset = asset(symbol)?;
+    let units = units_for(&asset, cents, now)?;
+    if !user.debit(symbol, units) {
+        return Err(BitcoinUSTBillsError::InsufficientFunds);
+    }
+    Ok(units)
+}
+
+/// Subaccount of the canister that receives a user's deposits on every ledger: the
+/// length-prefixed principal bytes, zero-padded to 32 bytes
+pub fn deposit_subaccount(owner: &Principal) -> Vec<u8> {
+    let bytes = owner.as_slice();
+    let mut subaccount = vec![0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..=bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+pub fn deposit_account(canister: Principal, owner: &Principal) -> Account {
+    Account {
+        owner: canister,
+        subaccount: Some(deposit_subaccount(owner)),
+    }
+}
+
+/// Asks the ckBTC minter for the BTC address that mints into the user's deposit subaccount
+pub async fn btc_deposit_address(canister: Principal, owner: &Principal) -> Result<String> {
+    let minter = SettlementConfigStorage::get()
+        .assets
+        .into_iter()
+        .find_map(|asset| asset.minter_canister_id)
+        .ok_or(BitcoinUSTBillsError::PlatformConfigurationError)?;
+    let args = GetBtcAddressArgs {
+        owner: Some(canister),
+        subaccount: Some(deposit_subaccount(owner)),
+    };
+
+    let method = "get_btc_address";
+    call(Call::unbounded_wait(minter, method).with_arg(args), method).await
+}
+
+/// Sweeps the user's deposit subaccount on the asset's ledger into the canister's custody
+/// account and credits the swept amount to their balance of that asset.
+///
+/// A second notification racing the first finds the subaccount empty at the ledger, so a
+/// deposit is credited at most once.
+pub async fn notify_deposit<L: Icrc1Ledger>(
+    ledger: &L,
+    canister: Principal,
+    owner: Principal,
+    asset: &SettlementAsset,
+    now: u64,
+) -> Result<DepositReceipt> {
+    UserStorage::get(&owner)?;
+
+    let account = deposit_account(canister, &owner);
+    let fee = ledger.fee().await?;
+    let balance = ledger.balance_of(account.clone()).await?;
+
+    // Cash is swept in whole cents; a fraction of a cent waits for the next deposit
+    let mut amount = balance.saturating_sub(fee);
+    if is_cash(&asset.symbol) {
+        amount -= amount % cash_asset().1;
+    }
+    let fees = usd_value(asset, fee, now)?;
+    let usd_value = usd_value(asset, amount, now)?;
+    if usd_value == 0 {
+        return Err(BitcoinUSTBillsError::InvalidAmount);
+    }
+
+    let block_index = ledger
+        .transfer(TransferArg {
+            from_subaccount: account.subaccount,
+            to: Account {
+                owner: canister,
+                subaccount: None,
+            },
+            amount: Nat::from(amount),
+            fee: Some(Nat::from(fee)),
+            memo: None,
+            created_at_time: Some(now * 1_000_000_000),
+        })
+        .await?;
+
+    // The wallet may have changed while the ledger calls were in flight
+    let mut user = UserStorage::get(&owner)?;
+    user.credit(&asset.symbol, amount);
+    user.updated_at = now;
+    UserStorage::update(user.clone())?;
+
+    let transaction_id = generate_id();
+    let booked = booked(&asset.symbol, amount);
+    accounting::deposit(owner, &asset.symbol, booked, &transaction_id, now)?;
+    TransactionStorage::insert(Transaction {
+        id: transaction_id,
+        user_principal: owner,
+        transaction_type: TransactionType::Deposit,
+        amount: usd_value,
+        ustbill_id: None,
+        holding_id: None,
+        timestamp: now,
+        status: TransactionStatus::Completed,
+        fees,
+        description: format!(
+            "{} deposit of {} ledger units (block {})",
+            asset.symbol, amount, block_index
+        ),
+    })?;
+
+    Ok(DepositReceipt {
+        asset: asset.symbol.clone(),
+        block_index,
+        amount,
+        usd_value,
+        balance: user.balance(&asset.symbol),
+    })
+}
+
+/// Locks `amount` ledger units of the asset in the owner's wallet and records a pending
+/// withdrawal to `to`. Cash withdrawals are whole cents. Requests worth less than the
+/// policy's auto-approve limit are paid out straight away; larger ones wait for
+/// `approve_withdrawal`.
+pub async fn request_withdrawal<L: Icrc1Ledger>(
+    ledger: &L,
+    owner: Principal,
+    asset: &SettlementAsset,
+    amount: u64,
+    to: Account,
+    now: u64,
+) -> Result<Withdrawal> {
+    let fee = ledger.fee().await?;
+    if amount <= fee || (is_cash(&asset.symbol) && !amount.is_multiple_of(cash_asset().1)) {
+        return Err(BitcoinUSTBillsError::InvalidAmount);
+    }
+    let usd_value = usd_value(asset, amount, now)?;
+
+    // Lock before anything else can spend the same balance
+    let mut user = UserStorage::get(&owner)?;
+    if !user.debit(&asset.symbol, amount) {
+        return Err(BitcoinUSTBillsError::InsufficientFunds);
+    }
+    user.updated_at = now;
+    UserStorage::update(user)?;
+
+    let withdrawal = Withdrawal {
+        id: generate_id(),
+        owner,
+        asset: asset.symbol.clone(),
+        amount,
+        to,
+        usd_value,
+        status: WithdrawalStatus::PendingApproval,
+        requested_at: now,
+        updated_at: now,
+        reviewed_by: None,
+        block_index: None,
+        reason: None,
+    };
+    WithdrawalStorage::insert(withdrawal.clone())?;
+    let booked = booked(&asset.symbol, amount);
+    accounting::withdrawal(owner, &asset.symbol, booked, &withdrawal.id, now)?;
+    TransactionStorage::insert(Transaction {
+        id: withdrawal.id.clone(),
+        user_principal: owner,
+        transaction_type: TransactionType::Withdrawal,
+        amount: usd_value,
+        ustbill_id: None,
+        holding_id: None,
+        timestamp: now,
+        status: TransactionStatus::Pending,
+        fees: 0,
+        description: format!("{} withdrawal of {} ledger units", asset.symbol, amount),
+    })?;
+
+    if usd_value < WithdrawalPolicyStorage::get().auto_approve_limit {
+        return pay_out(ledger, withdrawal, None, now).await;
+    }
+    Ok(withdrawal)
+}
+
+/// Pays out a pending withdrawal on a Treasurer's approval. The requester can't approve
+/// their own withdrawal.
+pub async fn approve_withdrawal<L: Icrc1Ledger>(
+    ledger: &L,
+    withdrawal_id: &str,
+    approver: Principal,
+    now: u64,
+) -> Result<Withdrawal> {
+    let withdrawal = pending_withdrawal(withdrawal_id)?;
+    if withdrawal.owner == approver {
+        return Err(BitcoinUSTBillsError::SelfApproval);
+    }
+    pay_out(ledger, withdrawal, Some(approver), now).await
+}
+
+/// Rejects a pending withdrawal and refunds the wallet
+pub fn reject_withdrawal(
+    withdrawal_id: &str,
+    reviewer: Principal,
+    reason: String,
+    now: u64,
+) -> Result<Withdrawal> {
+    let mut withdrawal = pending_withdrawal(withdrawal_id)?;
+    withdrawal.reviewed_by = Some(reviewer);
+    withdrawal.reason = Some(reason);
+    close_unpaid(withdrawal, WithdrawalStatus::Rejected, now)
+}
+
+/// Cancels the owner's pending withdrawal and refunds the wallet
+pub fn cancel_withdrawal(owner: Principal, withdrawal_id: &str, now: u64) -> Result<Withdrawal> {
+    let withdrawal = pending_withdrawal(withdrawal_id)?;
+    if withdrawal.owner != owner {
+        return Err(BitcoinUSTBillsError::Unauthorized);
+    }
+    close_unpaid(withdrawal, WithdrawalStatus::Cancelled, now)
+}
+
+fn pending_withdrawal(withdrawal_id: &str) -> Result<Withdrawal> {
+    let withdrawal = WithdrawalStorage::get(withdrawal_id)?;
+    if withdrawal.status != WithdrawalStatus::PendingApproval {
+        return Err(BitcoinUSTBillsError::WithdrawalNotPending);
+    }
+    Ok(withdrawal)
+}
+
+/// Transfers the locked amount, less the ledger fee, out of custody. Marking the
+/// withdrawal Processing first keeps it from being cancelled or approved twice while the
+/// transfer is in flight; a failed transfer leaves it Failed and refunded.
+async fn pay_out<L: Icrc1Ledger>(
+    ledger: &L,
+    mut withdrawal: Withdrawal,
+    reviewer: Option<Principal>,
+    now: u64,
+) -> Result<Withdrawal> {
+    withdrawal.status = WithdrawalStatus::Processing;
+    withdrawal.reviewed_by = reviewer;
+    withdrawal.updated_at = now;
+    WithdrawalStorage::update(withdrawal.clone())?;
+
+    let transfer = async {
+        let fee = ledger.fee().await?;
+        if withdrawal.amount <= fee {
+            return Err(BitcoinUSTBillsError::InvalidAmount);
+        }
+        let block_index = ledger
+            .transfer(TransferArg {
+                from_subaccount: None,
+                to: withdrawal.to.clone(),
+                amount: Nat::from(withdrawal.amount - fee),
+                fee: Some(Nat::from(fee)),
+                memo: Some(withdrawal.id.clone().into_bytes()),
+                created_at_time: Some(now * 1_000_000_000),
+            })
+            .await?;
+        Ok((block_index, fee))
+    };
+
+    let (block_index, fee) = match transfer.await {
+        Ok(paid) => paid,
+        Err(e) => {
+            withdrawal.reason = Some(e.to_string());
+            return close_unpaid(withdrawal, WithdrawalStatus::Failed, now);
+        }
+    };
+
+    let mut transaction = TransactionStorage::get(&withdrawal.id)?;
+    transaction.status = TransactionStatus::Completed;
+    transaction.fees = crate::pro_rata(withdrawal.usd_value, fee, withdrawal.amount)?;
+    transaction.description = format!(
+        "{} withdrawal of {} ledger units (block {})",
+        withdrawal.asset,
+        withdrawal.amount - fee,
+        block_index
+    );
+    TransactionStorage::update(transaction)?;
+
+    withdrawal.status = WithdrawalStatus::Completed;
+    withdrawal.block_index = Some(block_index);
+    WithdrawalStorage::update(withdrawal.clone())?;
+    Ok(withdrawal)
+}
+
+/// Settles a withdrawal that paid nothing out: refunds the locked amount and closes its
+/// transaction as Failed or Cancelled
+fn close_unpaid(
+    mut withdrawal: Withdrawal,
+    status: WithdrawalStatus,
+    now: u64,
+) -> Result<Withdrawal> {
+    let mut user = UserStorage::get(&withdrawal.owner)?;
+    user.credit(&withdrawal.asset, withdrawal.amount);
+    user.updated_at = now;
+    UserStorage::update(user)?;
+    let booked = booked(&withdrawal.asset, withdrawal.amount);
+    accounting::withdrawal_refund(
+        withdrawal.owner,
+        &withdrawal.asset,
+        booked,
+        &withdrawal.id,
+        now,
+    )?;
+
+    let mut transaction = TransactionStorage::get(&withdrawal.id)?;
+    transaction.status = match status {
+        WithdrawalStatus::Failed => TransactionStatus::Failed,
+        _ => TransactionStatus::Cancelled,
+    };
+    TransactionStorage::update(transaction)?;
+
+    withdrawal.status = status;
+    withdrawal.updated_at = now;
+    WithdrawalStorage::update(withdrawal.clone())?;
+    Ok(withdrawal)
+}
+
+/// Pays `amount` of the fee revenue collected in `asset` from that asset's custody to the
+/// withdrawal policy's platform fee account. The amount is in cents for the cash asset and
+/// in ledger units for any other, and the ledger fee comes out of it. The fee account is
+/// debited before the transfer so concurrent withdrawals can't overdraw it, and credited
+/// back if the transfer fails.
+pub async fn withdraw_platform_fees<L: Icrc1Ledger>(
+    ledger: &L,
+    asset: &SettlementAsset,
+    amount: u64,
+    now: u64,
+) -> Result<FeeWithdrawal> {
+    let to = WithdrawalPolicyStorage::get()
+        .platform_fee_account
+        .ok_or_else(|| {
+            BitcoinUSTBillsError::ValidationError(
+                "No platform fee account is configured".to_string(),
+            )
+        })?;
+    let units = if is_cash(&asset.symbol) {
+        amount
+            .checked_mul(cash_asset().1)
+            .ok_or(BitcoinUSTBillsError::InvalidAmount)?
+    } else {
+        amount
+    };
+    let fee = ledger.fee().await?;
+    if units <= fee {
+        return Err(BitcoinUSTBillsError::InvalidAmount);
+    }
+    if amount > accounting::available_fees(&asset.symbol) {
+        return Err(BitcoinUSTBillsError::InsufficientFunds);
+    }
+
+    let id = generate_id();
+    accounting::fee_withdrawal(&asset.symbol, amount, &id, now)?;
+    let transfer = ledger
+        .transfer(TransferArg {
+            from_subaccount: None,
+            to: to.clone(),
+            amount: Nat::from(units - fee),
+            fee: Some(Nat::from(fee)),
+            memo: Some(id.clone().into_bytes()),
+            created_at_time: Some(now * 1_000_000_000),
+        })
+        .await;
+    let block_index = match transfer {
+        Ok(block_index) => block_index,
+        Err(e) => {
+            accounting::fee_withdrawal_refund(&asset.symbol, amount, &id, now)?;
+            return Err(e);
+        }
+    };
+
+    Ok(FeeWithdrawal {
+        id,
+        asset: asset.symbol.clone(),
+        amount,
+        to,
+        block_index,
+        remaining: accounting::available_fees(&asset.symbol),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    const NOW: u64 = 1_700_000_000;
+    const FEE: u64 = 10;
+
+    /// In-memory ICRC-1 ledger standing in for an asset's ledger
+    #[derive(Default)]
+    struct StandInLedger {
+        canister: Option<Principal>,
+        balances: RefCell<HashMap<(Principal, Vec<u8>), u64>>,
+        blocks: Cell<u64>,
+        reject_transfers: Cell<bool>,
+    }
+
+    impl StandInLedger {
+        fn key(account: &Account) -> (Principal, Vec<u8>) {
+            let subaccount = account.subaccount.clone().unwrap_or_else(|| vec![0; 32]);
+            (account.owner, subaccount)
+        }
+
+        fn mint(&self, account: &Account, amount: u64) {
+            *self
+                .balances
+                .borrow_mut()
+                .entry(Self::key(account))
+                .or_default() += amount;
+        }
+
+        fn balance(&self, account: &Account) -> u64 {
+            self.balances
+                .borrow()
+                .get(&Self::key(account))
+                .copied()
+                .unwrap_or(0)
+        }
+    }
+
+    impl Icrc1Ledger for StandInLedger {
+        async fn fee(&self) -> Result<u64> {
+            Ok(FEE)
+        }
+
+        async fn balance_of(&self, account: Account) -> Result<u64> {
+            Ok(self.balance(&account))
+        }
+
+        async fn transfer(&self, arg: TransferArg) -> Result<Nat> {
+            if self.reject_transfers.get() {
+                return Err(BitcoinUSTBillsError::ledger_error("TemporarilyUnavailable"));
+            }
+            let from = Account {
+                owner: self.canister.unwrap(),
+                subaccount: arg.from_subaccount,
+            };
+            let amount = u64::try_from(&arg.amount.0).unwrap();
+            if self.balance(&from) < amount + FEE {
+                return Err(BitcoinUSTBillsError::ledger_error("InsufficientFunds"));
+            }
+
+            *self
+                .balances
+                .borrow_mut()
+                .get_mut(&Self::key(&from))
+                .unwrap() -= amount + FEE;
+            self.mint(&arg.to, amount);
+            self.blocks.set(self.blocks.get() + 1);
+            Ok(Nat::from(self.blocks.get()))
+        }
+    }
+
+    fn principal(text: &str) -> Principal {
+        Principal::from_text(text).unwrap()
+    }
+
+    fn register(owner: Principal) {
+        UserStorage::insert(User {
+            principal: owner,
+            email: "investor@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![],
+            total_invested: 0,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        })
+        .unwrap();
+    }
+
+    /// A registered investor and a stand-in ckBTC ledger, with BTC at $60,000
+    fn setup() -> (StandInLedger, SettlementAsset, Principal, Principal) {
+        let canister = principal("rrkah-fqaaa-aaaaa-aaaaq-cai");
+        let investor = principal("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe");
+        register(investor);
+        AssetPriceStorage::update(AssetPrice {
+            asset: "ckBTC".to_string(),
+            price_cents: 6_000_000,
+            updated_at: NOW,
+        })
+        .unwrap();
+
+        let ledger = StandInLedger {
+            canister: Some(canister),
+            ..StandInLedger::default()
+        };
+        (ledger, asset("ckBTC").unwrap(), canister, investor)
+    }
+
+    #[tokio::test]
+    async fn test_deposit_is_credited_once() {
+        let (ledger, ckbtc, canister, investor) = setup();
+        let other = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        assert_ne!(deposit_subaccount(&investor), deposit_subaccount(&other));
+
+        // Nothing deposited yet
+        assert!(matches!(
+            notify_deposit(&ledger, canister, investor, &ckbtc, NOW).await,
+            Err(BitcoinUSTBillsError::InvalidAmount)
+        ));
+
+        ledger.mint(&deposit_account(canister, &investor), 100_000 + FEE);
+        let receipt = notify_deposit(&ledger, canister, investor, &ckbtc, NOW)
+            .await
+            .unwrap();
+
+        // 0.001 BTC at $60,000
+        assert_eq!(receipt.amount, 100_000);
+        assert_eq!(receipt.usd_value, 6_000);
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            100_000
+        );
+        assert_eq!(
+            ledger.balance(&Account {
+                owner: canister,
+                subaccount: None
+            }),
+            100_000
+        );
+
+        assert!(notify_deposit(&ledger, canister, investor, &ckbtc, NOW)
+            .await
+            .is_err());
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            100_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_withdrawal_is_refunded() {
+        let (ledger, ckbtc, canister, investor) = setup();
+        ledger.mint(&deposit_account(canister, &investor), 100_000 + FEE);
+        notify_deposit(&ledger, canister, investor, &ckbtc, NOW)
+            .await
+            .unwrap();
+        let destination = Account {
+            owner: investor,
+            subaccount: None,
+        };
+
+        assert!(matches!(
+            request_withdrawal(&ledger, investor, &ckbtc, 100_001, destination.clone(), NOW).await,
+            Err(BitcoinUSTBillsError::InsufficientFunds)
+        ));
+
+        // $30 is under the auto-approve limit, so the payout is attempted at once
+        ledger.reject_transfers.set(true);
+        let failed =
+            request_withdrawal(&ledger, investor, &ckbtc, 50_000, destination.clone(), NOW)
+                .await
+                .unwrap();
+        assert_eq!(failed.status, WithdrawalStatus::Failed);
+        assert!(failed.reason.is_some());
+        assert_eq!(
+            TransactionStorage::get(&failed.id).unwrap().status,
+            TransactionStatus::Failed
+        );
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            100_000
+        );
+
+        ledger.reject_transfers.set(false);
+        let paid = request_withdrawal(&ledger, investor, &ckbtc, 50_000, destination.clone(), NOW)
+            .await
+            .unwrap();
+        assert_eq!(paid.status, WithdrawalStatus::Completed);
+        assert_eq!(paid.usd_value, 3_000);
+        assert_eq!(paid.reviewed_by, None);
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            50_000
+        );
+        assert_eq!(ledger.balance(&destination), 50_000 - FEE);
+        assert_eq!(
+            TransactionStorage::get(&paid.id).unwrap().status,
+            TransactionStatus::Completed
+        );
+
+        // The journal follows the ckBTC wallet through the refund and the payout
+        let wallet = LedgerAccount::UserAsset(investor, "ckBTC".to_string());
+        assert_eq!(accounting::balance(&wallet).balance, -50_000);
+        accounting::reconcile().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_journal_reconciles_with_wallets() {
+        let (_, _, canister, investor) = setup();
+        let ckusdc = asset("ckUSDC").unwrap();
+        let ledger = StandInLedger {
+            canister: Some(canister),
+            ..StandInLedger::default()
+        };
+        let destination = Account {
+            owner: investor,
+            subaccount: None,
+        };
+
+        // $1.23 is swept; the rest of a cent waits in the deposit subaccount
+        ledger.mint(&deposit_account(canister, &investor), 1_234_567 + FEE);
+        let receipt = notify_deposit(&ledger, canister, investor, &ckusdc, NOW)
+            .await
+            .unwrap();
+        assert_eq!((receipt.amount, receipt.usd_value), (1_230_000, 123));
+        assert_eq!(ledger.balance(&deposit_account(canister, &investor)), 4_567);
+        accounting::reconcile().unwrap();
+
+        assert!(matches!(
+            request_withdrawal(&ledger, investor, &ckusdc, 15_000, destination, NOW).await,
+            Err(BitcoinUSTBillsError::InvalidAmount)
+        ));
+
+        // A wallet changed without a journal entry is reported
+        let mut user = UserStorage::get(&investor).unwrap();
+        user.credit("ckBTC", 1);
+        UserStorage::update(user).unwrap();
+        match accounting::reconcile() {
+            Err(BitcoinUSTBillsError::JournalDrift(drift)) => {
+                assert!(drift.starts_with("UserAsset/"), "{}", drift)
+            }
+            other => panic!("expected drift, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_large_withdrawal_needs_another_approver() {
+        let (ledger, ckbtc, canister, investor) = setup();
+        let treasurer = principal("rdmx6-jaaaa-aaaaa-aaadq-cai");
+        ledger.mint(&deposit_account(canister, &investor), 100_000 + FEE);
+        notify_deposit(&ledger, canister, investor, &ckbtc, NOW)
+            .await
+            .unwrap();
+        WithdrawalPolicyStorage::update(WithdrawalPolicy {
+            auto_approve_limit: 1_000,
+            ..WithdrawalPolicy::default()
+        })
+        .unwrap();
+        let destination = Account {
+            owner: investor,
+            subaccount: None,
+        };
+
+        // $30 waits for review with the funds locked, and the owner can take it back
+        let pending =
+            request_withdrawal(&ledger, investor, &ckbtc, 50_000, destination.clone(), NOW)
+                .await
+                .unwrap();
+        assert_eq!(pending.status, WithdrawalStatus::PendingApproval);
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            50_000
+        );
+        assert!(matches!(
+            cancel_withdrawal(treasurer, &pending.id, NOW),
+            Err(BitcoinUSTBillsError::Unauthorized)
+        ));
+        let cancelled = cancel_withdrawal(investor, &pending.id, NOW).unwrap();
+        assert_eq!(cancelled.status, WithdrawalStatus::Cancelled);
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            100_000
+        );
+        assert!(matches!(
+            approve_withdrawal(&ledger, &pending.id, treasurer, NOW).await,
+            Err(BitcoinUSTBillsError::WithdrawalNotPending)
+        ));
+
+        let pending =
+            request_withdrawal(&ledger, investor, &ckbtc, 50_000, destination.clone(), NOW)
+                .await
+                .unwrap();
+        assert!(matches!(
+            approve_withdrawal(&ledger, &pending.id, investor, NOW).await,
+            Err(BitcoinUSTBillsError::SelfApproval)
+        ));
+        let paid = approve_withdrawal(&ledger, &pending.id, treasurer, NOW)
+            .await
+            .unwrap();
+        assert_eq!(paid.status, WithdrawalStatus::Completed);
+        assert_eq!(paid.reviewed_by, Some(treasurer));
+        assert_eq!(ledger.balance(&destination), 50_000 - FEE);
+        assert!(matches!(
+            cancel_withdrawal(investor, &pending.id, NOW),
+            Err(BitcoinUSTBillsError::WithdrawalNotPending)
+        ));
+
+        let rejected = request_withdrawal(&ledger, investor, &ckbtc, 50_000, destination, NOW)
+            .await
+            .unwrap();
+        let rejected = reject_withdrawal(
+            &rejected.id,
+            treasurer,
+            "Unverified destination".to_string(),
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(rejected.status, WithdrawalStatus::Rejected);
+        assert_eq!(
+            TransactionStorage::get(&rejected.id).unwrap().status,
+            TransactionStatus::Cancelled
+        );
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            50_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_price_blocks_settlement() {
+        let (ledger, ckbtc, canister, investor) = setup();
+        ledger.mint(&deposit_account(canister, &investor), 100_000 + FEE);
+        let later = NOW + OracleConfigStorage::get().max_age_seconds + 1;
+
+        assert!(matches!(
+            notify_deposit(&ledger, canister, investor, &ckbtc, later).await,
+            Err(BitcoinUSTBillsError::StalePrice(_))
+        ));
+        // Nothing was swept, so the deposit settles once the price is fresh again
+        assert_eq!(
+            ledger.balance(&deposit_account(canister, &investor)),
+            100_000 + FEE
+        );
+        notify_deposit(&ledger, canister, investor, &ckbtc, NOW)
+            .await
+            .unwrap();
+
+        let destination = Account {
+            owner: investor,
+            subaccount: None,
+        };
+        assert!(matches!(
+            request_withdrawal(&ledger, investor, &ckbtc, 50_000, destination, later).await,
+            Err(BitcoinUSTBillsError::StalePrice(_))
+        ));
+        assert_eq!(
+            UserStorage::get(&investor).unwrap().balance("ckBTC"),
+            100_000
+        );
+
+        // Pegged assets never go stale
+        let ckusdc = asset("ckUSDC").unwrap();
+        assert_eq!(usd_value(&ckusdc, 1_000_000, later).unwrap(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_platform_fees_are_withdrawn_to_the_fee_account() {
+        let (ckbtc_ledger, ckbtc, canister, investor) = setup();
+        let ckusdc = asset("ckUSDC").unwrap();
+        let ledger = StandInLedger {
+            canister: Some(canister),
+            ..StandInLedger::default()
+        };
+        let treasury = Account {
+            owner: principal("rdmx6-jaaaa-aaaaa-aaadq-cai"),
+            subaccount: None,
+        };
+        let custody = Account {
+            owner: canister,
+            subaccount: None,
+        };
+        // $10 of fees held in ckUSDC custody, and a purchase paid with 0.01 ckBTC whose
+        // fee share stays in ckBTC custody
+        ledger.mint(&custody, 10_000_000);
+        ckbtc_ledger.mint(&custody, 1_000_000);
+        accounting::purchase(investor, 95_000, 1_000, None, "p1", NOW).unwrap();
+        accounting::deposit(investor, "ckBTC", 1_000_000, "d1", NOW).unwrap();
+        accounting::purchase(investor, 59_400, 600, Some(("ckBTC", 1_000_000)), "p2", NOW).unwrap();
+
+        assert!(matches!(
+            withdraw_platform_fees(&ledger, &ckusdc, 400, NOW).await,
+            Err(BitcoinUSTBillsError::ValidationError(_))
+        ));
+        WithdrawalPolicyStorage::update(WithdrawalPolicy {
+            platform_fee_account: Some(treasury.clone()),
+            ..WithdrawalPolicy::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            withdraw_platform_fees(&ledger, &ckusdc, 1_001, NOW).await,
+            Err(BitcoinUSTBillsError::InsufficientFunds)
+        ));
+
+        // A failed transfer leaves the revenue in the fee account
+        ledger.reject_transfers.set(true);
+        assert!(withdraw_platform_fees(&ledger, &ckusdc, 400, NOW)
+            .await
+            .is_err());
+        assert_eq!(accounting::available_fees("ckUSDC"), 1_000);
+
+        ledger.reject_transfers.set(false);
+        let withdrawal = withdraw_platform_fees(&ledger, &ckusdc, 400, NOW)
+            .await
+            .unwrap();
+        assert_eq!(withdrawal.remaining, 600);
+        assert_eq!(ledger.balance(&treasury), 4_000_000 - FEE);
+        assert_eq!(accounting::trial_balance().net, 0);
+
+        let report = accounting::fee_revenue_report("ckUSDC", NOW, NOW);
+        assert_eq!(report.total_earned, 1_000);
+        assert_eq!(report.total_withdrawn, 400);
+        assert_eq!(report.available, 600);
+
+        // The ckBTC fees are paid from ckBTC custody, in ledger units
+        assert_eq!(accounting::available_fees("ckBTC"), 10_000);
+        assert!(matches!(
+            withdraw_platform_fees(&ckbtc_ledger, &ckbtc, 10_001, NOW).await,
+            Err(BitcoinUSTBillsError::InsufficientFunds)
+        ));
+        let withdrawal = withdraw_platform_fees(&ckbtc_ledger, &ckbtc, 10_000, NOW)
+            .await
+            .unwrap();
+        assert_eq!(withdrawal.asset, "ckBTC");
+        assert_eq!(withdrawal.remaining, 0);
+        assert_eq!(ckbtc_ledger.balance(&treasury), 10_000 - FEE);
+        assert_eq!(ckbtc_ledger.balance(&custody), 990_000);
+        assert_eq!(accounting::available_fees("ckUSDC"), 600);
+        assert_eq!(accounting::trial_balance().net, 0);
+    }
+
+    #[test]
+    fn test_cash_and_conversion() {
+        let (_, _, _, investor) = setup();
+        let mut user = UserStorage::get(&investor).unwrap();
+
+        // $10 of cash is 10 ckUSDC
+        credit_cash(&mut user, 1_000);
+        assert_eq!(user.balance("ckUSDC"), 10_000_000);
+        assert_eq!(cash_balance(&user), 1_000);
+        assert!(debit_cash(&mut user, 1_001).is_err());
+
+        // $30 in ckBTC at $60,000 is 50,000 sats; a cent more rounds up
+        user.credit("ckBTC", 50_001);
+        assert_eq!(debit_value(&mut user, "ckBTC", 3_000, NOW).unwrap(), 50_000);
+        assert!(matches!(
+            debit_value(&mut user, "ckBTC", 1, NOW),
+            Err(BitcoinUSTBillsError::InsufficientFunds)
+        ));
+        assert_eq!(
+            debit_value(&mut user, "ckUSDC", 250, NOW).unwrap(),
+            2_500_000
+        );
+        assert!(matches!(
+            debit_value(&mut user, "ckETH", 1, NOW),
+            Err(BitcoinUSTBillsError::UnknownAsset(_))
+        ));
+
+        let mut config = SettlementConfig::default();
+        assert!(validate_config(&config).is_ok());
+        config.cash_asset = "ckBTC".to_string();
+        assert!(validate_config(&config).is_err());
+        config.cash_asset = "ckUSDC".to_string();
+        config.assets.push(config.assets[0].clone());
+        assert!(validate_config(&config).is_err());
+    }
+}
@@ -3,8 +3,10 @@ use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemor
 use ic_stable_structures::{Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 
 use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::money::Rate;
 use crate::types::*;
 
 // Memory management
@@ -20,11 +22,34 @@ const TREASURY_RATES_MEMORY_ID: MemoryId = MemoryId::new(5);
 const TRADING_METRICS_MEMORY_ID: MemoryId = MemoryId::new(6);
 const ID_COUNTER_MEMORY_ID: MemoryId = MemoryId::new(7);
 const VERIFIED_PURCHASES_LEDGER_MEMORY_ID: MemoryId = MemoryId::new(8);
+const ORDERS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const YIELD_DISTRIBUTIONS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const YIELD_ACCRUALS_MEMORY_ID: MemoryId = MemoryId::new(11);
+const RATE_SOURCE_MEMORY_ID: MemoryId = MemoryId::new(12);
+const RATE_FEED_STATUS_MEMORY_ID: MemoryId = MemoryId::new(13);
+const LEGACY_DATA_MEMORY_ID: MemoryId = MemoryId::new(14);
+const ROLES_MEMORY_ID: MemoryId = MemoryId::new(15);
+const FEATURE_FLAGS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const ALLOWANCES_MEMORY_ID: MemoryId = MemoryId::new(17);
+const BLOCKS_MEMORY_ID: MemoryId = MemoryId::new(18);
+const SETTLEMENT_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(19);
+const ASSET_PRICES_MEMORY_ID: MemoryId = MemoryId::new(20);
+const ORACLE_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(21);
+const BTC_USD_PRICE_MEMORY_ID: MemoryId = MemoryId::new(22);
+const WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(23);
+const WITHDRAWAL_POLICY_MEMORY_ID: MemoryId = MemoryId::new(24);
+const JOURNAL_MEMORY_ID: MemoryId = MemoryId::new(25);
+const ACCOUNT_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(26);
+const QUOTES_MEMORY_ID: MemoryId = MemoryId::new(27);
+const QUOTE_SIGNING_KEY_MEMORY_ID: MemoryId = MemoryId::new(28);
+const LEDGER_CALLS_MEMORY_ID: MemoryId = MemoryId::new(29);
 
 // Thread-local storage for memory manager and stable data structures
 thread_local! {
+    static STABLE_MEMORY: DefaultMemoryImpl = DefaultMemoryImpl::default();
+
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
+        MemoryManager::init(STABLE_MEMORY.with(|memory| memory.clone()))
     );
 
     static ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
@@ -49,6 +74,12 @@ thread_local! {
         )
     );
 
+    static ORDERS: RefCell<StableBTreeMap<String, Order, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ORDERS_MEMORY_ID))
+        )
+    );
+
     static TRANSACTIONS: RefCell<StableBTreeMap<String, Transaction, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(TRANSACTIONS_MEMORY_ID))
@@ -68,6 +99,57 @@ thread_local! {
         )
     );
 
+    static RATE_SOURCE: RefCell<Cell<RateSource, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RATE_SOURCE_MEMORY_ID)),
+            RateSource::TreasuryApi {
+                url: crate::treasury::DEFAULT_TREASURY_RATES_URL.to_string(),
+            }
+        )
+    );
+
+    static FEATURE_FLAGS: RefCell<Cell<FeatureFlags, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(FEATURE_FLAGS_MEMORY_ID)),
+            FeatureFlags::default()
+        )
+    );
+
+    static SETTLEMENT_CONFIG: RefCell<Cell<SettlementConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SETTLEMENT_CONFIG_MEMORY_ID)),
+            SettlementConfig::default()
+        )
+    );
+
+    static ORACLE_CONFIG: RefCell<Cell<OracleConfig, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ORACLE_CONFIG_MEMORY_ID)),
+            OracleConfig::default()
+        )
+    );
+
+    static BTC_USD_PRICE: RefCell<Cell<BtcUsdPrice, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(BTC_USD_PRICE_MEMORY_ID)),
+            BtcUsdPrice::default()
+        )
+    );
+
+    static WITHDRAWAL_POLICY: RefCell<Cell<WithdrawalPolicy, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(WITHDRAWAL_POLICY_MEMORY_ID)),
+            WithdrawalPolicy::default()
+        )
+    );
+
+    static RATE_FEED_STATUS: RefCell<Cell<RateFeedStatus, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RATE_FEED_STATUS_MEMORY_ID)),
+            RateFeedStatus::default()
+        )
+    );
+
     static TRADING_METRICS: RefCell<Cell<TradingMetrics, Memory>> = RefCell::new(
         Cell::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(TRADING_METRICS_MEMORY_ID)),
@@ -82,6 +164,93 @@ thread_local! {
         )
     );
 
+    static YIELD_DISTRIBUTIONS: RefCell<StableBTreeMap<u64, YieldDistribution, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(YIELD_DISTRIBUTIONS_MEMORY_ID))
+        )
+    );
+
+    // Holding ID -> timestamp yield has been accrued up to
+    static YIELD_ACCRUALS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(YIELD_ACCRUALS_MEMORY_ID))
+        )
+    );
+
+    static ROLES: RefCell<StableBTreeMap<Principal, RoleAssignment, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ROLES_MEMORY_ID))
+        )
+    );
+
+    // Bill token allowances keyed by "{ustbill_id}:{owner}:{spender}"
+    static ALLOWANCES: RefCell<StableBTreeMap<String, Allowance, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ALLOWANCES_MEMORY_ID))
+        )
+    );
+
+    // Latest market price per asset symbol
+    static ASSET_PRICES: RefCell<StableBTreeMap<String, AssetPrice, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ASSET_PRICES_MEMORY_ID))
+        )
+    );
+
+    static WITHDRAWALS: RefCell<StableBTreeMap<String, Withdrawal, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(WITHDRAWALS_MEMORY_ID))
+        )
+    );
+
+    static JOURNAL: RefCell<StableBTreeMap<u64, JournalEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(JOURNAL_MEMORY_ID))
+        )
+    );
+
+    // Running totals per account, keyed by `LedgerAccount::key`
+    static ACCOUNT_BALANCES: RefCell<StableBTreeMap<String, AccountBalance, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ACCOUNT_BALANCES_MEMORY_ID))
+        )
+    );
+
+    static QUOTES: RefCell<StableBTreeMap<String, PurchaseQuote, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(QUOTES_MEMORY_ID))
+        )
+    );
+
+    // Empty until the first quote is signed
+    static QUOTE_SIGNING_KEY: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(QUOTE_SIGNING_KEY_MEMORY_ID)),
+            Vec::new()
+        )
+    );
+
+    // Append-only ICRC-3 block log keyed by block index
+    static BLOCKS: RefCell<StableBTreeMap<u64, ICRC3Value, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(BLOCKS_MEMORY_ID))
+        )
+    );
+
+    // Recent ledger calls that set created_at_time, keyed "{created_at_time:020}:{call hash}",
+    // to the block that recorded them
+    static LEDGER_CALLS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LEDGER_CALLS_MEMORY_ID))
+        )
+    );
+
+    static LEGACY_DATA: RefCell<StableBTreeMap<Principal, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(LEGACY_DATA_MEMORY_ID))
+        )
+    );
+
     static VERIFIED_PURCHASES_LEDGER: RefCell<StableBTreeMap<u64, VerifiedBrokerPurchase, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(VERIFIED_PURCHASES_LEDGER_MEMORY_ID))
@@ -96,7 +265,8 @@ impl Storable for USTBill {
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        candid::decode_one(&bytes)
+            .unwrap_or_else(|_| candid::decode_one::<LegacyUSTBill>(&bytes).unwrap().into())
     }
 
     fn into_bytes(self) -> Vec<u8> {
@@ -107,13 +277,95 @@ impl Storable for USTBill {
         ic_stable_structures::storable::Bound::Unbounded;
 }
 
+/// `USTBill` as stored while its yield was a float
+#[derive(candid::CandidType, serde::Deserialize)]
+struct LegacyUSTBill {
+    id: String,
+    cusip: String,
+    face_value: u64,
+    purchase_price: u64,
+    maturity_date: u64,
+    annual_yield: f64,
+    total_tokens: u64,
+    tokens_sold: u64,
+    status: USTBillStatus,
+    created_at: u64,
+    updated_at: u64,
+    issuer: String,
+    bill_type: String,
+    market_price_per_token: Option<u64>,
+    last_priced_at: Option<u64>,
+}
+
+impl From<LegacyUSTBill> for USTBill {
+    fn from(legacy: LegacyUSTBill) -> Self {
+        USTBill {
+            id: legacy.id,
+            cusip: legacy.cusip,
+            face_value: legacy.face_value,
+            purchase_price: legacy.purchase_price,
+            maturity_date: legacy.maturity_date,
+            // Stored yields were validated as rates in [0, 1] when the bill was created
+            annual_yield: Rate::from_f64(legacy.annual_yield).unwrap_or_default(),
+            total_tokens: legacy.total_tokens,
+            tokens_sold: legacy.tokens_sold,
+            status: legacy.status,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+            issuer: legacy.issuer,
+            bill_type: legacy.bill_type,
+            market_price_per_token: legacy.market_price_per_token,
+            market_yield: None,
+            last_priced_at: legacy.last_priced_at,
+        }
+    }
+}
+
+/// `User` as stored before balances were kept per settlement asset
+#[derive(candid::CandidType, serde::Deserialize)]
+struct LegacyUser {
+    principal: Principal,
+    email: String,
+    kyc_status: KYCStatus,
+    wallet_balance: u64, // In cents
+    total_invested: u64,
+    total_yield_earned: u64,
+    created_at: u64,
+    updated_at: u64,
+    is_active: bool,
+    phone_number: Option<String>,
+    country: String,
+}
+
+impl From<LegacyUser> for User {
+    fn from(legacy: LegacyUser) -> Self {
+        // The single cents balance becomes cash
+        let mut user = User {
+            principal: legacy.principal,
+            email: legacy.email,
+            kyc_status: legacy.kyc_status,
+            balances: vec![],
+            total_invested: legacy.total_invested,
+            total_yield_earned: legacy.total_yield_earned,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+            is_active: legacy.is_active,
+            phone_number: legacy.phone_number,
+            country: legacy.country,
+        };
+        crate::settlement::credit_cash(&mut user, legacy.wallet_balance);
+        user
+    }
+}
+
 impl Storable for User {
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        candid::decode_one(&bytes)
+            .unwrap_or_else(|_| candid::decode_one::<LegacyUser>(&bytes).unwrap().into())
     }
 
     fn into_bytes(self) -> Vec<u8> {
@@ -129,6 +381,62 @@ impl Storable for TokenHolding {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
 
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes)
+            .unwrap_or_else(|_| candid::decode_one::<LegacyTokenHolding>(&bytes).unwrap().into())
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+/// `TokenHolding` as stored before it kept its exact cost basis
+#[derive(candid::CandidType, serde::Deserialize)]
+struct LegacyTokenHolding {
+    id: String,
+    user_principal: Principal,
+    ustbill_id: String,
+    tokens_owned: u64,
+    purchase_price_per_token: u64,
+    purchase_date: u64,
+    yield_option: YieldOption,
+    status: HoldingStatus,
+    current_value: u64,
+    projected_yield: u64,
+}
+
+impl From<LegacyTokenHolding> for TokenHolding {
+    fn from(legacy: LegacyTokenHolding) -> Self {
+        TokenHolding {
+            id: legacy.id,
+            user_principal: legacy.user_principal,
+            ustbill_id: legacy.ustbill_id,
+            tokens_owned: legacy.tokens_owned,
+            purchase_price_per_token: legacy.purchase_price_per_token,
+            // The best basis an old holding has is its recorded price per token
+            cost_basis: legacy
+                .purchase_price_per_token
+                .saturating_mul(legacy.tokens_owned),
+            purchase_date: legacy.purchase_date,
+            yield_option: legacy.yield_option,
+            status: legacy.status,
+            current_value: legacy.current_value,
+            projected_yield: legacy.projected_yield,
+            // Yield was first paid out after this layout
+            yield_paid: 0,
+        }
+    }
+}
+
+impl Storable for Order {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         candid::decode_one(&bytes).unwrap()
     }
@@ -164,7 +472,11 @@ impl Storable for PlatformConfig {
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        candid::decode_one(&bytes).unwrap_or_else(|_| {
+            candid::decode_one::<LegacyPlatformConfig>(&bytes)
+                .unwrap()
+                .into()
+        })
     }
 
     fn into_bytes(self) -> Vec<u8> {
@@ -175,6 +487,32 @@ impl Storable for PlatformConfig {
         ic_stable_structures::storable::Bound::Unbounded;
 }
 
+/// `PlatformConfig` as stored while its fee was a float
+#[derive(candid::CandidType, serde::Deserialize)]
+struct LegacyPlatformConfig {
+    platform_fee_percentage: f64,
+    minimum_investment: u64,
+    maximum_investment: u64,
+    yield_distribution_frequency: u64,
+    kyc_expiry_days: u64,
+    treasury_api_refresh_interval: u64,
+}
+
+impl From<LegacyPlatformConfig> for PlatformConfig {
+    fn from(legacy: LegacyPlatformConfig) -> Self {
+        PlatformConfig {
+            // Stored fees were validated as rates in [0, 1) when the config was set
+            platform_fee_percentage: Rate::from_f64(legacy.platform_fee_percentage)
+                .unwrap_or_default(),
+            minimum_investment: legacy.minimum_investment,
+            maximum_investment: legacy.maximum_investment,
+            yield_distribution_frequency: legacy.yield_distribution_frequency,
+            kyc_expiry_days: legacy.kyc_expiry_days,
+            treasury_api_refresh_interval: legacy.treasury_api_refresh_interval,
+        }
+    }
+}
+
 impl Storable for TreasuryRate {
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
@@ -192,8 +530,8 @@ impl Storable for TreasuryRate {
         ic_stable_structures::storable::Bound::Unbounded;
 }
 
-impl Storable for TradingMetrics {
-    fn to_bytes(&self) -> Cow<[u8]> {
+impl Storable for RateSource {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
 
@@ -209,8 +547,8 @@ impl Storable for TradingMetrics {
         ic_stable_structures::storable::Bound::Unbounded;
 }
 
-impl Storable for VerifiedBrokerPurchase {
-    fn to_bytes(&self) -> Cow<[u8]> {
+impl Storable for SettlementConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
 
@@ -226,177 +564,1404 @@ impl Storable for VerifiedBrokerPurchase {
         ic_stable_structures::storable::Bound::Unbounded;
 }
 
-// Storage interface for USTBills
-pub struct USTBillStorage;
-
-impl USTBillStorage {
-    pub fn insert(ustbill: USTBill) -> Result<()> {
-        USTBILLS.with(|ustbills| {
-            ustbills.borrow_mut().insert(ustbill.id.clone(), ustbill);
-            Ok(())
-        })
+impl Storable for AssetPrice {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
     }
 
-    pub fn get(ustbill_id: &str) -> Result<USTBill> {
-        USTBILLS.with(|ustbills| {
-            ustbills
-                .borrow()
-                .get(&ustbill_id.to_string())
-                .ok_or(BitcoinUSTBillsError::USTBillNotFound)
-        })
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
     }
 
-    pub fn update(ustbill: USTBill) -> Result<()> {
-        USTBILLS.with(|ustbills| {
-            let mut ustbills = ustbills.borrow_mut();
-            if ustbills.contains_key(&ustbill.id) {
-                ustbills.insert(ustbill.id.clone(), ustbill);
-                Ok(())
-            } else {
-                Err(BitcoinUSTBillsError::USTBillNotFound)
-            }
-        })
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
     }
 
-    pub fn remove(ustbill_id: &str) -> Result<USTBill> {
-        USTBILLS.with(|ustbills| {
-            ustbills
-                .borrow_mut()
-                .remove(&ustbill_id.to_string())
-                .ok_or(BitcoinUSTBillsError::USTBillNotFound)
-        })
-    }
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
 
-    pub fn get_all() -> Vec<USTBill> {
-        USTBILLS.with(|ustbills| {
-            ustbills
-                .borrow()
-                .iter()
-                .map(|entry| entry.value().clone())
-                .collect()
-        })
+impl Storable for OracleConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
     }
 
-    pub fn get_active() -> Vec<USTBill> {
-        USTBILLS.with(|ustbills| {
-            ustbills
-                .borrow()
-                .iter()
-                .filter(|entry| entry.value().status == USTBillStatus::Active)
-                .map(|entry| entry.value().clone())
-                .collect()
-        })
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
     }
 
-    pub fn count() -> u64 {
-        USTBILLS.with(|ustbills| ustbills.borrow().len())
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
     }
-}
 
-// Storage interface for Users
-pub struct UserStorage;
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
 
-impl UserStorage {
-    pub fn insert(user: User) -> Result<()> {
-        USERS.with(|users| {
-            if users.borrow().contains_key(&user.principal) {
-                Err(BitcoinUSTBillsError::UserAlreadyExists)
-            } else {
-                users.borrow_mut().insert(user.principal, user);
-                Ok(())
-            }
-        })
+impl Storable for BtcUsdPrice {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
     }
 
-    pub fn get(principal: &Principal) -> Result<User> {
-        USERS.with(|users| {
-            users
-                .borrow()
-                .get(principal)
-                .ok_or(BitcoinUSTBillsError::UserNotFound)
-        })
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
     }
 
-    pub fn update(user: User) -> Result<()> {
-        USERS.with(|users| {
-            let mut users = users.borrow_mut();
-            if users.contains_key(&user.principal) {
-                users.insert(user.principal, user);
-                Ok(())
-            } else {
-                Err(BitcoinUSTBillsError::UserNotFound)
-            }
-        })
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
     }
 
-    pub fn remove(principal: &Principal) -> Result<User> {
-        USERS.with(|users| {
-            users
-                .borrow_mut()
-                .remove(principal)
-                .ok_or(BitcoinUSTBillsError::UserNotFound)
-        })
-    }
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
 
-    pub fn get_all() -> Vec<User> {
-        USERS.with(|users| {
-            users
-                .borrow()
-                .iter()
-                .map(|entry| entry.value().clone())
-                .collect()
-        })
+impl Storable for Withdrawal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
     }
 
-    pub fn count() -> u64 {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for PurchaseQuote {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for WithdrawalPolicy {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for JournalEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for AccountBalance {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for RateFeedStatus {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for FeatureFlags {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for Allowance {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for ICRC3Value {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for TradingMetrics {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for YieldDistribution {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for RoleAssignment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Storable for VerifiedBrokerPurchase {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        candid::encode_one(self).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+// Storage interface for USTBills
+pub struct USTBillStorage;
+
+impl USTBillStorage {
+    pub fn insert(ustbill: USTBill) -> Result<()> {
+        USTBILLS.with(|ustbills| {
+            ustbills.borrow_mut().insert(ustbill.id.clone(), ustbill);
+            Ok(())
+        })
+    }
+
+    pub fn get(ustbill_id: &str) -> Result<USTBill> {
+        USTBILLS.with(|ustbills| {
+            ustbills
+                .borrow()
+                .get(&ustbill_id.to_string())
+                .ok_or(BitcoinUSTBillsError::USTBillNotFound)
+        })
+    }
+
+    pub fn update(ustbill: USTBill) -> Result<()> {
+        USTBILLS.with(|ustbills| {
+            let mut ustbills = ustbills.borrow_mut();
+            if ustbills.contains_key(&ustbill.id) {
+                ustbills.insert(ustbill.id.clone(), ustbill);
+                Ok(())
+            } else {
+                Err(BitcoinUSTBillsError::USTBillNotFound)
+            }
+        })
+    }
+
+    pub fn remove(ustbill_id: &str) -> Result<USTBill> {
+        USTBILLS.with(|ustbills| {
+            ustbills
+                .borrow_mut()
+                .remove(&ustbill_id.to_string())
+                .ok_or(BitcoinUSTBillsError::USTBillNotFound)
+        })
+    }
+
+    pub fn get_all() -> Vec<USTBill> {
+        USTBILLS.with(|ustbills| {
+            ustbills
+                .borrow()
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_active() -> Vec<USTBill> {
+        USTBILLS.with(|ustbills| {
+            ustbills
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().status == USTBillStatus::Active)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn count() -> u64 {
+        USTBILLS.with(|ustbills| ustbills.borrow().len())
+    }
+}
+
+// Storage interface for Users
+pub struct UserStorage;
+
+impl UserStorage {
+    pub fn insert(user: User) -> Result<()> {
+        USERS.with(|users| {
+            if users.borrow().contains_key(&user.principal) {
+                Err(BitcoinUSTBillsError::UserAlreadyExists)
+            } else {
+                users.borrow_mut().insert(user.principal, user);
+                Ok(())
+            }
+        })
+    }
+
+    pub fn get(principal: &Principal) -> Result<User> {
+        USERS.with(|users| {
+            users
+                .borrow()
+                .get(principal)
+                .ok_or(BitcoinUSTBillsError::UserNotFound)
+        })
+    }
+
+    pub fn update(user: User) -> Result<()> {
+        USERS.with(|users| {
+            let mut users = users.borrow_mut();
+            if users.contains_key(&user.principal) {
+                users.insert(user.principal, user);
+                Ok(())
+            } else {
+                Err(BitcoinUSTBillsError::UserNotFound)
+            }
+        })
+    }
+
+    pub fn remove(principal: &Principal) -> Result<User> {
+        USERS.with(|users| {
+            users
+                .borrow_mut()
+                .remove(principal)
+                .ok_or(BitcoinUSTBillsError::UserNotFound)
+        })
+    }
+
+    pub fn get_all() -> Vec<User> {
+        USERS.with(|users| {
+            users
+                .borrow()
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn count() -> u64 {
         USERS.with(|users| users.borrow().len())
     }
 }
 
-// Storage interface for Token Holdings
-pub struct HoldingStorage;
+// Storage interface for Token Holdings
+pub struct HoldingStorage;
+
+impl HoldingStorage {
+    pub fn insert(holding: TokenHolding) -> Result<()> {
+        HOLDINGS.with(|holdings| {
+            holdings.borrow_mut().insert(holding.id.clone(), holding);
+            Ok(())
+        })
+    }
+
+    pub fn get(holding_id: &str) -> Result<TokenHolding> {
+        HOLDINGS.with(|holdings| {
+            holdings
+                .borrow()
+                .get(&holding_id.to_string())
+                .ok_or(BitcoinUSTBillsError::HoldingNotFound)
+        })
+    }
+
+    pub fn update(holding: TokenHolding) -> Result<()> {
+        HOLDINGS.with(|holdings| {
+            let mut holdings = holdings.borrow_mut();
+            if holdings.contains_key(&holding.id) {
+                holdings.insert(holding.id.clone(), holding);
+                Ok(())
+            } else {
+                Err(BitcoinUSTBillsError::HoldingNotFound)
+            }
+        })
+    }
+
+    pub fn remove(holding_id: &str) -> Result<TokenHolding> {
+        HOLDINGS.with(|holdings| {
+            holdings
+                .borrow_mut()
+                .remove(&holding_id.to_string())
+                .ok_or(BitcoinUSTBillsError::HoldingNotFound)
+        })
+    }
+
+    pub fn get_by_user(user_principal: &Principal) -> Vec<TokenHolding> {
+        HOLDINGS.with(|holdings| {
+            holdings
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().user_principal == *user_principal)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_by_ustbill(ustbill_id: &str) -> Vec<TokenHolding> {
+        HOLDINGS.with(|holdings| {
+            holdings
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().ustbill_id == ustbill_id)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_active() -> Vec<TokenHolding> {
+        HOLDINGS.with(|holdings| {
+            holdings
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().status == HoldingStatus::Active)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn count() -> u64 {
+        HOLDINGS.with(|holdings| holdings.borrow().len())
+    }
+}
+
+// Storage interface for Orders
+pub struct OrderStorage;
+
+impl OrderStorage {
+    pub fn insert(order: Order) -> Result<()> {
+        ORDERS.with(|orders| {
+            orders.borrow_mut().insert(order.id.clone(), order);
+            Ok(())
+        })
+    }
+
+    pub fn get(order_id: &str) -> Result<Order> {
+        ORDERS.with(|orders| {
+            orders
+                .borrow()
+                .get(&order_id.to_string())
+                .ok_or(BitcoinUSTBillsError::OrderNotFound)
+        })
+    }
+
+    pub fn update(order: Order) -> Result<()> {
+        ORDERS.with(|orders| {
+            let mut orders = orders.borrow_mut();
+            if orders.contains_key(&order.id) {
+                orders.insert(order.id.clone(), order);
+                Ok(())
+            } else {
+                Err(BitcoinUSTBillsError::OrderNotFound)
+            }
+        })
+    }
+
+    pub fn get_by_user(owner: &Principal) -> Vec<Order> {
+        ORDERS.with(|orders| {
+            orders
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().owner == *owner)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_open() -> Vec<Order> {
+        ORDERS.with(|orders| {
+            orders
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().is_open())
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_open_by_ustbill(ustbill_id: &str) -> Vec<Order> {
+        ORDERS.with(|orders| {
+            orders
+                .borrow()
+                .iter()
+                .filter(|entry| {
+                    let order = entry.value();
+                    order.ustbill_id == ustbill_id && order.is_open()
+                })
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    /// Tokens of a holding that are committed to open ask orders; asks past their expiry
+    /// no longer hold tokens back, even before they are marked expired
+    pub fn reserved_tokens(holding_id: &str, now: u64) -> u64 {
+        ORDERS.with(|orders| {
+            orders
+                .borrow()
+                .iter()
+                .map(|entry| entry.value())
+                .filter(|order| {
+                    order.is_open()
+                        && !order.is_expired(now)
+                        && order.holding_id.as_deref() == Some(holding_id)
+                })
+                .map(|order| order.remaining())
+                .sum()
+        })
+    }
+
+    pub fn count() -> u64 {
+        ORDERS.with(|orders| orders.borrow().len())
+    }
+}
+
+// Storage interface for Transactions
+pub struct TransactionStorage;
+
+impl TransactionStorage {
+    pub fn insert(transaction: Transaction) -> Result<()> {
+        TRANSACTIONS.with(|transactions| {
+            transactions
+                .borrow_mut()
+                .insert(transaction.id.clone(), transaction);
+            Ok(())
+        })
+    }
+
+    pub fn get(transaction_id: &str) -> Result<Transaction> {
+        TRANSACTIONS.with(|transactions| {
+            transactions
+                .borrow()
+                .get(&transaction_id.to_string())
+                .ok_or(BitcoinUSTBillsError::TransactionNotFound)
+        })
+    }
+
+    pub fn update(transaction: Transaction) -> Result<()> {
+        TRANSACTIONS.with(|transactions| {
+            let mut transactions = transactions.borrow_mut();
+            if transactions.contains_key(&transaction.id) {
+                transactions.insert(transaction.id.clone(), transaction);
+                Ok(())
+            } else {
+                Err(BitcoinUSTBillsError::TransactionNotFound)
+            }
+        })
+    }
+
+    pub fn get_by_user(user_principal: &Principal) -> Vec<Transaction> {
+        TRANSACTIONS.with(|transactions| {
+            transactions
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().user_principal == *user_principal)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_by_type(transaction_type: &TransactionType) -> Vec<Transaction> {
+        TRANSACTIONS.with(|transactions| {
+            transactions
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().transaction_type == *transaction_type)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn count() -> u64 {
+        TRANSACTIONS.with(|transactions| transactions.borrow().len())
+    }
+}
+
+/// A row staged in a WriteBatch
+enum Staged<T> {
+    Insert(T),
+    Update(T),
+}
+
+impl<T> Staged<T> {
+    fn row(&self) -> &T {
+        match self {
+            Staged::Insert(row) | Staged::Update(row) => row,
+        }
+    }
+}
+
+/// Stages an update; updating a row inserted in the same batch keeps it an insert
+fn stage_update<K: Ord, T>(rows: &mut BTreeMap<K, Staged<T>>, key: K, row: T) {
+    match rows.get_mut(&key) {
+        Some(Staged::Insert(staged)) => *staged = row,
+        _ => {
+            rows.insert(key, Staged::Update(row));
+        }
+    }
+}
+
+/// Checks an insert doesn't overwrite a row and an update has one to replace
+fn check_staged<T>(
+    staged: &Staged<T>,
+    stored: bool,
+    what: &str,
+    not_found: BitcoinUSTBillsError,
+) -> Result<()> {
+    match staged {
+        Staged::Insert(_) if stored => Err(BitcoinUSTBillsError::InternalError(format!(
+            "{} already exists",
+            what
+        ))),
+        Staged::Update(_) if !stored => Err(not_found),
+        _ => Ok(()),
+    }
+}
+
+type Posting = Box<dyn FnOnce() -> Result<()>>;
+
+/// Stages changes to users, bills, holdings, orders and transactions and applies all of
+/// them or none. Reads through the batch see its staged rows. `commit` checks every row
+/// and the token invariants before writing anything; postings (journal entries, ICRC-3
+/// blocks, metrics) run after the rows are written. A failure once writing has started
+/// traps, which rolls back the whole call.
+#[derive(Default)]
+pub struct WriteBatch {
+    users: BTreeMap<Principal, Staged<User>>,
+    ustbills: BTreeMap<String, Staged<USTBill>>,
+    holdings: BTreeMap<String, Staged<TokenHolding>>,
+    orders: BTreeMap<String, Staged<Order>>,
+    transactions: BTreeMap<String, Staged<Transaction>>,
+    postings: Vec<Posting>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn user(&self, principal: &Principal) -> Result<User> {
+        match self.users.get(principal) {
+            Some(staged) => Ok(staged.row().clone()),
+            None => UserStorage::get(principal),
+        }
+    }
+
+    pub fn ustbill(&self, ustbill_id: &str) -> Result<USTBill> {
+        match self.ustbills.get(ustbill_id) {
+            Some(staged) => Ok(staged.row().clone()),
+            None => USTBillStorage::get(ustbill_id),
+        }
+    }
+
+    pub fn holding(&self, holding_id: &str) -> Result<TokenHolding> {
+        match self.holdings.get(holding_id) {
+            Some(staged) => Ok(staged.row().clone()),
+            None => HoldingStorage::get(holding_id),
+        }
+    }
+
+    pub fn update_user(&mut self, user: User) {
+        stage_update(&mut self.users, user.principal, user);
+    }
+
+    pub fn update_ustbill(&mut self, ustbill: USTBill) {
+        stage_update(&mut self.ustbills, ustbill.id.clone(), ustbill);
+    }
+
+    pub fn insert_holding(&mut self, holding: TokenHolding) {
+        self.holdings
+            .insert(holding.id.clone(), Staged::Insert(holding));
+    }
+
+    pub fn update_holding(&mut self, holding: TokenHolding) {
+        stage_update(&mut self.holdings, holding.id.clone(), holding);
+    }
+
+    pub fn insert_order(&mut self, order: Order) {
+        self.orders.insert(order.id.clone(), Staged::Insert(order));
+    }
+
+    pub fn update_order(&mut self, order: Order) {
+        stage_update(&mut self.orders, order.id.clone(), order);
+    }
+
+    pub fn insert_transaction(&mut self, transaction: Transaction) {
+        self.transactions
+            .insert(transaction.id.clone(), Staged::Insert(transaction));
+    }
+
+    /// Queues a write to another store, run in order after the rows are committed
+    pub fn then(&mut self, posting: impl FnOnce() -> Result<()> + 'static) {
+        self.postings.push(Box::new(posting));
+    }
+
+    /// Checks the staged rows against storage and the invariants, without writing
+    pub fn check(&self) -> Result<()> {
+        for (principal, staged) in &self.users {
+            let stored = UserStorage::get(principal).is_ok();
+            check_staged(staged, stored, "User", BitcoinUSTBillsError::UserNotFound)?;
+        }
+        for (id, staged) in &self.ustbills {
+            let stored = USTBillStorage::get(id).is_ok();
+            check_staged(
+                staged,
+                stored,
+                "UST Bill",
+                BitcoinUSTBillsError::USTBillNotFound,
+            )?;
+            let ustbill = staged.row();
+            if ustbill.tokens_sold > ustbill.total_tokens {
+                return Err(BitcoinUSTBillsError::InternalError(format!(
+                    "UST Bill {} is oversold",
+                    id
+                )));
+            }
+        }
+        for (id, staged) in &self.orders {
+            let stored = OrderStorage::get(id).is_ok();
+            check_staged(staged, stored, "Order", BitcoinUSTBillsError::OrderNotFound)?;
+            let order = staged.row();
+            if order.filled_amount > order.token_amount {
+                return Err(BitcoinUSTBillsError::InternalError(format!(
+                    "Order {} is overfilled",
+                    id
+                )));
+            }
+        }
+        for (id, staged) in &self.transactions {
+            let stored = TransactionStorage::get(id).is_ok();
+            check_staged(
+                staged,
+                stored,
+                "Transaction",
+                BitcoinUSTBillsError::TransactionNotFound,
+            )?;
+        }
+
+        // Tokens only enter or leave active holdings as a bill's tokens are sold or
+        // returned to it, so each bill's change in active tokens must match its sales.
+        // Redeemed tokens stay sold, so matured holdings still count.
+        let active_tokens = |holding: &TokenHolding| -> i128 {
+            match holding.status {
+                HoldingStatus::Active | HoldingStatus::Matured => holding.tokens_owned as i128,
+                HoldingStatus::Sold | HoldingStatus::Cancelled => 0,
+            }
+        };
+        let mut token_changes: BTreeMap<&str, i128> = BTreeMap::new();
+        for (id, staged) in &self.holdings {
+            let stored = HoldingStorage::get(id).ok();
+            check_staged(
+                staged,
+                stored.is_some(),
+                "Holding",
+                BitcoinUSTBillsError::HoldingNotFound,
+            )?;
+            let holding = staged.row();
+            if holding.is_active() && holding.tokens_owned == 0 {
+                return Err(BitcoinUSTBillsError::InternalError(format!(
+                    "Holding {} is active with no tokens",
+                    id
+                )));
+            }
+            self.user(&holding.user_principal)?;
+            self.ustbill(&holding.ustbill_id)?;
+
+            *token_changes.entry(&holding.ustbill_id).or_default() +=
+                active_tokens(holding) - stored.as_ref().map_or(0, active_tokens);
+        }
+        for (id, staged) in &self.ustbills {
+            let sold_before = USTBillStorage::get(id).map_or(0, |ustbill| ustbill.tokens_sold);
+            *token_changes.entry(id).or_default() -=
+                staged.row().tokens_sold as i128 - sold_before as i128;
+        }
+        if let Some((ustbill_id, _)) = token_changes.iter().find(|(_, change)| **change != 0) {
+            return Err(BitcoinUSTBillsError::InternalError(format!(
+                "Holdings of UST Bill {} don't match its tokens sold",
+                ustbill_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks, then applies every staged row and runs the postings
+    pub fn commit(self) -> Result<()> {
+        self.check()?;
+
+        let applied = |result: Result<()>| {
+            if let Err(e) = result {
+                ic_cdk::trap(format!("Write batch failed after its checks: {}", e));
+            }
+        };
+        for staged in self.users.into_values() {
+            applied(match staged {
+                Staged::Insert(user) => UserStorage::insert(user),
+                Staged::Update(user) => UserStorage::update(user),
+            });
+        }
+        for staged in self.ustbills.into_values() {
+            applied(match staged {
+                Staged::Insert(ustbill) => USTBillStorage::insert(ustbill),
+                Staged::Update(ustbill) => USTBillStorage::update(ustbill),
+            });
+        }
+        for staged in self.holdings.into_values() {
+            applied(match staged {
+                Staged::Insert(holding) => HoldingStorage::insert(holding),
+                Staged::Update(holding) => HoldingStorage::update(holding),
+            });
+        }
+        for staged in self.orders.into_values() {
+            applied(match staged {
+                Staged::Insert(order) => OrderStorage::insert(order),
+                Staged::Update(order) => OrderStorage::update(order),
+            });
+        }
+        for staged in self.transactions.into_values() {
+            applied(match staged {
+                Staged::Insert(transaction) => TransactionStorage::insert(transaction),
+                Staged::Update(transaction) => TransactionStorage::update(transaction),
+            });
+        }
+        for posting in self.postings {
+            applied(posting());
+        }
+        Ok(())
+    }
+}
+
+// Storage interface for Platform Configuration
+pub struct PlatformConfigStorage;
+
+impl PlatformConfigStorage {
+    pub fn get() -> PlatformConfig {
+        PLATFORM_CONFIG.with(|config| config.borrow().get().clone())
+    }
+
+    pub fn update(config: PlatformConfig) -> Result<()> {
+        PLATFORM_CONFIG.with(|platform_config| {
+            platform_config.borrow_mut().set(config);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for Treasury Rates
+pub struct TreasuryRateStorage;
+
+impl TreasuryRateStorage {
+    /// Keys sort by CUSIP, then by ISO rate date, so a CUSIP's history is one contiguous range
+    fn key(cusip: &str, rate_date: &str) -> String {
+        format!("{}_{}", cusip, rate_date)
+    }
+
+    /// Inserts a rate, replacing any earlier record for the same CUSIP and rate date
+    pub fn insert(rate: TreasuryRate) -> Result<()> {
+        let key = Self::key(&rate.cusip, &rate.rate_date);
+        TREASURY_RATES.with(|rates| {
+            rates.borrow_mut().insert(key, rate);
+            Ok(())
+        })
+    }
+
+    /// Rates of a CUSIP with `from <= rate_date <= to`, oldest first
+    pub fn get_history(cusip: &str, from: &str, to: &str) -> Vec<TreasuryRate> {
+        TREASURY_RATES.with(|rates| {
+            rates
+                .borrow()
+                .range(Self::key(cusip, from)..=Self::key(cusip, to))
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    /// Most recent rate for a security description, e.g. "13-Week Treasury Bill"
+    pub fn get_latest_by_desc(security_desc: &str) -> Option<TreasuryRate> {
+        TREASURY_RATES.with(|rates| {
+            rates
+                .borrow()
+                .iter()
+                .map(|entry| entry.value().clone())
+                .filter(|rate| rate.security_desc.eq_ignore_ascii_case(security_desc))
+                .max_by(|a, b| {
+                    (&a.rate_date, &a.record_date).cmp(&(&b.rate_date, &b.record_date))
+                })
+        })
+    }
+
+    /// Removes rates dated before `cutoff_date`, returning how many were dropped
+    pub fn prune_before(cutoff_date: &str) -> u64 {
+        TREASURY_RATES.with(|rates| {
+            let mut rates = rates.borrow_mut();
+            let expired: Vec<String> = rates
+                .iter()
+                .filter(|entry| entry.value().rate_date.as_str() < cutoff_date)
+                .map(|entry| entry.key().clone())
+                .collect();
+            for key in &expired {
+                rates.remove(key);
+            }
+            expired.len() as u64
+        })
+    }
+
+    pub fn count() -> u64 {
+        TREASURY_RATES.with(|rates| rates.borrow().len())
+    }
+
+    pub fn get_by_cusip(cusip: &str) -> Vec<TreasuryRate> {
+        TREASURY_RATES.with(|rates| {
+            rates
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().cusip == cusip)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_all() -> Vec<TreasuryRate> {
+        TREASURY_RATES.with(|rates| {
+            rates
+                .borrow()
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn clear() -> Result<()> {
+        TREASURY_RATES.with(|rates| {
+            *rates.borrow_mut() = StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(TREASURY_RATES_MEMORY_ID)),
+            );
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for the Treasury rate source
+pub struct RateSourceStorage;
+
+impl RateSourceStorage {
+    pub fn get() -> RateSource {
+        RATE_SOURCE.with(|source| source.borrow().get().clone())
+    }
+
+    pub fn update(source: RateSource) -> Result<()> {
+        RATE_SOURCE.with(|rate_source| {
+            rate_source.borrow_mut().set(source);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for feature toggles
+pub struct FeatureFlagsStorage;
+
+impl FeatureFlagsStorage {
+    pub fn get() -> FeatureFlags {
+        FEATURE_FLAGS.with(|flags| flags.borrow().get().clone())
+    }
+
+    pub fn update(flags: FeatureFlags) -> Result<()> {
+        FEATURE_FLAGS.with(|feature_flags| {
+            feature_flags.borrow_mut().set(flags);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for the configured settlement assets
+pub struct SettlementConfigStorage;
+
+impl SettlementConfigStorage {
+    pub fn get() -> SettlementConfig {
+        SETTLEMENT_CONFIG.with(|config| config.borrow().get().clone())
+    }
+
+    pub fn update(config: SettlementConfig) -> Result<()> {
+        SETTLEMENT_CONFIG.with(|settlement_config| {
+            settlement_config.borrow_mut().set(config);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for the latest USD price of each oracle-priced asset
+pub struct AssetPriceStorage;
+
+impl AssetPriceStorage {
+    pub fn get(asset: &str) -> Option<AssetPrice> {
+        ASSET_PRICES.with(|prices| prices.borrow().get(&asset.to_string()))
+    }
+
+    pub fn update(price: AssetPrice) -> Result<()> {
+        ASSET_PRICES.with(|prices| {
+            prices.borrow_mut().insert(price.asset.clone(), price);
+            Ok(())
+        })
+    }
+
+    pub fn get_all() -> Vec<AssetPrice> {
+        ASSET_PRICES.with(|prices| {
+            prices
+                .borrow()
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+}
+
+// Storage interface for the BTC/USD oracle sources and thresholds
+pub struct OracleConfigStorage;
+
+impl OracleConfigStorage {
+    pub fn get() -> OracleConfig {
+        ORACLE_CONFIG.with(|config| config.borrow().get().clone())
+    }
+
+    pub fn update(config: OracleConfig) -> Result<()> {
+        ORACLE_CONFIG.with(|oracle_config| {
+            oracle_config.borrow_mut().set(config);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for the last accepted BTC/USD price
+pub struct BtcUsdPriceStorage;
+
+impl BtcUsdPriceStorage {
+    /// None until the oracle has accepted a price
+    pub fn get() -> Option<BtcUsdPrice> {
+        let price = BTC_USD_PRICE.with(|price| price.borrow().get().clone());
+        Some(price).filter(|price| price.price_cents > 0)
+    }
+
+    pub fn update(price: BtcUsdPrice) -> Result<()> {
+        BTC_USD_PRICE.with(|btc_usd_price| {
+            btc_usd_price.borrow_mut().set(price);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for withdrawal requests
+pub struct WithdrawalStorage;
+
+impl WithdrawalStorage {
+    pub fn insert(withdrawal: Withdrawal) -> Result<()> {
+        WITHDRAWALS.with(|withdrawals| {
+            withdrawals
+                .borrow_mut()
+                .insert(withdrawal.id.clone(), withdrawal);
+            Ok(())
+        })
+    }
+
+    pub fn get(withdrawal_id: &str) -> Result<Withdrawal> {
+        WITHDRAWALS.with(|withdrawals| {
+            withdrawals
+                .borrow()
+                .get(&withdrawal_id.to_string())
+                .ok_or(BitcoinUSTBillsError::WithdrawalNotFound)
+        })
+    }
+
+    pub fn update(withdrawal: Withdrawal) -> Result<()> {
+        WITHDRAWALS.with(|withdrawals| {
+            let mut withdrawals = withdrawals.borrow_mut();
+            if withdrawals.contains_key(&withdrawal.id) {
+                withdrawals.insert(withdrawal.id.clone(), withdrawal);
+                Ok(())
+            } else {
+                Err(BitcoinUSTBillsError::WithdrawalNotFound)
+            }
+        })
+    }
+
+    pub fn get_by_owner(owner: &Principal) -> Vec<Withdrawal> {
+        WITHDRAWALS.with(|withdrawals| {
+            withdrawals
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().owner == *owner)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn get_by_status(status: &WithdrawalStatus) -> Vec<Withdrawal> {
+        WITHDRAWALS.with(|withdrawals| {
+            withdrawals
+                .borrow()
+                .iter()
+                .filter(|entry| entry.value().status == *status)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+}
+
+// Storage interface for the withdrawal approval thresholds
+pub struct WithdrawalPolicyStorage;
+
+impl WithdrawalPolicyStorage {
+    pub fn get() -> WithdrawalPolicy {
+        WITHDRAWAL_POLICY.with(|policy| policy.borrow().get().clone())
+    }
+
+    pub fn update(policy: WithdrawalPolicy) -> Result<()> {
+        WITHDRAWAL_POLICY.with(|withdrawal_policy| {
+            withdrawal_policy.borrow_mut().set(policy);
+            Ok(())
+        })
+    }
+}
+
+// Storage interface for the double-entry journal and the account totals derived from it
+pub struct JournalStorage;
+
+impl JournalStorage {
+    /// Appends a balanced entry and adds its lines to the account totals, returning its id
+    pub fn append(mut entry: JournalEntry) -> Result<u64> {
+        let id = JOURNAL.with(|journal| journal.borrow().len());
+        entry.id = id;
+
+        ACCOUNT_BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            for line in &entry.lines {
+                let key = line.account.key();
+                let mut balance = balances.get(&key).unwrap_or(AccountBalance {
+                    account: line.account.clone(),
+                    debits: 0,
+                    credits: 0,
+                    balance: 0,
+                });
+                match line.side {
+                    EntrySide::Debit => balance.debits += line.amount,
+                    EntrySide::Credit => balance.credits += line.amount,
+                }
+                balance.balance = balance.debits as i64 - balance.credits as i64;
+                balances.insert(key, balance);
+            }
+        });
+        JOURNAL.with(|journal| journal.borrow_mut().insert(id, entry));
+        Ok(id)
+    }
+
+    pub fn get_range(offset: u64, limit: u64) -> Vec<JournalEntry> {
+        JOURNAL.with(|journal| {
+            journal
+                .borrow()
+                .range(offset..)
+                .take(limit as usize)
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    /// Entries posted between `from` and `to` inclusive; entries are appended in time order
+    pub fn get_between(from: u64, to: u64) -> Vec<JournalEntry> {
+        JOURNAL.with(|journal| {
+            journal
+                .borrow()
+                .values()
+                .skip_while(|entry| entry.timestamp < from)
+                .take_while(|entry| entry.timestamp <= to)
+                .collect()
+        })
+    }
+
+    pub fn balance(account: &LedgerAccount) -> Option<AccountBalance> {
+        ACCOUNT_BALANCES.with(|balances| balances.borrow().get(&account.key()))
+    }
+
+    pub fn get_balances() -> Vec<AccountBalance> {
+        ACCOUNT_BALANCES.with(|balances| {
+            balances
+                .borrow()
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect()
+        })
+    }
+
+    pub fn count() -> u64 {
+        JOURNAL.with(|journal| journal.borrow().len())
+    }
+}
+
+// Storage interface for purchase quotes awaiting execution
+pub struct QuoteStorage;
+
+impl QuoteStorage {
+    pub fn insert(quote: PurchaseQuote) -> Result<()> {
+        QUOTES.with(|quotes| {
+            quotes.borrow_mut().insert(quote.id.clone(), quote);
+            Ok(())
+        })
+    }
+
+    pub fn get(quote_id: &str) -> Result<PurchaseQuote> {
+        QUOTES.with(|quotes| {
+            quotes
+                .borrow()
+                .get(&quote_id.to_string())
+                .ok_or(BitcoinUSTBillsError::QuoteNotFound)
+        })
+    }
+
+    pub fn remove(quote_id: &str) -> Result<()> {
+        QUOTES.with(|quotes| {
+            quotes
+                .borrow_mut()
+                .remove(&quote_id.to_string())
+                .map(|_| ())
+                .ok_or(BitcoinUSTBillsError::QuoteNotFound)
+        })
+    }
+
+    /// Drops every quote expired as of `now`, returning how many
+    pub fn remove_expired(now: u64) -> u64 {
+        QUOTES.with(|quotes| {
+            let mut quotes = quotes.borrow_mut();
+            let expired: Vec<String> = quotes
+                .iter()
+                .filter(|entry| entry.value().expires_at <= now)
+                .map(|entry| entry.key().clone())
+                .collect();
+            for quote_id in &expired {
+                quotes.remove(quote_id);
+            }
+            expired.len() as u64
+        })
+    }
+
+    pub fn signing_key() -> Option<Vec<u8>> {
+        QUOTE_SIGNING_KEY.with(|key| Some(key.borrow().get().clone()).filter(|key| !key.is_empty()))
+    }
+
+    pub fn set_signing_key(key: Vec<u8>) {
+        QUOTE_SIGNING_KEY.with(|signing_key| signing_key.borrow_mut().set(key));
+    }
+
+    pub fn count() -> u64 {
+        QUOTES.with(|quotes| quotes.borrow().len())
+    }
+}
+
+// Storage interface for the Treasury rate feed health
+pub struct RateFeedStatusStorage;
+
+impl RateFeedStatusStorage {
+    pub fn get() -> RateFeedStatus {
+        RATE_FEED_STATUS.with(|status| status.borrow().get().clone())
+    }
+
+    pub fn update(status: RateFeedStatus) -> Result<()> {
+        RATE_FEED_STATUS.with(|feed_status| {
+            feed_status.borrow_mut().set(status);
+            Ok(())
+        })
+    }
+
+    pub fn record_success(rate_count: u64) -> Result<()> {
+        let mut status = Self::get();
+        status.last_success = Some(get_current_timestamp());
+        status.last_rate_count = rate_count;
+        status.consecutive_failures = 0;
+        Self::update(status)
+    }
+
+    pub fn record_failure(error: &BitcoinUSTBillsError) -> Result<()> {
+        let mut status = Self::get();
+        status.last_failure = Some(get_current_timestamp());
+        status.last_error = Some(error.to_string());
+        status.consecutive_failures += 1;
+        Self::update(status)
+    }
+}
+
+// Storage interface for Trading Metrics
+pub struct TradingMetricsStorage;
 
-impl HoldingStorage {
-    pub fn insert(holding: TokenHolding) -> Result<()> {
-        HOLDINGS.with(|holdings| {
-            holdings.borrow_mut().insert(holding.id.clone(), holding);
+impl TradingMetricsStorage {
+    pub fn get() -> TradingMetrics {
+        TRADING_METRICS.with(|metrics| metrics.borrow().get().clone())
+    }
+
+    pub fn update(metrics: TradingMetrics) -> Result<()> {
+        TRADING_METRICS.with(|trading_metrics| {
+            trading_metrics.borrow_mut().set(metrics);
             Ok(())
         })
     }
 
-    pub fn get(holding_id: &str) -> Result<TokenHolding> {
-        HOLDINGS.with(|holdings| {
-            holdings
-                .borrow()
-                .get(&holding_id.to_string())
-                .ok_or(BitcoinUSTBillsError::HoldingNotFound)
-        })
+    pub fn update_volume(volume: u64, now: u64) -> Result<()> {
+        let mut metrics = Self::get();
+        metrics.total_volume += volume;
+        metrics.total_transactions += 1;
+        metrics.last_updated = now;
+        Self::update(metrics)
     }
 
-    pub fn update(holding: TokenHolding) -> Result<()> {
-        HOLDINGS.with(|holdings| {
-            let mut holdings = holdings.borrow_mut();
-            if holdings.contains_key(&holding.id) {
-                holdings.insert(holding.id.clone(), holding);
-                Ok(())
-            } else {
-                Err(BitcoinUSTBillsError::HoldingNotFound)
-            }
-        })
+    pub fn update_price(price: u64, now: u64) -> Result<()> {
+        let mut metrics = Self::get();
+        if metrics.highest_price == 0 || price > metrics.highest_price {
+            metrics.highest_price = price;
+        }
+        if metrics.lowest_price == 0 || price < metrics.lowest_price {
+            metrics.lowest_price = price;
+        }
+        // Calculate new average price
+        let total_volume = metrics.total_volume;
+        if total_volume > 0 {
+            metrics.average_price =
+                (metrics.average_price * total_volume + price) / (total_volume + 1);
+        } else {
+            metrics.average_price = price;
+        }
+        metrics.last_updated = now;
+        Self::update(metrics)
     }
+}
 
-    pub fn remove(holding_id: &str) -> Result<TokenHolding> {
-        HOLDINGS.with(|holdings| {
-            holdings
-                .borrow_mut()
-                .remove(&holding_id.to_string())
-                .ok_or(BitcoinUSTBillsError::HoldingNotFound)
+// Storage interface for Yield Distributions
+pub struct YieldDistributionStorage;
+
+impl YieldDistributionStorage {
+    pub fn insert(distribution: YieldDistribution) -> Result<()> {
+        YIELD_DISTRIBUTIONS.with(|distributions| {
+            let id = distributions.borrow().len();
+            distributions.borrow_mut().insert(id, distribution);
+            Ok(())
         })
     }
 
-    pub fn get_by_user(user_principal: &Principal) -> Vec<TokenHolding> {
-        HOLDINGS.with(|holdings| {
-            holdings
+    pub fn get_by_user(user_principal: &Principal) -> Vec<YieldDistribution> {
+        YIELD_DISTRIBUTIONS.with(|distributions| {
+            distributions
                 .borrow()
                 .iter()
                 .filter(|entry| entry.value().user_principal == *user_principal)
@@ -405,195 +1970,339 @@ impl HoldingStorage {
         })
     }
 
-    pub fn get_by_ustbill(ustbill_id: &str) -> Vec<TokenHolding> {
-        HOLDINGS.with(|holdings| {
-            holdings
+    pub fn get_by_holding(holding_id: &str) -> Vec<YieldDistribution> {
+        YIELD_DISTRIBUTIONS.with(|distributions| {
+            distributions
                 .borrow()
                 .iter()
-                .filter(|entry| entry.value().ustbill_id == ustbill_id)
+                .filter(|entry| entry.value().holding_id == holding_id)
                 .map(|entry| entry.value().clone())
                 .collect()
         })
     }
 
-    pub fn get_active() -> Vec<TokenHolding> {
-        HOLDINGS.with(|holdings| {
-            holdings
-                .borrow()
-                .iter()
-                .filter(|entry| entry.value().status == HoldingStatus::Active)
-                .map(|entry| entry.value().clone())
-                .collect()
+    /// Timestamp a holding has accrued yield up to, if it has accrued at all
+    pub fn accrued_until(holding_id: &str) -> Option<u64> {
+        YIELD_ACCRUALS.with(|accruals| accruals.borrow().get(&holding_id.to_string()))
+    }
+
+    pub fn set_accrued_until(holding_id: &str, timestamp: u64) -> Result<()> {
+        YIELD_ACCRUALS.with(|accruals| {
+            accruals.borrow_mut().insert(holding_id.to_string(), timestamp);
+            Ok(())
         })
     }
 
     pub fn count() -> u64 {
-        HOLDINGS.with(|holdings| holdings.borrow().len())
+        YIELD_DISTRIBUTIONS.with(|distributions| distributions.borrow().len())
     }
 }
 
-// Storage interface for Transactions
-pub struct TransactionStorage;
+// Storage interface for access-control roles
+pub struct RoleStorage;
 
-impl TransactionStorage {
-    pub fn insert(transaction: Transaction) -> Result<()> {
-        TRANSACTIONS.with(|transactions| {
-            transactions
-                .borrow_mut()
-                .insert(transaction.id.clone(), transaction);
-            Ok(())
+impl RoleStorage {
+    pub fn get(principal: &Principal) -> Vec<Role> {
+        ROLES.with(|roles| {
+            roles
+                .borrow()
+                .get(principal)
+                .map(|assignment| assignment.roles)
+                .unwrap_or_default()
         })
     }
 
-    pub fn get(transaction_id: &str) -> Result<Transaction> {
-        TRANSACTIONS.with(|transactions| {
-            transactions
-                .borrow()
-                .get(&transaction_id.to_string())
-                .ok_or(BitcoinUSTBillsError::TransactionNotFound)
-        })
+    pub fn has_role(principal: &Principal, role: Role) -> bool {
+        Self::get(principal).contains(&role)
     }
 
-    pub fn update(transaction: Transaction) -> Result<()> {
-        TRANSACTIONS.with(|transactions| {
-            let mut transactions = transactions.borrow_mut();
-            if transactions.contains_key(&transaction.id) {
-                transactions.insert(transaction.id.clone(), transaction);
-                Ok(())
+    // Returns false if the principal already held the role
+    pub fn grant(principal: Principal, role: Role) -> bool {
+        let mut roles = Self::get(&principal);
+        if roles.contains(&role) {
+            return false;
+        }
+        roles.push(role);
+        roles.sort();
+
+        ROLES.with(|stored| {
+            stored
+                .borrow_mut()
+                .insert(principal, RoleAssignment { principal, roles });
+        });
+        true
+    }
+
+    // Returns false if the principal did not hold the role
+    pub fn revoke(principal: &Principal, role: Role) -> bool {
+        let mut roles = Self::get(principal);
+        if !roles.contains(&role) {
+            return false;
+        }
+        roles.retain(|held| *held != role);
+
+        ROLES.with(|stored| {
+            let mut stored = stored.borrow_mut();
+            if roles.is_empty() {
+                stored.remove(principal);
             } else {
-                Err(BitcoinUSTBillsError::TransactionNotFound)
+                stored.insert(
+                    *principal,
+                    RoleAssignment {
+                        principal: *principal,
+                        roles,
+                    },
+                );
             }
-        })
+        });
+        true
     }
 
-    pub fn get_by_user(user_principal: &Principal) -> Vec<Transaction> {
-        TRANSACTIONS.with(|transactions| {
-            transactions
+    pub fn holders(role: Role) -> Vec<Principal> {
+        Self::get_all()
+            .into_iter()
+            .filter(|assignment| assignment.roles.contains(&role))
+            .map(|assignment| assignment.principal)
+            .collect()
+    }
+
+    pub fn get_all() -> Vec<RoleAssignment> {
+        ROLES.with(|roles| {
+            roles
                 .borrow()
                 .iter()
-                .filter(|entry| entry.value().user_principal == *user_principal)
                 .map(|entry| entry.value().clone())
                 .collect()
         })
     }
 
-    pub fn get_by_type(transaction_type: &TransactionType) -> Vec<Transaction> {
-        TRANSACTIONS.with(|transactions| {
-            transactions
+    pub fn clear() {
+        ROLES.with(|roles| roles.borrow_mut().clear_new())
+    }
+
+    pub fn count() -> u64 {
+        ROLES.with(|roles| roles.borrow().len())
+    }
+}
+
+// Storage interface for bill token allowances
+pub struct AllowanceStorage;
+
+impl AllowanceStorage {
+    fn key(ustbill_id: &str, owner: &Principal, spender: &Principal) -> String {
+        format!("{}:{}:{}", ustbill_id, owner, spender)
+    }
+
+    pub fn get(ustbill_id: &str, owner: &Principal, spender: &Principal) -> Option<Allowance> {
+        ALLOWANCES.with(|allowances| {
+            allowances
                 .borrow()
-                .iter()
-                .filter(|entry| entry.value().transaction_type == *transaction_type)
-                .map(|entry| entry.value().clone())
-                .collect()
+                .get(&Self::key(ustbill_id, owner, spender))
+        })
+    }
+
+    // A zero allowance removes the entry
+    pub fn set(ustbill_id: &str, owner: &Principal, spender: &Principal, allowance: Allowance) {
+        let key = Self::key(ustbill_id, owner, spender);
+        ALLOWANCES.with(|allowances| {
+            let mut allowances = allowances.borrow_mut();
+            if allowance.allowance == 0u64 {
+                allowances.remove(&key);
+            } else {
+                allowances.insert(key, allowance);
+            }
         })
     }
 
     pub fn count() -> u64 {
-        TRANSACTIONS.with(|transactions| transactions.borrow().len())
+        ALLOWANCES.with(|allowances| allowances.borrow().len())
     }
 }
 
-// Storage interface for Platform Configuration
-pub struct PlatformConfigStorage;
+// Storage interface for recent ledger calls, used to reject duplicates
+pub struct LedgerCallStorage;
 
-impl PlatformConfigStorage {
-    pub fn get() -> PlatformConfig {
-        PLATFORM_CONFIG.with(|config| config.borrow().get().clone())
+impl LedgerCallStorage {
+    pub fn get(key: &str) -> Option<u64> {
+        LEDGER_CALLS.with(|calls| calls.borrow().get(&key.to_string()))
     }
 
-    pub fn update(config: PlatformConfig) -> Result<()> {
-        PLATFORM_CONFIG.with(|platform_config| {
-            platform_config.borrow_mut().set(config);
-            Ok(())
+    pub fn insert(key: String, block_index: u64) {
+        LEDGER_CALLS.with(|calls| {
+            calls.borrow_mut().insert(key, block_index);
+        })
+    }
+
+    /// Forgets calls created before `created_before`, which are too old to be replayed
+    pub fn prune(created_before: u64) {
+        let end = format!("{:020}", created_before);
+        LEDGER_CALLS.with(|calls| {
+            let mut calls = calls.borrow_mut();
+            let expired: Vec<String> = calls
+                .range(..end)
+                .map(|entry| entry.key().clone())
+                .collect();
+            for key in expired {
+                calls.remove(&key);
+            }
         })
     }
+
+    pub fn count() -> u64 {
+        LEDGER_CALLS.with(|calls| calls.borrow().len())
+    }
 }
 
-// Storage interface for Treasury Rates
-pub struct TreasuryRateStorage;
+// Storage interface for the ICRC-3 block log; blocks are only ever appended
+pub struct BlockStorage;
 
-impl TreasuryRateStorage {
-    pub fn insert(rate: TreasuryRate) -> Result<()> {
-        let key = format!("{}_{}", rate.cusip, rate.rate_date);
-        TREASURY_RATES.with(|rates| {
-            rates.borrow_mut().insert(key, rate);
-            Ok(())
+impl BlockStorage {
+    pub fn append(block: ICRC3Value) -> Result<u64> {
+        BLOCKS.with(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            let index = blocks.len();
+            blocks.insert(index, block);
+            Ok(index)
         })
     }
 
-    pub fn get_by_cusip(cusip: &str) -> Vec<TreasuryRate> {
-        TREASURY_RATES.with(|rates| {
-            rates
+    pub fn get(index: u64) -> Option<ICRC3Value> {
+        BLOCKS.with(|blocks| blocks.borrow().get(&index))
+    }
+
+    pub fn last() -> Option<(u64, ICRC3Value)> {
+        BLOCKS.with(|blocks| {
+            blocks
                 .borrow()
-                .iter()
-                .filter(|entry| entry.value().cusip == cusip)
-                .map(|entry| entry.value().clone())
-                .collect()
+                .last_key_value()
         })
     }
 
-    pub fn get_all() -> Vec<TreasuryRate> {
-        TREASURY_RATES.with(|rates| {
-            rates
+    pub fn range(start: u64, length: u64) -> Vec<(u64, ICRC3Value)> {
+        BLOCKS.with(|blocks| {
+            blocks
                 .borrow()
-                .iter()
-                .map(|entry| entry.value().clone())
+                .range(start..start.saturating_add(length))
+                .map(|entry| (*entry.key(), entry.value()))
                 .collect()
         })
     }
 
-    pub fn clear() -> Result<()> {
-        TREASURY_RATES.with(|rates| {
-            *rates.borrow_mut() = StableBTreeMap::init(
-                MEMORY_MANAGER.with(|m| m.borrow().get(TREASURY_RATES_MEMORY_ID)),
-            );
-            Ok(())
-        })
+    pub fn count() -> u64 {
+        BLOCKS.with(|blocks| blocks.borrow().len())
     }
 }
 
-// Storage interface for Trading Metrics
-pub struct TradingMetricsStorage;
+// Storage interface for the legacy principal -> string data
+pub struct LegacyDataStorage;
 
-impl TradingMetricsStorage {
-    pub fn get() -> TradingMetrics {
-        TRADING_METRICS.with(|metrics| metrics.borrow().get().clone())
+impl LegacyDataStorage {
+    pub fn insert(principal: Principal, data: String) {
+        LEGACY_DATA.with(|legacy| {
+            legacy.borrow_mut().insert(principal, data);
+        })
     }
 
-    pub fn update(metrics: TradingMetrics) -> Result<()> {
-        TRADING_METRICS.with(|trading_metrics| {
-            trading_metrics.borrow_mut().set(metrics);
-            Ok(())
+    pub fn get(principal: &Principal) -> Option<String> {
+        LEGACY_DATA.with(|legacy| legacy.borrow().get(principal))
+    }
+
+    pub fn remove(principal: &Principal) -> Option<String> {
+        LEGACY_DATA.with(|legacy| legacy.borrow_mut().remove(principal))
+    }
+
+    pub fn get_all() -> Vec<(Principal, String)> {
+        LEGACY_DATA.with(|legacy| {
+            legacy
+                .borrow()
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().clone()))
+                .collect()
         })
     }
 
-    pub fn update_volume(volume: u64) -> Result<()> {
-        let mut metrics = Self::get();
-        metrics.total_volume += volume;
-        metrics.total_transactions += 1;
-        metrics.last_updated = ic_cdk::api::time() / 1_000_000_000;
-        Self::update(metrics)
+    pub fn clear() {
+        LEGACY_DATA.with(|legacy| legacy.borrow_mut().clear_new())
     }
 
-    pub fn update_price(price: u64) -> Result<()> {
-        let mut metrics = Self::get();
-        if metrics.highest_price == 0 || price > metrics.highest_price {
-            metrics.highest_price = price;
+    pub fn count() -> u64 {
+        LEGACY_DATA.with(|legacy| legacy.borrow().len())
+    }
+}
+
+/// The admin list and legacy data the baseline's pre_upgrade saved with `stable_save`
+#[derive(candid::CandidType, serde::Deserialize)]
+pub struct LegacyStableStore {
+    pub data: std::collections::HashMap<Principal, String>,
+    pub guard: Vec<Principal>,
+}
+
+// MemoryManager layout v1: magic, version, bucket count, bucket size, 32 reserved bytes and
+// 255 memory sizes, followed by one memory id per bucket
+const MANAGER_HEADER_SIZE: usize = 2_080;
+const MANAGER_MAX_BUCKETS: usize = 32_768;
+const MANAGER_BUCKET_SIZE_IN_PAGES: u64 = 128;
+const UNALLOCATED_BUCKET: u8 = 255;
+
+/// Takes the payload the baseline's pre_upgrade left in stable memory, if any.
+///
+/// The baseline kept its stores in the memory manager but also ran `stable_save` at offset 0,
+/// overwriting the manager's header; `MemoryManager::init` would then start an empty manager.
+/// While the payload fits inside the header, the bucket allocation table after it is intact and
+/// the header is rebuilt from it. A larger payload has overwritten bucket allocations that can't
+/// be recovered, and the canister has to be reinstalled. Must run before any store is touched.
+pub fn take_legacy_stable_store() -> Result<Option<LegacyStableStore>> {
+    use ic_stable_structures::Memory as _;
+
+    STABLE_MEMORY.with(|memory| {
+        if memory.size() == 0 {
+            return Ok(None);
         }
-        if metrics.lowest_price == 0 || price < metrics.lowest_price {
-            metrics.lowest_price = price;
+        let mut header = vec![0; MANAGER_HEADER_SIZE];
+        memory.read(0, &mut header);
+        if !header.starts_with(b"DIDL") {
+            return Ok(None);
         }
-        // Calculate new average price
-        let total_volume = metrics.total_volume;
-        if total_volume > 0 {
-            metrics.average_price =
-                (metrics.average_price * total_volume + price) / (total_volume + 1);
-        } else {
-            metrics.average_price = price;
+
+        // Decoding from the header bytes alone proves the payload ends inside the header
+        let store = candid::de::IDLDeserialize::new(&header)
+            .and_then(|mut de| de.get_value::<LegacyStableStore>())
+            .map_err(|_| {
+                BitcoinUSTBillsError::StorageError(
+                    "The pre-upgrade payload overwrote the memory manager's bucket table; \
+                     reinstall the canister"
+                        .to_string(),
+                )
+            })?;
+
+        let mut buckets = vec![0; MANAGER_MAX_BUCKETS];
+        memory.read(MANAGER_HEADER_SIZE as u64, &mut buckets);
+        // Buckets are allocated in order, so the allocated ones come first
+        let allocated = buckets.iter().take_while(|&&id| id != UNALLOCATED_BUCKET).count();
+        if 1 + allocated as u64 * MANAGER_BUCKET_SIZE_IN_PAGES > memory.size() {
+            return Err(BitcoinUSTBillsError::StorageError(
+                "The memory manager's bucket table doesn't match stable memory".to_string(),
+            ));
         }
-        metrics.last_updated = ic_cdk::api::time() / 1_000_000_000;
-        Self::update(metrics)
-    }
+
+        // A memory's size is rounded up to whole buckets; every page of them is allocated
+        let mut sizes_in_pages = [0u64; UNALLOCATED_BUCKET as usize];
+        for &id in &buckets[..allocated] {
+            sizes_in_pages[id as usize] += MANAGER_BUCKET_SIZE_IN_PAGES;
+        }
+        let mut rebuilt = Vec::with_capacity(MANAGER_HEADER_SIZE);
+        rebuilt.extend_from_slice(b"MGR");
+        rebuilt.push(1);
+        rebuilt.extend_from_slice(&(allocated as u16).to_le_bytes());
+        rebuilt.extend_from_slice(&(MANAGER_BUCKET_SIZE_IN_PAGES as u16).to_le_bytes());
+        rebuilt.extend_from_slice(&[0; 32]);
+        for size in sizes_in_pages {
+            rebuilt.extend_from_slice(&size.to_le_bytes());
+        }
+        memory.write(0, &rebuilt);
+
+        Ok(Some(store))
+    })
 }
 
 // Storage interface for Verified Purchases Ledger
@@ -623,6 +2332,58 @@ impl VerifiedPurchasesLedgerStorage {
     }
 }
 
+// Re-creates every stable structure from the raw stable memory, as an upgrade would
+#[cfg(test)]
+pub fn simulate_upgrade() {
+    let memory = STABLE_MEMORY.with(|memory| memory.clone());
+    MEMORY_MANAGER.with(|m| *m.borrow_mut() = MemoryManager::init(memory));
+    let get = |id| MEMORY_MANAGER.with(|m| m.borrow().get(id));
+
+    macro_rules! reload_map {
+        ($map:ident, $id:expr) => {
+            $map.with(|map| *map.borrow_mut() = StableBTreeMap::init(get($id)))
+        };
+    }
+    macro_rules! reload_cell {
+        ($cell:ident, $id:expr) => {
+            $cell.with(|cell| {
+                let default = cell.borrow().get().clone();
+                *cell.borrow_mut() = Cell::init(get($id), default);
+            })
+        };
+    }
+
+    reload_cell!(ID_COUNTER, ID_COUNTER_MEMORY_ID);
+    reload_map!(USTBILLS, USTBILLS_MEMORY_ID);
+    reload_map!(USERS, USERS_MEMORY_ID);
+    reload_map!(HOLDINGS, HOLDINGS_MEMORY_ID);
+    reload_map!(ORDERS, ORDERS_MEMORY_ID);
+    reload_map!(TRANSACTIONS, TRANSACTIONS_MEMORY_ID);
+    reload_cell!(PLATFORM_CONFIG, PLATFORM_CONFIG_MEMORY_ID);
+    reload_map!(TREASURY_RATES, TREASURY_RATES_MEMORY_ID);
+    reload_cell!(RATE_SOURCE, RATE_SOURCE_MEMORY_ID);
+    reload_cell!(RATE_FEED_STATUS, RATE_FEED_STATUS_MEMORY_ID);
+    reload_cell!(FEATURE_FLAGS, FEATURE_FLAGS_MEMORY_ID);
+    reload_cell!(SETTLEMENT_CONFIG, SETTLEMENT_CONFIG_MEMORY_ID);
+    reload_map!(ASSET_PRICES, ASSET_PRICES_MEMORY_ID);
+    reload_cell!(ORACLE_CONFIG, ORACLE_CONFIG_MEMORY_ID);
+    reload_cell!(BTC_USD_PRICE, BTC_USD_PRICE_MEMORY_ID);
+    reload_map!(WITHDRAWALS, WITHDRAWALS_MEMORY_ID);
+    reload_cell!(WITHDRAWAL_POLICY, WITHDRAWAL_POLICY_MEMORY_ID);
+    reload_map!(JOURNAL, JOURNAL_MEMORY_ID);
+    reload_map!(ACCOUNT_BALANCES, ACCOUNT_BALANCES_MEMORY_ID);
+    reload_map!(QUOTES, QUOTES_MEMORY_ID);
+    reload_cell!(QUOTE_SIGNING_KEY, QUOTE_SIGNING_KEY_MEMORY_ID);
+    reload_cell!(TRADING_METRICS, TRADING_METRICS_MEMORY_ID);
+    reload_map!(YIELD_DISTRIBUTIONS, YIELD_DISTRIBUTIONS_MEMORY_ID);
+    reload_map!(YIELD_ACCRUALS, YIELD_ACCRUALS_MEMORY_ID);
+    reload_map!(ROLES, ROLES_MEMORY_ID);
+    reload_map!(LEGACY_DATA, LEGACY_DATA_MEMORY_ID);
+    reload_map!(ALLOWANCES, ALLOWANCES_MEMORY_ID);
+    reload_map!(BLOCKS, BLOCKS_MEMORY_ID);
+    reload_map!(LEDGER_CALLS, LEDGER_CALLS_MEMORY_ID);
+    reload_map!(VERIFIED_PURCHASES_LEDGER, VERIFIED_PURCHASES_LEDGER_MEMORY_ID);
+}
 
 // Utility functions for storage operations
 pub fn generate_id() -> String {
@@ -644,6 +2405,299 @@ pub fn get_storage_stats() -> std::collections::HashMap<String, u64> {
     stats.insert("users".to_string(), UserStorage::count());
     stats.insert("holdings".to_string(), HoldingStorage::count());
     stats.insert("transactions".to_string(), TransactionStorage::count());
+    stats.insert("orders".to_string(), OrderStorage::count());
+    stats.insert("yield_distributions".to_string(), YieldDistributionStorage::count());
+    stats.insert("treasury_rates".to_string(), TreasuryRateStorage::count());
     stats.insert("verified_purchases".to_string(), VerifiedPurchasesLedgerStorage::count());
+    stats.insert("allowances".to_string(), AllowanceStorage::count());
+    stats.insert("blocks".to_string(), BlockStorage::count());
     stats
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_user(principal: Principal) -> User {
+        User {
+            principal,
+            email: "investor@example.com".to_string(),
+            kyc_status: KYCStatus::Verified,
+            balances: vec![AssetBalance {
+                asset: "ckBTC".to_string(),
+                amount: 50_000,
+            }],
+            total_invested: 0,
+            total_yield_earned: 0,
+            created_at: 0,
+            updated_at: 0,
+            is_active: true,
+            phone_number: None,
+            country: "US".to_string(),
+        }
+    }
+
+    fn test_ustbill(id: &str) -> USTBill {
+        USTBill {
+            id: id.to_string(),
+            cusip: "912796ZV4".to_string(),
+            face_value: 100_000,
+            purchase_price: 98_670,
+            maturity_date: 1_900_000_000,
+            annual_yield: Rate::from_nanos(54_000_000),
+            total_tokens: 1_000,
+            tokens_sold: 0,
+            status: USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "13-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        }
+    }
+
+    #[test]
+    fn test_state_survives_repeated_upgrades() {
+        let investor = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let admin = Principal::from_text("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe").unwrap();
+
+        UserStorage::insert(test_user(investor)).unwrap();
+        USTBillStorage::insert(test_ustbill(&generate_id())).unwrap();
+        RoleStorage::grant(admin, Role::SuperAdmin);
+        LegacyDataStorage::insert(investor, "legacy".to_string());
+
+        simulate_upgrade();
+        USTBillStorage::insert(test_ustbill(&generate_id())).unwrap();
+        simulate_upgrade();
+
+        assert_eq!(UserStorage::get(&investor).unwrap().balance("ckBTC"), 50_000);
+        let ids: Vec<String> = USTBillStorage::get_all().into_iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec!["0".to_string(), "1".to_string()]);
+        assert_eq!(RoleStorage::get(&admin), vec![Role::SuperAdmin]);
+        assert_eq!(LegacyDataStorage::get(&investor), Some("legacy".to_string()));
+        assert_eq!(generate_id(), "2");
+    }
+
+    #[test]
+    fn test_state_survives_upgrade_from_baseline() {
+        use ic_stable_structures::Memory as _;
+
+        let investor = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let admin = Principal::from_text("6lzil-lzkgm-twmv5-rz5xg-a5nnm-togvj-mlu6s-p4xyl-5j3zi-6a6jy-yqe").unwrap();
+        UserStorage::insert(test_user(investor)).unwrap();
+        USTBillStorage::insert(test_ustbill(&generate_id())).unwrap();
+
+        // The baseline's pre_upgrade: stable_save((StableStore { data, guard },)) at offset 0
+        let payload = candid::encode_one(LegacyStableStore {
+            data: [(investor, "legacy".to_string())].into_iter().collect(),
+            guard: vec![admin],
+        })
+        .unwrap();
+        STABLE_MEMORY.with(|memory| memory.write(0, &payload));
+
+        let store = take_legacy_stable_store().unwrap().unwrap();
+        simulate_upgrade();
+        assert_eq!(store.guard, vec![admin]);
+        assert_eq!(store.data.get(&investor), Some(&"legacy".to_string()));
+        assert_eq!(UserStorage::get(&investor).unwrap().balance("ckBTC"), 50_000);
+        assert_eq!(USTBillStorage::get_all().len(), 1);
+        assert_eq!(generate_id(), "1");
+
+        // Later upgrades find the rebuilt header
+        assert!(take_legacy_stable_store().unwrap().is_none());
+        USTBillStorage::insert(test_ustbill(&generate_id())).unwrap();
+        simulate_upgrade();
+        assert_eq!(USTBillStorage::get_all().len(), 2);
+
+        // A payload reaching into the bucket table can't be recovered
+        let payload = candid::encode_one(LegacyStableStore {
+            data: [(investor, "x".repeat(4_000))].into_iter().collect(),
+            guard: vec![admin],
+        })
+        .unwrap();
+        STABLE_MEMORY.with(|memory| memory.write(0, &payload));
+        assert!(take_legacy_stable_store().is_err());
+    }
+
+    #[test]
+    fn test_write_batch_applies_all_or_nothing() {
+        let investor = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        UserStorage::insert(test_user(investor)).unwrap();
+        USTBillStorage::insert(test_ustbill("bill")).unwrap();
+
+        // A purchase of 10 tokens paid with 10,000 sats
+        let stage_purchase = |batch: &mut WriteBatch| {
+            let mut user = batch.user(&investor).unwrap();
+            assert!(user.debit("ckBTC", 10_000));
+            batch.update_user(user);
+            let mut ustbill = batch.ustbill("bill").unwrap();
+            ustbill.tokens_sold += 10;
+            batch.update_ustbill(ustbill);
+            batch.then(move || {
+                LegacyDataStorage::insert(investor, "posted".to_string());
+                Ok(())
+            });
+        };
+        let holding = TokenHolding {
+            id: "holding".to_string(),
+            user_principal: investor,
+            ustbill_id: "bill".to_string(),
+            tokens_owned: 10,
+            purchase_price_per_token: 9_867,
+            cost_basis: 98_670,
+            purchase_date: 0,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 98_670,
+            projected_yield: 0,
+            yield_paid: 0,
+        };
+        let unchanged = || {
+            UserStorage::get(&investor).unwrap().balance("ckBTC") == 50_000
+                && USTBillStorage::get("bill").unwrap().tokens_sold == 0
+                && HoldingStorage::get("holding").is_err()
+                && LegacyDataStorage::get(&investor).is_none()
+        };
+
+        // The holding update has nothing to replace, so nothing is written
+        let mut batch = WriteBatch::new();
+        stage_purchase(&mut batch);
+        batch.update_holding(holding.clone());
+        assert!(matches!(
+            batch.commit(),
+            Err(BitcoinUSTBillsError::HoldingNotFound)
+        ));
+        assert!(unchanged());
+
+        // Tokens sold without a holding to show for them
+        let mut batch = WriteBatch::new();
+        stage_purchase(&mut batch);
+        assert!(matches!(
+            batch.commit(),
+            Err(BitcoinUSTBillsError::InternalError(_))
+        ));
+        assert!(unchanged());
+
+        let mut batch = WriteBatch::new();
+        stage_purchase(&mut batch);
+        batch.insert_holding(holding.clone());
+        // Reads through the batch see its staged rows
+        assert_eq!(batch.user(&investor).unwrap().balance("ckBTC"), 40_000);
+        assert_eq!(batch.holding("holding").unwrap().tokens_owned, 10);
+        batch.commit().unwrap();
+
+        assert_eq!(UserStorage::get(&investor).unwrap().balance("ckBTC"), 40_000);
+        assert_eq!(USTBillStorage::get("bill").unwrap().tokens_sold, 10);
+        assert_eq!(HoldingStorage::get("holding").unwrap().tokens_owned, 10);
+        assert_eq!(LegacyDataStorage::get(&investor), Some("posted".to_string()));
+
+        // Inserting it again would overwrite it
+        let mut batch = WriteBatch::new();
+        batch.insert_holding(holding);
+        assert!(batch.commit().is_err());
+    }
+
+    #[test]
+    fn test_expired_asks_release_reserved_tokens() {
+        let ask = |id: &str, token_amount, expires_at| Order {
+            id: id.to_string(),
+            ustbill_id: "bill".to_string(),
+            owner: Principal::anonymous(),
+            side: OrderSide::Ask,
+            price_per_token: 99,
+            token_amount,
+            filled_amount: 0,
+            holding_id: Some("reserved-holding".to_string()),
+            escrowed_amount: 0,
+            status: OrderStatus::Open,
+            created_at: 0,
+            updated_at: 0,
+            expires_at,
+        };
+        OrderStorage::insert(ask("standing", 10, None)).unwrap();
+        OrderStorage::insert(ask("expiring", 5, Some(1_000))).unwrap();
+
+        assert_eq!(OrderStorage::reserved_tokens("reserved-holding", 999), 15);
+        assert_eq!(OrderStorage::reserved_tokens("reserved-holding", 1_000), 10);
+    }
+
+    #[test]
+    fn test_legacy_user_balance_becomes_cash() {
+        let user = test_user(Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap());
+        let legacy = LegacyUser {
+            principal: user.principal,
+            email: user.email,
+            kyc_status: user.kyc_status,
+            wallet_balance: 1_234,
+            total_invested: user.total_invested,
+            total_yield_earned: user.total_yield_earned,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            is_active: user.is_active,
+            phone_number: user.phone_number,
+            country: user.country,
+        };
+
+        let migrated = User::from_bytes(Cow::Owned(candid::encode_one(legacy).unwrap()));
+        assert_eq!(migrated.balances.len(), 1);
+        assert_eq!(migrated.balance("ckUSDC"), 12_340_000);
+        assert_eq!(crate::settlement::cash_balance(&migrated), 1_234);
+    }
+
+    #[test]
+    fn test_legacy_holding_gets_a_cost_basis() {
+        let legacy = LegacyTokenHolding {
+            id: "legacy-holding".to_string(),
+            user_principal: Principal::anonymous(),
+            ustbill_id: "bill".to_string(),
+            tokens_owned: 10,
+            purchase_price_per_token: 9_867,
+            purchase_date: 0,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 98_670,
+            projected_yield: 0,
+        };
+        let migrated = TokenHolding::from_bytes(Cow::Owned(candid::encode_one(legacy).unwrap()));
+        assert_eq!(migrated.cost_basis, 98_670);
+    }
+
+    #[test]
+    fn test_legacy_float_rates_become_exact() {
+        let ustbill = test_ustbill("legacy-bill");
+        let legacy = LegacyUSTBill {
+            id: ustbill.id,
+            cusip: ustbill.cusip,
+            face_value: ustbill.face_value,
+            purchase_price: ustbill.purchase_price,
+            maturity_date: ustbill.maturity_date,
+            annual_yield: 0.0526,
+            total_tokens: ustbill.total_tokens,
+            tokens_sold: ustbill.tokens_sold,
+            status: ustbill.status,
+            created_at: ustbill.created_at,
+            updated_at: ustbill.updated_at,
+            issuer: ustbill.issuer,
+            bill_type: ustbill.bill_type,
+            market_price_per_token: None,
+            last_priced_at: None,
+        };
+        let migrated = USTBill::from_bytes(Cow::Owned(candid::encode_one(legacy).unwrap()));
+        assert_eq!(migrated.annual_yield, Rate::from_nanos(52_600_000));
+
+        let config = PlatformConfig::default();
+        let legacy = LegacyPlatformConfig {
+            platform_fee_percentage: 0.005,
+            minimum_investment: config.minimum_investment,
+            maximum_investment: config.maximum_investment,
+            yield_distribution_frequency: config.yield_distribution_frequency,
+            kyc_expiry_days: config.kyc_expiry_days,
+            treasury_api_refresh_interval: config.treasury_api_refresh_interval,
+        };
+        let migrated =
+            PlatformConfig::from_bytes(Cow::Owned(candid::encode_one(legacy).unwrap()));
+        assert_eq!(migrated.platform_fee_percentage, Rate::from_bps(50).unwrap());
+    }
+}
@@ -0,0 +1,112 @@
+use ic_cdk_timers::TimerId;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::storage::{
+    get_current_timestamp, FeatureFlagsStorage, OracleConfigStorage, PlatformConfigStorage,
+};
+use crate::{maturity, oracle, orderbook, yield_distribution};
+
+/// How often bills are checked for maturity
+const MATURITY_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How often open orders are checked for expiry, releasing their escrow
+const ORDER_EXPIRY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How often holdings are checked for a due yield distribution. The distribution
+/// period itself comes from `PlatformConfig::yield_distribution_frequency`.
+const YIELD_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+thread_local! {
+    // Rate refresh timer, kept so it can be re-armed when the interval changes
+    static RATE_REFRESH_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    // BTC/USD price refresh timer, re-armed the same way
+    static PRICE_REFRESH_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+/// Registers the periodic jobs. Timers do not survive upgrades, so this runs
+/// from both `init` and `post_upgrade`.
+pub fn start_timers() {
+    ic_cdk_timers::set_timer_interval(MATURITY_CHECK_INTERVAL, run_maturity_processing);
+    ic_cdk_timers::set_timer_interval(YIELD_CHECK_INTERVAL, run_yield_distribution);
+    ic_cdk_timers::set_timer_interval(ORDER_EXPIRY_INTERVAL, run_order_expiry);
+    schedule_rate_refresh(PlatformConfigStorage::get().treasury_api_refresh_interval);
+    schedule_price_refresh(OracleConfigStorage::get().refresh_interval_seconds);
+}
+
+/// (Re-)arms the Treasury rate refresh to run every `interval_secs` seconds
+pub fn schedule_rate_refresh(interval_secs: u64) {
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(interval_secs), || {
+        ic_cdk::futures::spawn(run_rate_refresh())
+    });
+
+    RATE_REFRESH_TIMER.with(|timer| {
+        if let Some(previous) = timer.borrow_mut().replace(timer_id) {
+            ic_cdk_timers::clear_timer(previous);
+        }
+    });
+}
+
+/// Refreshes Treasury rates and reprices bills unless the refresh is switched off;
+/// fetch failures are recorded in the rate feed status
+async fn run_rate_refresh() {
+    if !FeatureFlagsStorage::get().rate_refresh_enabled {
+        return;
+    }
+    if let Err(e) = crate::refresh_market_data().await {
+        ic_cdk::println!("Treasury rate refresh failed: {}", e);
+    }
+}
+
+/// (Re-)arms the BTC/USD price refresh to run every `interval_secs` seconds
+pub fn schedule_price_refresh(interval_secs: u64) {
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(interval_secs), || {
+        ic_cdk::futures::spawn(run_price_refresh())
+    });
+
+    PRICE_REFRESH_TIMER.with(|timer| {
+        if let Some(previous) = timer.borrow_mut().replace(timer_id) {
+            ic_cdk_timers::clear_timer(previous);
+        }
+    });
+}
+
+/// Refreshes the BTC/USD price; while refreshes fail the last price ages into staleness
+async fn run_price_refresh() {
+    if let Err(e) = oracle::refresh_btc_usd_price(get_current_timestamp()).await {
+        ic_cdk::println!("BTC/USD price refresh failed: {}", e);
+    }
+}
+
+/// Runs one maturity batch and schedules an immediate follow-up while work remains
+fn run_maturity_processing() {
+    match maturity::process_maturities(get_current_timestamp()) {
+        Ok(report) if report.pending => {
+            ic_cdk_timers::set_timer(Duration::ZERO, run_maturity_processing);
+        }
+        Ok(_) => {}
+        Err(e) => ic_cdk::println!("Maturity processing failed: {}", e),
+    }
+}
+
+/// Expires open orders past their expiry so their escrow goes back to the owners
+fn run_order_expiry() {
+    if let Err(e) = orderbook::expire_orders(get_current_timestamp()) {
+        ic_cdk::println!("Order expiry failed: {}", e);
+    }
+}
+
+/// Runs one yield distribution batch, unless switched off, and schedules an immediate
+/// follow-up while work remains
+fn run_yield_distribution() {
+    if !FeatureFlagsStorage::get().yield_distribution_enabled {
+        return;
+    }
+    match yield_distribution::distribute_yield(get_current_timestamp()) {
+        Ok(report) if report.pending => {
+            ic_cdk_timers::set_timer(Duration::ZERO, run_yield_distribution);
+        }
+        Ok(_) => {}
+        Err(e) => ic_cdk::println!("Yield distribution failed: {}", e),
+    }
+}
@@ -0,0 +1,188 @@
+use ic_cdk::management_canister::{
+    http_request, transform_context_from_query, HttpHeader, HttpMethod, HttpRequestArgs,
+    HttpRequestResult, TransformArgs,
+};
+
+use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::storage::RateSourceStorage;
+use crate::types::*;
+
+/// Fiscal Data auction results for Treasury bills, most recent first
+pub const DEFAULT_TREASURY_RATES_URL: &str = "https://api.fiscaldata.treasury.gov/services/api/fiscal_service/v1/accounting/od/auctions_query?fields=record_date,security_type,security_term,auction_date,high_discount_rate,cusip&filter=security_type:eq:Bill&sort=-auction_date&page[size]=50";
+
+/// Upper bound on the outcall response, which also bounds its cycle cost
+const MAX_RESPONSE_BYTES: u64 = 100_000;
+
+/// Name of the query method replicas use to normalise the outcall response
+const TRANSFORM_METHOD: &str = "transform_treasury_response";
+
+/// HTTPS outcalls only reach `https://` URLs
+pub fn validate_rates_url(url: &str) -> Result<()> {
+    match url.strip_prefix("https://") {
+        Some(rest) if !rest.is_empty() => Ok(()),
+        _ => Err(BitcoinUSTBillsError::validation_error(
+            "Treasury rates URL must be an https:// URL",
+        )),
+    }
+}
+
+/// Reads the latest Treasury rates from the configured rate source
+pub async fn fetch_rates() -> Result<Vec<TreasuryRate>> {
+    let body = match RateSourceStorage::get() {
+        RateSource::TreasuryApi { url } => http_get(url).await?,
+        RateSource::Canned { body } => normalize_treasury_body(body.as_bytes())?,
+    };
+
+    parse_treasury_body(&body)
+}
+
+async fn http_get(url: String) -> Result<Vec<u8>> {
+    let request = HttpRequestArgs {
+        url,
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        method: HttpMethod::GET,
+        headers: vec![HttpHeader {
+            name: "Accept".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: None,
+        transform: Some(transform_context_from_query(
+            TRANSFORM_METHOD.to_string(),
+            vec![],
+        )),
+    };
+
+    let response = http_request(&request).await.map_err(|e| {
+        BitcoinUSTBillsError::http_request_error(format!("HTTP request failed: {}", e))
+    })?;
+
+    if response.status != 200u32 {
+        return Err(BitcoinUSTBillsError::http_request_error(format!(
+            "Treasury API returned status {}",
+            response.status
+        )));
+    }
+
+    Ok(response.body)
+}
+
+/// Strips headers and volatile body fields so that every replica sees the same response
+pub fn transform(args: TransformArgs) -> HttpRequestResult {
+    let mut response = args.response;
+    response.headers.clear();
+    response.body = normalize_treasury_body(&response.body).unwrap_or_default();
+    response
+}
+
+/// Re-serialises a Fiscal Data body keeping only the rate records, in a stable order
+pub fn normalize_treasury_body(body: &[u8]) -> Result<Vec<u8>> {
+    let api_response: TreasuryApiResponse = serde_json::from_slice(body).map_err(|_| {
+        BitcoinUSTBillsError::external_api_error("Failed to parse Treasury API response")
+    })?;
+
+    let mut records = api_response.data;
+    records.sort_by(|a, b| {
+        (&a.cusip, &a.auction_date, &a.security_term).cmp(&(
+            &b.cusip,
+            &b.auction_date,
+            &b.security_term,
+        ))
+    });
+    records.dedup();
+
+    serde_json::to_vec(&serde_json::json!({ "data": records }))
+        .map_err(|e| BitcoinUSTBillsError::SerializationError(e.to_string()))
+}
+
+/// Converts a normalised body into rates, skipping rows without a published rate
+pub fn parse_treasury_body(body: &[u8]) -> Result<Vec<TreasuryRate>> {
+    let api_response: TreasuryApiResponse = serde_json::from_slice(body).map_err(|_| {
+        BitcoinUSTBillsError::external_api_error("Failed to parse Treasury API response")
+    })?;
+
+    Ok(api_response
+        .data
+        .into_iter()
+        .filter_map(|record| {
+            let rate = record.high_discount_rate.parse::<f64>().ok()?;
+            Some(TreasuryRate {
+                record_date: record.record_date,
+                security_desc: format!(
+                    "{} Treasury {}",
+                    record.security_term, record.security_type
+                ),
+                security_type: record.security_type,
+                rate_date: record.auction_date,
+                rate,
+                cusip: record.cusip,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANNED_BODY: &str = r#"{
+        "data": [
+            {"record_date": "2024-01-04", "security_type": "Bill", "security_term": "26-Week",
+             "auction_date": "2024-01-02", "high_discount_rate": "5.150", "cusip": "912797GB7"},
+            {"record_date": "2024-01-04", "security_type": "Bill", "security_term": "13-Week",
+             "auction_date": "2024-01-02", "high_discount_rate": "5.260", "cusip": "912796ZV4"},
+            {"record_date": "2024-01-04", "security_type": "Bill", "security_term": "4-Week",
+             "auction_date": "2024-01-02", "high_discount_rate": "null", "cusip": "912797HP5"}
+        ],
+        "meta": {"count": 3, "labels": {"cusip": "CUSIP"}},
+        "links": {"self": "&offset=0&limit=50"}
+    }"#;
+
+    #[test]
+    fn test_normalize_is_deterministic() {
+        let reordered = r#"{
+            "links": {"self": "&offset=0&limit=50", "next": null},
+            "data": [
+                {"cusip": "912797HP5", "record_date": "2024-01-04", "security_type": "Bill",
+                 "security_term": "4-Week", "auction_date": "2024-01-02", "high_discount_rate": "null"},
+                {"cusip": "912796ZV4", "record_date": "2024-01-04", "security_type": "Bill",
+                 "security_term": "13-Week", "auction_date": "2024-01-02", "high_discount_rate": "5.260"},
+                {"cusip": "912797GB7", "record_date": "2024-01-04", "security_type": "Bill",
+                 "security_term": "26-Week", "auction_date": "2024-01-02", "high_discount_rate": "5.150"}
+            ],
+            "meta": {"count": 3, "labels": {}}
+        }"#;
+
+        let normalized = normalize_treasury_body(CANNED_BODY.as_bytes()).unwrap();
+        assert_eq!(
+            normalized,
+            normalize_treasury_body(reordered.as_bytes()).unwrap()
+        );
+
+        let text = String::from_utf8(normalized).unwrap();
+        assert!(!text.contains("meta"));
+        assert!(!text.contains("links"));
+    }
+
+    #[test]
+    fn test_parse_treasury_body() {
+        let normalized = normalize_treasury_body(CANNED_BODY.as_bytes()).unwrap();
+        let rates = parse_treasury_body(&normalized).unwrap();
+
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0].security_desc, "13-Week Treasury Bill");
+        assert_eq!(rates[0].rate, 5.26);
+        assert_eq!(rates[0].rate_date, "2024-01-02");
+    }
+
+    #[test]
+    fn test_validate_rates_url() {
+        assert!(validate_rates_url(DEFAULT_TREASURY_RATES_URL).is_ok());
+        assert!(validate_rates_url("http://api.fiscaldata.treasury.gov").is_err());
+        assert!(validate_rates_url("https://").is_err());
+    }
+
+    #[test]
+    fn test_invalid_body_is_rejected() {
+        assert!(normalize_treasury_body(b"<html>rate limited</html>").is_err());
+    }
+}
@@ -1,7 +1,11 @@
-use candid::{CandidType, Principal};
+use candid::{CandidType, Int, Nat, Principal};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::calendar::{self, DayCount};
+use crate::errors::Result;
+use crate::money::{Money, Rate, Rounding, DAYS_PER_YEAR};
+
 // ============= CORE DATA STRUCTURES =============
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -11,7 +15,7 @@ pub struct USTBill {
     pub face_value: u64,        // In cents ($1000 = 100000)
     pub purchase_price: u64,    // In cents ($950 = 95000)
     pub maturity_date: u64,     // Unix timestamp
-    pub annual_yield: f64,      // 5.26% = 0.0526
+    pub annual_yield: Rate,     // Investment yield at issue, actual/365; 5.26% = 52_600_000 nanos
     pub total_tokens: u64,      // 1000 tokens
     pub tokens_sold: u64,       // Tokens already sold
     pub status: USTBillStatus,
@@ -19,6 +23,9 @@ pub struct USTBill {
     pub updated_at: u64,
     pub issuer: String,         // Treasury issuer info
     pub bill_type: String,      // 4-week, 13-week, 26-week, 52-week
+    pub market_price_per_token: Option<u64>, // In cents, from the latest Treasury rate
+    pub market_yield: Option<Rate>,          // Investment yield at the market price, for display
+    pub last_priced_at: Option<u64>,         // Unix timestamp
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
@@ -34,7 +41,7 @@ pub struct User {
     pub principal: Principal,
     pub email: String,
     pub kyc_status: KYCStatus,
-    pub wallet_balance: u64,    // In cents
+    pub balances: Vec<AssetBalance>, // Per settlement asset
     pub total_invested: u64,    // Total amount invested
     pub total_yield_earned: u64, // Total yield earned
     pub created_at: u64,
@@ -58,12 +65,14 @@ pub struct TokenHolding {
     pub user_principal: Principal,
     pub ustbill_id: String,
     pub tokens_owned: u64,
-    pub purchase_price_per_token: u64,  // In cents
+    pub purchase_price_per_token: u64,  // In cents, the average rounded down
+    pub cost_basis: u64,         // In cents, the exact cost of the tokens owned
     pub purchase_date: u64,
     pub yield_option: YieldOption,
     pub status: HoldingStatus,
     pub current_value: u64,      // Current market value
     pub projected_yield: u64,    // Projected yield at maturity
+    pub yield_paid: u64,         // Flexible yield already paid out on these tokens, in cents
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
@@ -119,6 +128,26 @@ pub struct YieldDistribution {
     pub yield_amount: u64,
     pub distribution_date: u64,
     pub ustbill_id: String,
+    pub period_start: u64,       // Unix timestamp
+    pub period_end: u64,         // Unix timestamp
+    pub yield_option: YieldOption,
+    pub paid_out: bool,          // Flexible yield is paid out, Maturity yield only accrues
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Default)]
+pub struct YieldRunReport {
+    pub holdings_accrued: u64,
+    pub total_accrued: u64,      // In cents
+    pub total_paid_out: u64,     // In cents
+    pub pending: bool,           // More holdings remain for a follow-up run
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Default)]
+pub struct MaturityReport {
+    pub bills_matured: u64,
+    pub holdings_settled: u64,
+    pub total_paid: u64,     // In cents
+    pub pending: bool,       // More holdings remain for a follow-up run
 }
 
 #[derive(Clone, Debug, CandidType, Serialize)]
@@ -141,6 +170,125 @@ pub struct TradingMetrics {
     pub last_updated: u64,
 }
 
+// ============= ORDER BOOK STRUCTURES =============
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Order {
+    pub id: String,
+    pub ustbill_id: String,
+    pub owner: Principal,
+    pub side: OrderSide,
+    pub price_per_token: u64,        // In cents
+    pub token_amount: u64,
+    pub filled_amount: u64,
+    pub holding_id: Option<String>,  // Holding the tokens are sold from (asks only)
+    pub escrowed_amount: u64,        // Cents locked in the order (bids only)
+    pub status: OrderStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub expires_at: Option<u64>,     // Unix timestamp
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum OrderSide {
+    Bid,
+    Ask,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum OrderStatus {
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct OrderRequest {
+    pub ustbill_id: String,
+    pub side: OrderSide,
+    pub price_per_token: u64,
+    pub token_amount: u64,
+    pub holding_id: Option<String>,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize)]
+pub struct OrderBookLevel {
+    pub price_per_token: u64,
+    pub token_amount: u64,
+    pub order_count: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize)]
+pub struct OrderBookSnapshot {
+    pub ustbill_id: String,
+    pub bids: Vec<OrderBookLevel>,   // Best (highest) price first
+    pub asks: Vec<OrderBookLevel>,   // Best (lowest) price first
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize)]
+pub struct MarketPriceUpdate {
+    pub ustbill_id: String,
+    pub cusip: String,
+    pub rate_date: String,
+    pub previous_price_per_token: Option<u64>,
+    pub new_price_per_token: u64,
+    pub previous_market_yield: Option<f64>,
+    pub new_market_yield: f64,
+}
+
+/// A bill priced by Treasury conventions as of a date
+#[derive(Clone, Debug, CandidType, Serialize)]
+pub struct PriceQuote {
+    pub ustbill_id: String,
+    pub as_of: u64,                    // Unix timestamp
+    pub settlement_date: u64,          // Start of the T+1 settlement day
+    pub days_to_maturity: u64,         // From settlement to the day the bill pays
+    pub discount_rate: f64,            // Bank discount rate, actual/360
+    pub investment_yield: f64,         // Coupon-equivalent yield, actual/365
+    pub price: u64,                    // Whole bill at the discount rate, in cents
+    pub price_per_token: u64,          // `price` over the tokens, rounded down
+    pub next_token_cost: Option<u64>,  // Offering price of the next token sold; None once sold out
+}
+
+/// A purchase priced for one buyer and held for them until it expires
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PurchaseQuote {
+    pub id: String, // Sequence number and a signature over the terms
+    pub owner: Principal,
+    pub ustbill_id: String,
+    pub token_amount: u64,
+    pub pay_with: Option<String>, // Settlement asset; None pays in the cash asset
+    pub cost: u64,                // In cents
+    pub fee: u64,                 // In cents
+    pub total_cost: u64,          // cost + fee, what the buyer pays
+    pub quoted_at: u64,           // Unix timestamp
+    pub expires_at: u64,          // Unix timestamp
+}
+
+/// Whether an amount to invest covers the platform fee or has it added on top
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum FeeMode {
+    Inclusive, // Tokens and fee together stay within the amount
+    Exclusive, // Tokens stay within the amount; the fee is paid on top
+}
+
+/// Breakdown of a purchase made for a dollar amount
+#[derive(Clone, Debug, CandidType, Serialize)]
+pub struct AmountPurchase {
+    pub holding: TokenHolding,
+    pub amount: u64, // Requested, in cents
+    pub fee_mode: FeeMode,
+    pub token_amount: u64,
+    pub cost: u64,       // In cents
+    pub fee: u64,        // In cents
+    pub total_cost: u64, // cost + fee, debited from the wallet
+    pub residue: u64,    // Part of the amount too small for another token, left in the wallet
+}
+
 // ============= EXTERNAL API STRUCTURES =============
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -155,8 +303,39 @@ pub struct TreasuryRate {
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct TreasuryApiResponse {
-    pub data: Vec<TreasuryRate>,
-    pub meta: TreasuryApiMeta,
+    pub data: Vec<TreasuryApiRecord>,
+    #[serde(default)]
+    pub meta: Option<TreasuryApiMeta>,
+}
+
+/// A single row of the Fiscal Data auctions dataset; every value arrives as a string
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct TreasuryApiRecord {
+    pub record_date: String,
+    pub security_type: String,
+    pub security_term: String,
+    pub auction_date: String,
+    pub high_discount_rate: String,
+    pub cusip: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, Default)]
+pub struct RateFeedStatus {
+    pub last_success: Option<u64>,       // Unix timestamp
+    pub last_failure: Option<u64>,       // Unix timestamp
+    pub last_error: Option<String>,
+    pub last_rate_count: u64,
+    pub consecutive_failures: u64,
+    pub refresh_interval: u64,           // Seconds
+    pub is_stale: bool,                  // No success within two refresh intervals
+}
+
+/// Where Treasury rates are read from. `Canned` serves a fixed Fiscal Data style
+/// JSON body, for tests and local replicas without HTTPS outcalls.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum RateSource {
+    TreasuryApi { url: String },
+    Canned { body: String },
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -189,6 +368,8 @@ pub enum TransactionType {
     Sale,
     YieldDistribution,
     Fee,
+    Transfer,
+    Approval,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
@@ -203,7 +384,7 @@ pub enum TransactionStatus {
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct PlatformConfig {
-    pub platform_fee_percentage: f64,  // 0.5% = 0.005
+    pub platform_fee_percentage: Rate, // 0.5% = 5_000_000 nanos
     pub minimum_investment: u64,        // $1 = 100 cents
     pub maximum_investment: u64,        // $10,000 = 1,000,000 cents
     pub yield_distribution_frequency: u64, // Days
@@ -214,7 +395,7 @@ pub struct PlatformConfig {
 impl Default for PlatformConfig {
     fn default() -> Self {
         Self {
-            platform_fee_percentage: 0.005,  // 0.5%
+            platform_fee_percentage: Rate::from_nanos(5_000_000), // 0.5%
             minimum_investment: 100,          // $1
             maximum_investment: 1_000_000,    // $10,000
             yield_distribution_frequency: 1,  // Daily
@@ -235,6 +416,578 @@ pub struct VerifiedBrokerPurchase {
     pub ustbill_type: String,
 }
 
+// ============= ICRC LEDGER =============
+// Each UST Bill is a token addressed by its bill id; these mirror the ICRC-1/ICRC-2 types.
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum MetadataValue {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+// ============= ICRC-3 BLOCK LOG =============
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum ICRC3Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<ICRC3Value>),
+    Map(Vec<(String, ICRC3Value)>),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GetBlocksRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: ICRC3Value,
+}
+
+candid::define_function!(pub GetBlocksCallback : (Vec<GetBlocksRequest>) -> (GetBlocksResult) query);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksRequest>,
+    pub callback: GetBlocksCallback,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>, // Always empty: blocks are never archived
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DataCertificate {
+    pub certificate: Vec<u8>,
+    pub hash_tree: Vec<u8>, // CBOR-encoded hash tree with last_block_index and last_block_hash
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GetArchivesArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ICRC3ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SupportedBlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+// ============= SETTLEMENT ASSETS =============
+// Wallets hold ICRC tokens per asset. Purchases are priced in USD cents and paid in
+// any configured asset at its USD price; proceeds and payouts settle in the cash asset.
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum UsdPricing {
+    Pegged { cents_per_unit: u64 }, // Per whole token, e.g. 100 for ckUSDC
+    Oracle,                         // Latest AssetPrice stored for the asset
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct SettlementAsset {
+    pub symbol: String,                        // "ckUSDC", "ckBTC"
+    pub ledger_canister_id: Principal,         // Or a stand-in ICRC-1 ledger locally
+    pub decimals: u8,
+    pub pricing: UsdPricing,
+    pub minter_canister_id: Option<Principal>, // ckBTC minter, for BTC deposit addresses
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct SettlementConfig {
+    pub assets: Vec<SettlementAsset>,
+    pub cash_asset: String, // Pegged asset that proceeds, payouts and order escrow settle in
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        let principal = |text| Principal::from_text(text).unwrap();
+        Self {
+            assets: vec![
+                SettlementAsset {
+                    symbol: "ckUSDC".to_string(),
+                    ledger_canister_id: principal("xevnm-gaaaa-aaaar-qafnq-cai"),
+                    decimals: 6,
+                    pricing: UsdPricing::Pegged { cents_per_unit: 100 },
+                    minter_canister_id: None,
+                },
+                SettlementAsset {
+                    symbol: "ckBTC".to_string(),
+                    ledger_canister_id: principal("mxzaz-hqaaa-aaaar-qaada-cai"),
+                    decimals: 8,
+                    pricing: UsdPricing::Oracle,
+                    minter_canister_id: Some(principal("mqygn-kiaaa-aaaar-qaadq-cai")),
+                },
+            ],
+            cash_asset: "ckUSDC".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct AssetBalance {
+    pub asset: String,
+    pub amount: u64, // In the asset's ledger units
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct AssetPrice {
+    pub asset: String,
+    pub price_cents: u64, // USD cents per whole token
+    pub updated_at: u64,  // Unix timestamp
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct DepositReceipt {
+    pub asset: String,
+    pub block_index: Nat, // Ledger block of the sweep into custody
+    pub amount: u64,      // Credited ledger units, after the ledger fee
+    pub usd_value: u64,   // In cents
+    pub balance: u64,     // Asset balance after the deposit, in ledger units
+}
+
+/// A withdrawal locks its amount in the wallet while it waits. Requests worth less than
+/// the policy's auto-approve limit are paid out at once; larger ones need a Treasurer other
+/// than the requester. Anything not paid out is refunded.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Withdrawal {
+    pub id: String, // Also the id of its Transaction
+    pub owner: Principal,
+    pub asset: String,
+    pub amount: u64,    // Locked ledger units, including the ledger fee
+    pub to: Account,
+    pub usd_value: u64, // In cents, at request time
+    pub status: WithdrawalStatus,
+    pub requested_at: u64,
+    pub updated_at: u64,
+    pub reviewed_by: Option<Principal>, // Approving or rejecting Treasurer; None when auto-approved
+    pub block_index: Option<Nat>,       // Ledger block of the payout
+    pub reason: Option<String>,         // Why it failed or was rejected
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum WithdrawalStatus {
+    PendingApproval,
+    Processing, // Payout in flight
+    Completed,
+    Failed,     // Refunded
+    Cancelled,  // By the owner, refunded
+    Rejected,   // By a Treasurer, refunded
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct WithdrawalPolicy {
+    pub auto_approve_limit: u64, // USD cents; withdrawals worth this or more wait for a Treasurer
+    pub platform_fee_account: Option<Account>, // Where withdrawn fee revenue is paid; None disables fee withdrawals
+}
+
+impl Default for WithdrawalPolicy {
+    fn default() -> Self {
+        Self {
+            auto_approve_limit: 100_000, // $1,000
+            platform_fee_account: None,
+        }
+    }
+}
+
+/// Argument of the ckBTC minter's `get_btc_address`
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GetBtcAddressArgs {
+    pub owner: Option<Principal>,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+// ============= DOUBLE-ENTRY ACCOUNTING =============
+// Every movement of value posts a balanced journal entry. Cash accounts are kept in cents
+// of the cash asset and the Asset* accounts in their asset's ledger units; each entry
+// balances in every unit it touches.
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum LedgerAccount {
+    UserCash(Principal),          // Liability: the user's cash balance
+    UserHoldings(Principal),      // Liability: the user's bill holdings at cost
+    OrderEscrow,                  // Liability: cash locked in open bids
+    FeeRevenue,                   // Income: platform fees
+    BillInventory,                // Asset: bills held for holders, at their cost
+    BrokerCustody,                // Asset: cash held in custody
+    YieldAdvances,                // Asset: yield paid ahead of maturity, recovered at redemption
+    UserAsset(Principal, String), // Liability: the user's balance of another settlement asset
+    AssetCustody(String),         // Asset: another settlement asset held in custody
+    AssetFeeRevenue(String),      // Income: platform fees collected in another settlement asset
+    AssetPayments(String),        // Liability: another asset paid for bills, held for conversion
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum EntrySide {
+    Debit,
+    Credit,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct JournalLine {
+    pub account: LedgerAccount,
+    pub side: EntrySide,
+    pub amount: u64, // In cents, or the account's asset's ledger units
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub description: String,
+    pub reference: Option<String>, // Transaction, order or withdrawal the entry records
+    pub lines: Vec<JournalLine>,   // Debits equal credits
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct AccountBalance {
+    pub account: LedgerAccount,
+    pub debits: u64,
+    pub credits: u64,
+    pub balance: i64, // Debits less credits; liabilities and income carry credit balances
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TrialBalance {
+    pub accounts: Vec<AccountBalance>,
+    pub total_debits: u64,
+    pub total_credits: u64,
+    pub net: i64, // Always zero
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FeeType {
+    Purchase,  // Primary purchases
+    Sale,      // Sales back to the platform
+    OrderBook, // Order book fills, charged to the seller
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct FeeRevenueLine {
+    pub ustbill_id: String,
+    pub fee_type: FeeType,
+    pub trades: u64,
+    pub amount: u64, // In the report's unit
+}
+
+/// Fees collected in one settlement asset: in cents for the cash asset, and in ledger
+/// units for any other
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct FeeRevenueReport {
+    pub asset: String,
+    pub from: u64,
+    pub to: u64,
+    pub lines: Vec<FeeRevenueLine>, // By bill, then fee type
+    pub total_earned: u64,
+    pub total_withdrawn: u64, // Paid out of the fee account during the period
+    pub available: u64,       // Unwithdrawn fee revenue now
+}
+
+/// Fee revenue paid out of custody to the platform fee account
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct FeeWithdrawal {
+    pub id: String,
+    pub asset: String,
+    pub amount: u64, // In cents for the cash asset, ledger units otherwise; includes the ledger fee
+    pub to: Account,
+    pub block_index: Nat,
+    pub remaining: u64, // Unwithdrawn fee revenue left in the asset, in the same unit
+}
+
+// ============= BTC/USD ORACLE =============
+// Oracle-priced assets that track bitcoin are priced from the median of several BTC/USD
+// sources; settlement refuses prices older than the configured window.
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub enum PriceSource {
+    ExchangeRateCanister { canister_id: Principal }, // The IC exchange rate canister (XRC)
+    HttpsJson {
+        name: String,
+        url: String,
+        json_path: Vec<String>, // Keys or array indices down to the price, a number or numeric string
+    },
+    Fixed { name: String, price_cents: u64 }, // For local replicas without outcalls
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct OracleConfig {
+    pub sources: Vec<PriceSource>,
+    pub priced_assets: Vec<String>,    // Oracle-priced settlement assets that track BTC/USD
+    pub min_sources: u32,              // Agreeing sources needed to accept a price
+    pub max_deviation_bps: u64,        // Sources further than this from the median are discarded
+    pub max_age_seconds: u64,          // Older prices are refused for settlement
+    pub refresh_interval_seconds: u64,
+}
+
+impl Default for OracleConfig {
+    fn default() -> Self {
+        let https_json = |name: &str, url: &str, json_path: &[&str]| PriceSource::HttpsJson {
+            name: name.to_string(),
+            url: url.to_string(),
+            json_path: json_path.iter().map(|key| key.to_string()).collect(),
+        };
+        Self {
+            sources: vec![
+                PriceSource::ExchangeRateCanister {
+                    canister_id: Principal::from_text("uf6dk-hyaaa-aaaaq-qaaaq-cai").unwrap(),
+                },
+                https_json(
+                    "coinbase",
+                    "https://api.coinbase.com/v2/prices/BTC-USD/spot",
+                    &["data", "amount"],
+                ),
+                https_json(
+                    "kraken",
+                    "https://api.kraken.com/0/public/Ticker?pair=XBTUSD",
+                    &["result", "XXBTZUSD", "c", "0"],
+                ),
+            ],
+            priced_assets: vec!["ckBTC".to_string()],
+            min_sources: 2,
+            max_deviation_bps: 100,
+            max_age_seconds: 900,
+            refresh_interval_seconds: 300,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct SourcePrice {
+    pub source: String,
+    pub price_cents: u64, // USD cents per BTC
+    pub observed_at: u64, // Unix timestamp
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, Default)]
+pub struct BtcUsdPrice {
+    pub price_cents: u64,          // Median of the accepted sources, USD cents per BTC
+    pub updated_at: u64,           // Oldest accepted observation
+    pub sources: Vec<SourcePrice>, // Accepted sources
+    pub rejected: Vec<String>,     // "source: reason" for failed, stale or outlying sources
+    pub is_stale: bool,            // Older than `max_age_seconds`
+}
+
+/// The exchange rate canister's asset identifier
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct XrcAsset {
+    pub symbol: String,
+    pub class: XrcAssetClass,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum XrcAssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+/// Argument of the exchange rate canister's `get_exchange_rate`
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GetExchangeRateRequest {
+    pub base_asset: XrcAsset,
+    pub quote_asset: XrcAsset,
+    pub timestamp: Option<u64>, // Defaults to the start of the current minute
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ExchangeRateMetadata {
+    pub decimals: u32,
+    pub base_asset_num_received_rates: u64,
+    pub base_asset_num_queried_sources: u64,
+    pub quote_asset_num_received_rates: u64,
+    pub quote_asset_num_queried_sources: u64,
+    pub standard_deviation: u64,
+    pub forex_timestamp: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ExchangeRate {
+    pub base_asset: XrcAsset,
+    pub quote_asset: XrcAsset,
+    pub timestamp: u64, // Unix timestamp
+    pub rate: u64,      // Scaled by 10^metadata.decimals
+    pub metadata: ExchangeRateMetadata,
+}
+
+// ============= ACCESS CONTROL =============
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    SuperAdmin,  // Every permission, including granting and revoking roles
+    BillIssuer,  // Creates UST Bills and records broker purchases
+    KYCOfficer,  // Reviews user KYC
+    Treasurer,   // Runs maturity/yield processing and manages market data
+    Auditor,     // Read-only access to operator data
+    Support,     // Day-to-day order book housekeeping
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Permission {
+    ManageRoles,
+    ManageBills,
+    ManageKyc,
+    ManageTreasury,
+    ManageMarketData,
+    ManageOrders,
+    ManagePlatform,
+    ViewAuditData,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub roles: Vec<Role>,
+}
+
+// ============= CANISTER ARGUMENTS =============
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq)]
+pub struct FeatureFlags {
+    pub trading_enabled: bool,            // Purchases, sales and order placement
+    pub yield_distribution_enabled: bool, // Scheduled yield distribution runs
+    pub rate_refresh_enabled: bool,       // Scheduled Treasury rate refreshes
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self {
+            trading_enabled: true,
+            yield_distribution_enabled: true,
+            rate_refresh_enabled: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize)]
+pub struct InitArgs {
+    pub initial_super_admins: Vec<Principal>, // Defaults to the installing principal when empty
+    pub platform_config: Option<PlatformConfig>,
+    pub treasury_rates_url: Option<String>,
+    pub features: Option<FeatureFlags>,
+    pub settlement: Option<SettlementConfig>,
+    pub oracle: Option<OracleConfig>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize)]
+pub struct UpgradeArgs {
+    pub add_super_admins: Option<Vec<Principal>>,
+    pub platform_config: Option<PlatformConfig>,
+    pub treasury_rates_url: Option<String>,
+    pub features: Option<FeatureFlags>,
+    pub settlement: Option<SettlementConfig>,
+    pub oracle: Option<OracleConfig>,
+}
+
 // ============= HELPER FUNCTIONS =============
 
 impl USTBill {
@@ -248,11 +1001,8 @@ impl USTBill {
     
     pub fn days_to_maturity(&self) -> u64 {
         let current_time = ic_cdk::api::time() / 1_000_000_000; // Convert to seconds
-        if self.maturity_date > current_time {
-            (self.maturity_date - current_time) / 86400 // Convert to days
-        } else {
-            0
-        }
+        // A maturity on a weekend or holiday pays the next business day
+        DayCount::days(current_time, calendar::payment_date(self.maturity_date))
     }
 }
 
@@ -265,13 +1015,57 @@ impl User {
         // This will be calculated dynamically by aggregating holdings
         self.total_invested
     }
+
+    /// Balance of a settlement asset, in its ledger units
+    pub fn balance(&self, asset: &str) -> u64 {
+        self.balances
+            .iter()
+            .find(|balance| balance.asset == asset)
+            .map_or(0, |balance| balance.amount)
+    }
+
+    pub fn credit(&mut self, asset: &str, amount: u64) {
+        match self.balances.iter_mut().find(|balance| balance.asset == asset) {
+            Some(balance) => balance.amount += amount,
+            None => self.balances.push(AssetBalance {
+                asset: asset.to_string(),
+                amount,
+            }),
+        }
+    }
+
+    /// Returns false, leaving the balance unchanged, if it is too low
+    pub fn debit(&mut self, asset: &str, amount: u64) -> bool {
+        match self.balances.iter_mut().find(|balance| balance.asset == asset) {
+            Some(balance) if balance.amount >= amount => {
+                balance.amount -= amount;
+                true
+            }
+            _ => amount == 0,
+        }
+    }
+}
+
+impl Order {
+    pub fn remaining(&self) -> u64 {
+        self.token_amount - self.filled_amount
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self.status, OrderStatus::Open | OrderStatus::PartiallyFilled)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
 }
 
 impl TokenHolding {
-    pub fn calculate_current_yield(&self, annual_rate: f64, days_held: u64) -> u64 {
-        let daily_rate = annual_rate / 365.0;
-        let current_value = self.tokens_owned * self.purchase_price_per_token;
-        (current_value as f64 * daily_rate * days_held as f64) as u64
+    /// Yield accrued on the cost basis over `days_held`, rounded down to the cent
+    pub fn calculate_current_yield(&self, annual_rate: Rate, days_held: u64) -> Result<u64> {
+        let accrued =
+            Money::from_cents(self.cost_basis).interest(annual_rate, days_held, DAYS_PER_YEAR, Rounding::Floor)?;
+        Ok(accrued.cents())
     }
     
     pub fn is_active(&self) -> bool {
@@ -1,4 +1,6 @@
+use crate::calendar::{DayCount, SECONDS_PER_DAY};
 use crate::errors::{BitcoinUSTBillsError, Result};
+use crate::money::{Money, Rate, Rounding, DAYS_PER_YEAR};
 use crate::types::*;
 use candid::Principal;
 use hex;
@@ -123,12 +125,8 @@ pub fn validate_phone_number(phone: &str) -> Result<()> {
 }
 
 /// Validates yield rate
-pub fn validate_yield_rate(rate: f64) -> Result<()> {
-    if rate < 0.0 {
-        return Err(BitcoinUSTBillsError::InvalidYieldRate);
-    }
-
-    if rate > 1.0 {
+pub fn validate_yield_rate(rate: Rate) -> Result<()> {
+    if rate > Rate::ONE {
         return Err(BitcoinUSTBillsError::InvalidYieldRate);
     }
 
@@ -186,57 +184,93 @@ pub fn get_current_timestamp() -> u64 {
 
 /// Converts timestamp to days
 pub fn timestamp_to_days(timestamp: u64) -> u64 {
-    timestamp / 86400
+    timestamp / SECONDS_PER_DAY
 }
 
-/// Calculates days between two timestamps
+/// Calculates actual days between two timestamps
 pub fn days_between(start: u64, end: u64) -> u64 {
-    if end > start {
-        (end - start) / 86400
-    } else {
-        0
+    DayCount::days(start, end)
+}
+
+/// Parses an ISO `YYYY-MM-DD` date string
+pub fn parse_iso_date(date: &str) -> Result<time::Date> {
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return Err(BitcoinUSTBillsError::InvalidDate);
     }
+
+    let year = parts[0].parse::<i32>().map_err(|_| BitcoinUSTBillsError::InvalidDate)?;
+    let month = parts[1].parse::<u8>().map_err(|_| BitcoinUSTBillsError::InvalidDate)?;
+    let day = parts[2].parse::<u8>().map_err(|_| BitcoinUSTBillsError::InvalidDate)?;
+
+    let month = time::Month::try_from(month).map_err(|_| BitcoinUSTBillsError::InvalidDate)?;
+    time::Date::from_calendar_date(year, month, day).map_err(|_| BitcoinUSTBillsError::InvalidDate)
+}
+
+/// Formats a Unix timestamp (seconds) as an ISO `YYYY-MM-DD` date string
+pub fn timestamp_to_iso_date(timestamp: u64) -> String {
+    let date = time::OffsetDateTime::from_unix_timestamp(timestamp as i64)
+        .map(|datetime| datetime.date())
+        .unwrap_or(time::Date::MIN);
+    format!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        u8::from(date.month()),
+        date.day()
+    )
 }
 
-/// Calculates compound interest
-pub fn calculate_compound_interest(principal: u64, rate: f64, time_in_days: u64) -> u64 {
-    let time_in_years = time_in_days as f64 / 365.0;
-    let amount = principal as f64 * (1.0 + rate).powf(time_in_years);
-    amount as u64
+/// Calculates the amount after compound interest: compounded yearly over whole years, with
+/// simple interest for the remaining days. Each year's interest is rounded down to the cent.
+pub fn calculate_compound_interest(principal: u64, rate: Rate, time_in_days: u64) -> Result<u64> {
+    let mut amount = Money::from_cents(principal);
+    for _ in 0..time_in_days / DAYS_PER_YEAR {
+        amount = amount.checked_add(amount.mul_rate(rate, Rounding::Floor)?)?;
+    }
+    let stub = amount.interest(
+        rate,
+        time_in_days % DAYS_PER_YEAR,
+        DAYS_PER_YEAR,
+        Rounding::Floor,
+    )?;
+    Ok(amount.checked_add(stub)?.cents())
 }
 
-/// Calculates simple interest
-pub fn calculate_simple_interest(principal: u64, rate: f64, time_in_days: u64) -> u64 {
-    let time_in_years = time_in_days as f64 / 365.0;
-    let interest = principal as f64 * rate * time_in_years;
-    interest as u64
+/// Calculates simple interest, rounded down to the cent
+pub fn calculate_simple_interest(principal: u64, rate: Rate, time_in_days: u64) -> Result<u64> {
+    let interest = Money::from_cents(principal).interest(
+        rate,
+        time_in_days,
+        DAYS_PER_YEAR,
+        Rounding::Floor,
+    )?;
+    Ok(interest.cents())
 }
 
-/// Calculates daily interest
-pub fn calculate_daily_interest(principal: u64, annual_rate: f64, days: u64) -> u64 {
-    let daily_rate = annual_rate / 365.0;
-    let interest = principal as f64 * daily_rate * days as f64;
-    interest as u64
+/// Calculates interest accrued daily over `days` of a `day_count` year, rounded down
+pub fn calculate_daily_interest(
+    principal: u64,
+    annual_rate: Rate,
+    days: u64,
+    day_count: DayCount,
+) -> Result<u64> {
+    let interest = Money::from_cents(principal).interest(
+        annual_rate,
+        days,
+        day_count.basis_days(),
+        Rounding::Floor,
+    )?;
+    Ok(interest.cents())
 }
 
 /// Formats amount in cents to dollar string
 pub fn format_amount_to_dollars(amount_cents: u64) -> String {
-    let dollars = amount_cents / 100;
-    let cents = amount_cents % 100;
-    format!("${}.{:02}", dollars, cents)
+    Money::from_cents(amount_cents).to_string()
 }
 
-/// Parses dollar string to amount in cents
+/// Parses dollar string to amount in cents; sub-cent amounts are refused
 pub fn parse_dollar_amount(amount_str: &str) -> Result<u64> {
-    let cleaned = amount_str.trim_start_matches('$');
-
-    if let Ok(amount) = cleaned.parse::<f64>() {
-        Ok((amount * 100.0) as u64)
-    } else {
-        Err(BitcoinUSTBillsError::validation_error(
-            "Invalid amount format",
-        ))
-    }
+    Money::parse(amount_str).map(Money::cents)
 }
 
 /// Generates a hash for data integrity
@@ -297,9 +331,10 @@ pub fn validate_country(country: &str) -> Result<()> {
     Ok(())
 }
 
-/// Calculates fees based on amount and rate
-pub fn calculate_fees(amount: u64, fee_rate: f64) -> u64 {
-    (amount as f64 * fee_rate) as u64
+/// Calculates fees based on amount and rate, with banker's rounding to the cent
+pub fn calculate_fees(amount: u64, fee_rate: Rate) -> Result<u64> {
+    let fees = Money::from_cents(amount).mul_rate(fee_rate, Rounding::HalfEven)?;
+    Ok(fees.cents())
 }
 
 /// Validates that a string is not empty and doesn't contain only whitespace
@@ -321,7 +356,7 @@ pub fn is_date_in_past(date: u64) -> bool {
 /// Checks if a date is within a certain range from now
 pub fn is_date_within_range(date: u64, max_days_from_now: u64) -> bool {
     let current_time = get_current_timestamp();
-    let max_time = current_time + (max_days_from_now * 86400);
+    let max_time = current_time + (max_days_from_now * SECONDS_PER_DAY);
     date <= max_time
 }
 
@@ -330,9 +365,9 @@ pub fn basis_points_to_percentage(basis_points: u64) -> f64 {
     basis_points as f64 / 10000.0
 }
 
-/// Converts percentage to basis points
+/// Converts percentage to basis points, to the nearest point
 pub fn percentage_to_basis_points(percentage: f64) -> u64 {
-    (percentage * 10000.0) as u64
+    (percentage * 10000.0).round() as u64
 }
 
 /// Sanitizes string input
@@ -369,18 +404,48 @@ mod tests {
     #[test]
     fn test_calculate_simple_interest() {
         let principal = 100000; // $1000
-        let rate = 0.05; // 5%
+        let rate = Rate::from_bps(500).unwrap(); // 5%
         let days = 365; // 1 year
-        let interest = calculate_simple_interest(principal, rate, days);
+        let interest = calculate_simple_interest(principal, rate, days).unwrap();
         assert_eq!(interest, 5000); // $50
     }
 
+    #[test]
+    fn test_fees_and_amounts_keep_every_cent() {
+        let fee_rate = Rate::from_bps(50).unwrap();
+        assert_eq!(calculate_fees(9_900, fee_rate).unwrap(), 50);
+
+        // 0.29 * 100 is 28.999... in floating point
+        assert_eq!(parse_dollar_amount("$0.29").unwrap(), 29);
+        assert!(parse_dollar_amount("0.295").is_err());
+        assert_eq!(percentage_to_basis_points(0.0526), 526);
+
+        // Two full years at 5% on $1,000, then 73 days
+        let rate = Rate::from_bps(500).unwrap();
+        assert_eq!(
+            calculate_compound_interest(100_000, rate, 730).unwrap(),
+            110_250
+        );
+        assert_eq!(
+            calculate_compound_interest(100_000, rate, 803).unwrap(),
+            111_352
+        );
+    }
+
     #[test]
     fn test_format_amount_to_dollars() {
         assert_eq!(format_amount_to_dollars(100000), "$1000.00");
         assert_eq!(format_amount_to_dollars(150), "$1.50");
     }
 
+    #[test]
+    fn test_iso_dates() {
+        assert!(parse_iso_date("2024-02-29").is_ok());
+        assert!(parse_iso_date("2023-02-29").is_err());
+        assert!(parse_iso_date("2024-1-05").is_err());
+        assert_eq!(timestamp_to_iso_date(1_704_153_600), "2024-01-02");
+    }
+
     #[test]
     fn test_calculate_percentage() {
         assert_eq!(calculate_percentage(50, 200), 25.0);
@@ -0,0 +1,249 @@
+use crate::accounting;
+use crate::calendar::{self, DayCount, SECONDS_PER_DAY};
+use crate::errors::Result;
+use crate::money::Rate;
+use crate::settlement;
+use crate::storage::*;
+use crate::types::*;
+
+/// Maximum number of holdings accrued in a single message
+const YIELD_BATCH_SIZE: usize = 100;
+
+/// Accrues yield for every active holding whose distribution period has elapsed.
+///
+/// Flexible holdings are paid out to the owner's wallet each period; Maturity holdings
+/// only get an accrual record, as their yield is paid when the bill is redeemed.
+pub fn distribute_yield(now: u64) -> Result<YieldRunReport> {
+    let config = PlatformConfigStorage::get();
+    let period = config.yield_distribution_frequency.max(1) * SECONDS_PER_DAY;
+
+    let mut report = YieldRunReport::default();
+    let mut budget = YIELD_BATCH_SIZE;
+
+    for holding in HoldingStorage::get_active() {
+        // One holding with a missing bill must not hold up everyone else's yield
+        let ustbill = match USTBillStorage::get(&holding.ustbill_id) {
+            Ok(ustbill) => ustbill,
+            Err(e) => {
+                ic_cdk::println!("Skipping yield for holding {}: {}", holding.id, e);
+                continue;
+            }
+        };
+        let start = accrual_start(&holding);
+        // Accrual runs in whole days up to the start of the day the bill pays
+        let paid_at = calendar::start_of(calendar::date_of(calendar::payment_date(
+            ustbill.maturity_date,
+        )));
+        let end = now.min(paid_at);
+
+        // Accrue full periods, plus whatever is left once the bill pays
+        let due = DayCount::days(start, end) > 0 && (end >= start + period || end == paid_at);
+        if !due {
+            continue;
+        }
+
+        if budget == 0 {
+            report.pending = true;
+            break;
+        }
+        budget -= 1;
+
+        accrue_holding(holding, &ustbill, start, end, now, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+/// Timestamp from which a holding has yet to accrue yield; a new holding accrues from
+/// its T+1 settlement
+pub fn accrual_start(holding: &TokenHolding) -> u64 {
+    YieldDistributionStorage::accrued_until(&holding.id)
+        .unwrap_or_else(|| calendar::settlement_date(holding.purchase_date))
+}
+
+/// Yield for whole days of a period, returned with the day-aligned end of the period
+pub fn period_yield(
+    holding: &TokenHolding,
+    annual_rate: Rate,
+    start: u64,
+    end: u64,
+) -> Result<(u64, u64)> {
+    let days = DayCount::days(start, end);
+    let amount = holding.calculate_current_yield(annual_rate, days)?;
+    Ok((amount, start + days * SECONDS_PER_DAY))
+}
+
+fn accrue_holding(
+    mut holding: TokenHolding,
+    ustbill: &USTBill,
+    start: u64,
+    end: u64,
+    now: u64,
+    report: &mut YieldRunReport,
+) -> Result<()> {
+    let (yield_amount, period_end) = period_yield(&holding, ustbill.annual_yield, start, end)?;
+    if period_end == start {
+        return Ok(());
+    }
+
+    // The payout, its journal entry and the accrual record are written together or not at all
+    let mut batch = WriteBatch::new();
+    let paid_out = holding.yield_option == YieldOption::Flexible;
+    if paid_out && yield_amount > 0 {
+        let mut user = batch.user(&holding.user_principal)?;
+        settlement::credit_cash(&mut user, yield_amount);
+        user.total_yield_earned += yield_amount;
+        user.updated_at = now;
+
+        let transaction = Transaction {
+            id: generate_id(),
+            user_principal: holding.user_principal,
+            transaction_type: TransactionType::YieldDistribution,
+            amount: yield_amount,
+            ustbill_id: Some(ustbill.id.clone()),
+            holding_id: Some(holding.id.clone()),
+            timestamp: now,
+            status: TransactionStatus::Completed,
+            fees: 0,
+            description: format!("Yield payout for UST Bill {}", ustbill.id),
+        };
+
+        // The paid yield stays with the tokens, so splits carry their share of it
+        holding.yield_paid += yield_amount;
+
+        let (owner, reference) = (holding.user_principal, transaction.id.clone());
+        batch.update_holding(holding.clone());
+        batch.update_user(user);
+        batch.insert_transaction(transaction);
+        batch.then(move || accounting::yield_payout(owner, yield_amount, &reference, now));
+        report.total_paid_out += yield_amount;
+    }
+
+    let distribution = YieldDistribution {
+        holding_id: holding.id.clone(),
+        user_principal: holding.user_principal,
+        yield_amount,
+        distribution_date: now,
+        ustbill_id: ustbill.id.clone(),
+        period_start: start,
+        period_end,
+        yield_option: holding.yield_option.clone(),
+        paid_out,
+    };
+    batch.then(move || {
+        YieldDistributionStorage::insert(distribution)?;
+        YieldDistributionStorage::set_accrued_until(&holding.id, period_end)
+    });
+    batch.commit()?;
+
+    report.holdings_accrued += 1;
+    report.total_accrued += yield_amount;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use time::{Date, Month};
+
+    fn day(month: Month, day: u8) -> u64 {
+        calendar::start_of(Date::from_calendar_date(2025, month, day).unwrap())
+    }
+
+    fn holding(id: &str, ustbill_id: &str, purchase_date: u64) -> TokenHolding {
+        TokenHolding {
+            id: id.to_string(),
+            user_principal: Principal::anonymous(),
+            ustbill_id: ustbill_id.to_string(),
+            tokens_owned: 1_000,
+            purchase_price_per_token: 100,
+            cost_basis: 100_000,
+            purchase_date,
+            yield_option: YieldOption::Maturity,
+            status: HoldingStatus::Active,
+            current_value: 100_000,
+            projected_yield: 0,
+            yield_paid: 0,
+        }
+    }
+
+    #[test]
+    fn test_final_period_ends_on_the_payment_day() {
+        // Matures an hour into Thursday 7 August; bought to settle on Tuesday 8 July
+        let ustbill = USTBill {
+            id: "accruing-bill".to_string(),
+            cusip: "912796ZV4".to_string(),
+            face_value: 100_000,
+            purchase_price: 100_000,
+            maturity_date: day(Month::August, 7) + 3_600,
+            annual_yield: Rate::from_nanos(36_500_000),
+            total_tokens: 1_000,
+            tokens_sold: 1_000,
+            status: USTBillStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+            issuer: "US Treasury".to_string(),
+            bill_type: "4-week".to_string(),
+            market_price_per_token: None,
+            market_yield: None,
+            last_priced_at: None,
+        };
+        USTBillStorage::insert(ustbill.clone()).unwrap();
+        HoldingStorage::insert(holding(
+            "accruing",
+            &ustbill.id,
+            day(Month::July, 7) + 50_000,
+        ))
+        .unwrap();
+        // A holding whose bill is gone is passed over
+        HoldingStorage::insert(holding("orphaned", "missing-bill", 0)).unwrap();
+
+        let now = ustbill.maturity_date + 7_200;
+        let report = distribute_yield(now).unwrap();
+        assert_eq!(report.holdings_accrued, 1);
+        // 3.65% of $1000 over 30 days actual/365
+        assert_eq!(report.total_accrued, 300);
+        assert_eq!(
+            YieldDistributionStorage::accrued_until("accruing"),
+            Some(day(Month::August, 7))
+        );
+
+        // Nothing is left to accrue, so nothing is due and no follow-up is asked for
+        let report = distribute_yield(now + SECONDS_PER_DAY).unwrap();
+        assert_eq!(report.holdings_accrued, 0);
+        assert!(!report.pending);
+    }
+
+    #[test]
+    fn test_period_yield_accrues_whole_days() {
+        let holding = TokenHolding {
+            id: "1".to_string(),
+            user_principal: Principal::anonymous(),
+            ustbill_id: "2".to_string(),
+            tokens_owned: 1_000,
+            purchase_price_per_token: 100,
+            cost_basis: 100_000,
+            purchase_date: 0,
+            yield_option: YieldOption::Flexible,
+            status: HoldingStatus::Active,
+            current_value: 100_000,
+            projected_yield: 0,
+            yield_paid: 0,
+        };
+
+        // 0.05 * 100_000 * 73 / 365 = 1000 cents; the extra half day carries over
+        let start = 1_000;
+        let rate = Rate::from_bps(500).unwrap();
+        let (amount, period_end) =
+            period_yield(&holding, rate, start, start + 73 * SECONDS_PER_DAY + 43_200).unwrap();
+        assert_eq!(amount, 1_000);
+        assert_eq!(period_end, start + 73 * SECONDS_PER_DAY);
+
+        assert_eq!(
+            period_yield(&holding, rate, start, start + 100).unwrap(),
+            (0, start)
+        );
+    }
+}
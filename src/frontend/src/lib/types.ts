@@ -16,10 +16,15 @@ export interface User {
   'kyc_status' : KYCStatus,
   'is_active' : boolean,
   'phone_number' : [] | [string],
-  'wallet_balance' : bigint,
+  'balances' : Array<AssetBalance>,
   'total_yield_earned' : bigint,
 }
 
+export interface AssetBalance {
+  'asset' : string,
+  'amount' : bigint,
+}
+
 export type KYCStatus = { 'Rejected' : null } |
   { 'Verified' : null } |
   { 'Expired' : null } |